@@ -0,0 +1,108 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::mail::{Email, ListHeaders};
+use crate::state::StateStore;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const PREFIX: &str = "archive:";
+const INDEX_PREFIX: &str = "archive-index:";
+
+// Full copies of delivered emails, keyed by trace ID, plus an index from sender and
+// normalized subject to the latest archived version.
+#[derive(Serialize, Deserialize)]
+pub struct Archived {
+    pub trace_id: String,
+    pub message_id: Option<String>,
+    pub subject: String,
+    pub from: String,
+    pub date: Option<DateTime<Utc>>,
+    pub archived_at: DateTime<Utc>,
+    pub body: String,
+    #[serde(default)]
+    pub list: ListHeaders,
+    // Base64 of the original message, and the payload it was rendered to, for `replay`
+    #[serde(default)]
+    pub raw: Option<String>,
+    #[serde(default)]
+    pub payload: Option<Value>,
+}
+
+pub fn enabled(config: &Config) -> bool {
+    config.archive.as_ref().is_some_and(|a| a.enabled)
+}
+
+// Public link to an archived email on the HTTP server, when it has a public_url
+pub fn url(config: &Config, trace_id: &str) -> Option<String> {
+    if !enabled(config) {
+        return None;
+    }
+    let base = config.server.as_ref()?.public_url.as_deref()?;
+    Some(format!("{}/archive/{}", base.trim_end_matches('/'), trace_id))
+}
+
+pub fn save(store: &dyn StateStore, email: &Email, payload: Option<&Value>) -> Result<(), Error> {
+    let archived = Archived {
+        trace_id: email.trace_id.clone(),
+        message_id: email.message_id.clone(),
+        subject: email.subject.clone(),
+        from: email.from.clone(),
+        date: email.date,
+        archived_at: Utc::now(),
+        body: email.body.clone(),
+        list: email.list.clone(),
+        raw: email.raw.as_deref().map(openssl::base64::encode_block),
+        payload: payload.cloned(),
+    };
+    store.put_json(&format!("{}{}", PREFIX, email.trace_id), &archived)?;
+    store.put(&index_key(email), &email.trace_id)
+}
+
+pub fn get(store: &dyn StateStore, trace_id: &str) -> Result<Option<Archived>, Error> {
+    store.get_json(&format!("{}{}", PREFIX, trace_id))
+}
+
+// The most recently archived email with the same sender and subject (ignoring
+// "Re:"/"Updated:"-style prefixes).
+pub fn previous_version(store: &dyn StateStore, email: &Email) -> Result<Option<Archived>, Error> {
+    match store.get(&index_key(email))? {
+        Some(trace_id) if trace_id != email.trace_id => get(store, &trace_id),
+        _ => Ok(None),
+    }
+}
+
+// The most recently archived email whose sender contains `sender`
+pub fn latest_from(store: &dyn StateStore, sender: &str) -> Result<Option<Archived>, Error> {
+    let sender = sender.to_lowercase();
+    Ok(store
+        .entries(PREFIX)?
+        .into_iter()
+        .filter_map(|(_, raw)| serde_json::from_str::<Archived>(&raw).ok())
+        .filter(|a| a.from.to_lowercase().contains(&sender))
+        .max_by_key(|a| a.archived_at))
+}
+
+// Every archived email, oldest first
+pub fn all(store: &dyn StateStore) -> Result<Vec<Archived>, Error> {
+    let mut archived: Vec<Archived> = store
+        .entries(PREFIX)?
+        .into_iter()
+        .filter_map(|(_, raw)| serde_json::from_str(&raw).ok())
+        .collect();
+    archived.sort_by_key(|a| a.archived_at);
+    Ok(archived)
+}
+
+fn index_key(email: &Email) -> String {
+    format!("{}{}|{}", INDEX_PREFIX, email.from.trim().to_lowercase(), normalize_subject(&email.subject))
+}
+
+fn normalize_subject(subject: &str) -> String {
+    const PREFIXES: &[&str] = &["re:", "fwd:", "fw:", "updated:", "update:", "correction:", "corrected:", "[updated]", "[correction]"];
+    let mut subject = subject.trim().to_lowercase();
+    while let Some(rest) = PREFIXES.iter().find_map(|p| subject.strip_prefix(p)) {
+        subject = rest.trim_start().to_string();
+    }
+    subject
+}
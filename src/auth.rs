@@ -0,0 +1,194 @@
+use crate::config::{AuthConfig, AuthMethod, Config};
+use crate::error::Error;
+use crate::ops;
+use chrono::{NaiveDate, Utc};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+// Access tokens by username, with when to stop using them, and the refresh token to use
+// next (Microsoft hands out a new one with each refresh)
+static TOKENS: Mutex<Option<HashMap<String, Token>>> = Mutex::new(None);
+
+#[derive(Clone)]
+struct Token {
+    access: String,
+    refresh: String,
+    expires: Instant,
+}
+
+// The server rejected the credentials (NO/BAD in response to LOGIN), as opposed to the
+// connection failing. Kept separate so the retry loop can back off instead of locking the
+// account.
+#[derive(Debug, thiserror::Error)]
+#[error("Authentication failed: {0}")]
+pub struct AuthError(pub String);
+
+#[derive(Default)]
+pub struct AuthHealth {
+    failures: u32,
+}
+
+impl AuthHealth {
+    pub fn record_success(&mut self, config: &Config) {
+        if self.failures > 0 {
+            info!("Authentication recovered after {} failure(s)", self.failures);
+        }
+        self.failures = 0;
+        check_expiry(config);
+    }
+
+    // Returns how long to wait before the next attempt. After `auth.max_failures` consecutive
+    // rejections we stop retrying at the normal pace, since most providers lock the account
+    // after a handful of bad logins.
+    pub fn record_failure(&mut self, config: &Config, err: &AuthError) -> Duration {
+        self.failures += 1;
+        let auth = config.auth.clone().unwrap_or_default();
+        if self.failures < auth.max_failures() {
+            return Duration::from_secs(10);
+        }
+
+        let hint = match auth.method() {
+            AuthMethod::Login => "The app password may have expired or been revoked; rotate it and restart.",
+            AuthMethod::Oauth2 => "The refresh token may have expired or been revoked; authorize again and restart.",
+        };
+        ops::alert_once(
+            config,
+            "auth-failures",
+            "IMAP authentication failing",
+            &format!(
+                "Login as {} was rejected {} times in a row ({}). {} Retrying every {} minutes until then.",
+                config.imap_username,
+                self.failures,
+                err.0,
+                hint,
+                auth.lockout_pause_minutes()
+            ),
+        );
+        Duration::from_secs(auth.lockout_pause_minutes() * 60)
+    }
+}
+
+fn check_expiry(config: &Config) {
+    let Some(ref auth) = config.auth else {
+        return;
+    };
+    let Some(ref expires) = auth.password_expires else {
+        return;
+    };
+    let Ok(date) = NaiveDate::parse_from_str(expires, "%Y-%m-%d") else {
+        warn!("Invalid auth.password_expires (expected YYYY-MM-DD): {}", expires);
+        return;
+    };
+
+    let days_left = (date - Utc::now().date_naive()).num_days();
+    if days_left <= auth.expiry_warning_days() {
+        ops::alert_once(
+            config,
+            &format!("password-expiry:{}", expires),
+            "IMAP app password expiring",
+            &format!(
+                "The app password for {} expires on {} ({} day(s) left). Generate a new one and update the configuration.",
+                config.imap_username, expires, days_left
+            ),
+        );
+    }
+}
+
+// Logs in with the configured method. A NO or BAD from the server is an AuthError, so
+// callers can tell rejected credentials from a dropped connection.
+pub fn login<T: Read + Write>(config: &Config, client: imap::Client<T>) -> Result<imap::Session<T>, Error> {
+    let auth = config.auth.clone().unwrap_or_default();
+    let result = match auth.method() {
+        AuthMethod::Login => client.login(&config.imap_username, &config.imap_password),
+        AuthMethod::Oauth2 => {
+            let token = access_token(config, &auth)?;
+            let xoauth2 = XOAuth2 { user: config.imap_username.clone(), token };
+            client.authenticate("XOAUTH2", &xoauth2)
+        }
+    };
+    result.map_err(|(e, _)| match e {
+        imap::error::Error::No(msg) | imap::error::Error::Bad(msg) => {
+            // A token the server no longer takes is refreshed on the next attempt
+            forget(config);
+            AuthError(msg).into()
+        }
+        e => e.into(),
+    })
+}
+
+// The cached access token while it's good for another minute, else a fresh one. Each
+// reconnect goes through here, so a session dropped because its token expired picks up a
+// new one.
+fn access_token(config: &Config, auth: &AuthConfig) -> Result<String, Error> {
+    let cached = TOKENS.lock().unwrap().as_ref().and_then(|t| t.get(&config.imap_username).cloned());
+    if let Some(ref token) = cached
+        && token.expires > Instant::now()
+    {
+        return Ok(token.access.clone());
+    }
+    let refresh_token = cached
+        .map(|t| t.refresh)
+        .or_else(|| auth.refresh_token.clone())
+        .ok_or_else(|| Error::config("auth.method = \"oauth2\" needs a refresh_token"))?;
+
+    let mut form = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token.as_str()),
+        ("client_id", auth.client_id.as_deref().unwrap_or_default()),
+    ];
+    if let Some(ref secret) = auth.client_secret {
+        form.push(("client_secret", secret));
+    }
+    if let Some(ref scope) = auth.scope {
+        form.push(("scope", scope));
+    }
+    let response = crate::http::client().post(auth.token_url()).form(&form).send()?;
+    let status = response.status();
+    let body: Value = response.json().unwrap_or_default();
+    let Some(access) = body["access_token"].as_str().filter(|_| status.is_success()) else {
+        // invalid_grant and friends: the refresh token itself is no good, which retrying
+        // won't fix any more than a wrong password would
+        let reason = body["error_description"].as_str().or(body["error"].as_str()).unwrap_or_default();
+        let message = format!("Token refresh failed with status {}: {}", status, reason);
+        return Err(if status.is_client_error() { AuthError(message).into() } else { Error::Network(message) });
+    };
+
+    let lifetime = body["expires_in"].as_u64().unwrap_or(3600);
+    debug!("Refreshed the OAuth2 access token for {}, valid for {}s", config.imap_username, lifetime);
+    let token = Token {
+        access: access.to_string(),
+        refresh: body["refresh_token"].as_str().map_or(refresh_token, str::to_string),
+        expires: Instant::now() + Duration::from_secs(lifetime.saturating_sub(60)),
+    };
+    TOKENS.lock().unwrap().get_or_insert_with(HashMap::new).insert(config.imap_username.clone(), token);
+    Ok(access.to_string())
+}
+
+// Expires the cached access token, keeping the refresh token
+fn forget(config: &Config) {
+    if let Some(token) = TOKENS.lock().unwrap().as_mut().and_then(|t| t.get_mut(&config.imap_username)) {
+        token.expires = Instant::now();
+    }
+}
+
+// `user=<user>^Aauth=Bearer <token>^A^A`. When the token is refused, Gmail sends the reason
+// as a challenge and expects an empty reply before the NO.
+struct XOAuth2 {
+    user: String,
+    token: String,
+}
+
+impl imap::Authenticator for XOAuth2 {
+    type Response = String;
+
+    fn process(&self, challenge: &[u8]) -> String {
+        if !challenge.is_empty() {
+            return String::new();
+        }
+        format!("user={}\x01auth=Bearer {}\x01\x01", self.user, self.token)
+    }
+}
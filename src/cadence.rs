@@ -0,0 +1,138 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::mail::Email;
+use crate::ops;
+use crate::series::sender_address;
+use crate::state::StateStore;
+use chrono::{DateTime, Datelike, NaiveTime, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::error;
+
+const PREFIX: &str = "cadence:";
+
+// Arrivals kept per sender; enough for a few months of a weekly newsletter
+const MAX_ARRIVALS: usize = 30;
+
+// Senders whose issues are usually less than this far apart are treated as daily: they
+// are expected at their usual time of day, on the weekdays they have been seen on.
+const DAILY: chrono::Duration = chrono::Duration::hours(36);
+
+const CHECK_EVERY: Duration = Duration::from_secs(10 * 60);
+
+// When each sender's newsletters arrived, to learn their cadence and notice a missing issue
+#[derive(Serialize, Deserialize, Default)]
+struct Cadence {
+    from: String,
+    arrivals: Vec<DateTime<Utc>>,
+    // The deadline of the last missed issue alerted about, so each one is reported once
+    alerted: Option<DateTime<Utc>>,
+}
+
+pub fn observe(config: &Config, store: &dyn StateStore, email: &Email) {
+    if !config.cadence.as_ref().is_some_and(|c| c.enabled) {
+        return;
+    }
+    let key = format!("{}{}", PREFIX, sender_address(&email.from));
+    let result = store.get_json::<Cadence>(&key).and_then(|cadence| {
+        let mut cadence = cadence.unwrap_or_default();
+        cadence.from = email.from.clone();
+        cadence.arrivals.push(Utc::now());
+        let excess = cadence.arrivals.len().saturating_sub(MAX_ARRIVALS);
+        cadence.arrivals.drain(..excess);
+        store.put_json(&key, &cadence)
+    });
+    if let Err(e) = result {
+        error!("Failed to record arrival for cadence: {}", e);
+    }
+}
+
+#[derive(Default)]
+pub struct Watcher {
+    last_run: Option<Instant>,
+}
+
+impl Watcher {
+    pub fn maybe_run(&mut self, config: &Config, store: &dyn StateStore) {
+        if !config.cadence.as_ref().is_some_and(|c| c.enabled) {
+            return;
+        }
+        if self.last_run.is_some_and(|last| last.elapsed() < CHECK_EVERY) {
+            return;
+        }
+        self.last_run = Some(Instant::now());
+        if let Err(e) = check(config, store) {
+            error!("Failed to check newsletter cadences: {}", e);
+        }
+    }
+}
+
+fn check(config: &Config, store: &dyn StateStore) -> Result<(), Error> {
+    let settings = config.cadence.clone().unwrap_or_default();
+    let tolerance = chrono::Duration::minutes(settings.tolerance_minutes() as i64);
+    let now = Utc::now();
+    for key in store.keys(PREFIX)? {
+        let Some(mut cadence) = store.get_json::<Cadence>(&key)? else {
+            continue;
+        };
+        if cadence.arrivals.len() < settings.min_samples() {
+            continue;
+        }
+        let Some((deadline, usual)) = missing(config, &cadence, tolerance, now) else {
+            continue;
+        };
+        if cadence.alerted == Some(deadline) {
+            continue;
+        }
+        ops::alert(config, "Newsletter missing", &format!("{} {}", cadence.from, usual));
+        cadence.alerted = Some(deadline);
+        store.put_json(&key, &cadence)?;
+    }
+    Ok(())
+}
+
+// The deadline an issue has missed, and how the sender usually behaves, if one is overdue
+fn missing(
+    config: &Config,
+    cadence: &Cadence,
+    tolerance: chrono::Duration,
+    now: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, String)> {
+    let last = *cadence.arrivals.last()?;
+    let mut gaps: Vec<chrono::Duration> = cadence.arrivals.windows(2).map(|w| w[1] - w[0]).collect();
+    gaps.sort();
+    let gap = *gaps.get(gaps.len() / 2)?;
+
+    if gap > DAILY {
+        // A quarter of the usual gap, so a weekly issue a day late isn't reported
+        let deadline = last + gap + tolerance.max(gap / 4);
+        let days = |d: chrono::Duration| (d.num_hours() as f64 / 24.0).round() as i64;
+        return (now > deadline).then(|| {
+            let usual = format!(
+                "usually arrives every {} days; the last one came {} days ago.",
+                days(gap),
+                days(now - last)
+            );
+            (deadline, usual)
+        });
+    }
+
+    let tz = config.timezone(None);
+    let local = |t: &DateTime<Utc>| t.with_timezone(&tz);
+    let today = local(&now);
+    if local(&last).date_naive() == today.date_naive() {
+        return None;
+    }
+    // Only on weekdays it has come on before (weekday-only newsletters skip weekends)
+    if !cadence.arrivals.iter().any(|t| local(t).weekday() == today.weekday()) {
+        return None;
+    }
+    let mut minutes: Vec<u32> = cadence.arrivals.iter().map(|t| local(t).num_seconds_from_midnight() / 60).collect();
+    minutes.sort();
+    let usual = NaiveTime::from_hms_opt(minutes[minutes.len() / 2] / 60, minutes[minutes.len() / 2] % 60, 0)?;
+    let expected = tz.from_local_datetime(&today.date_naive().and_time(usual)).earliest()?.with_timezone(&Utc);
+    let deadline = expected + tolerance;
+    (now > deadline).then(|| {
+        (deadline, format!("usually arrives by {}, nothing today.", (expected + tolerance).with_timezone(&tz).format("%H:%M")))
+    })
+}
@@ -0,0 +1,102 @@
+use crate::{cluster, webhooks};
+use crate::config::{Category, CategoryDelivery, Config, Route, parse_schedule};
+use crate::error::Error;
+use crate::mail::Email;
+use crate::snooze::HeldMessage;
+use crate::state::StateStore;
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+const PREFIX: &str = "digest:";
+
+// The emails a digest category has collected since its last digest
+#[derive(Serialize, Deserialize)]
+struct Pending {
+    since: DateTime<Utc>,
+    held: Vec<HeldMessage>,
+}
+
+// The category of the route, if it has one
+pub fn find<'a>(config: &'a Config, route: Option<&Route>) -> Option<&'a Category> {
+    let name = route?.category.as_deref()?;
+    config.categories.iter().flatten().find(|c| c.name == name)
+}
+
+// Posted as they come, even during quiet hours or while the route is paced
+pub fn is_immediate(config: &Config, route: Option<&Route>) -> bool {
+    find(config, route).is_some_and(|c| c.delivery == CategoryDelivery::Immediate)
+}
+
+// Returns true (and remembers the message for the digest) if the email's category is
+// delivered as a digest
+pub fn hold(config: &Config, store: &dyn StateStore, route: Option<&Route>, email: &Email) -> Result<bool, Error> {
+    let Some(category) = find(config, route).filter(|c| c.delivery == CategoryDelivery::Digest) else {
+        return Ok(false);
+    };
+    let key = format!("{}{}", PREFIX, category.name);
+    let mut pending = store.get_json::<Pending>(&key)?.unwrap_or_else(|| Pending {
+        since: Utc::now(),
+        held: Vec::new(),
+    });
+    pending.held.push(HeldMessage {
+        subject: email.subject.clone(),
+        from: email.from.clone(),
+    });
+    store.put_json(&key, &pending)?;
+    Ok(true)
+}
+
+// Posts the digest of every category whose scheduled time has come since it started
+// collecting
+pub fn flush_due(config: &Config, store: &dyn StateStore) -> Result<(), Error> {
+    let tz = config.timezone(None);
+    let now = Utc::now();
+    for category in config.categories.iter().flatten().filter(|c| c.delivery == CategoryDelivery::Digest) {
+        let key = format!("{}{}", PREFIX, category.name);
+        let Some(pending) = store.get_json::<Pending>(&key)? else {
+            continue;
+        };
+        let schedule = category.schedule.as_deref().unwrap_or_default();
+        let due = last_due(schedule, tz, now).map_err(|e| Error::Config(format!("Category {}: {}", category.name, e)))?;
+        if due <= pending.since {
+            continue;
+        }
+        info!("Posting the {} digest ({} messages)", category.name, pending.held.len());
+        let emails: Vec<Email> = pending
+            .held
+            .iter()
+            .map(|m| Email::new(m.subject.clone(), m.from.clone(), String::new()))
+            .collect();
+        let refs: Vec<&Email> = emails.iter().collect();
+        let title = format!("🗂️ {} digest: {} messages", category.name, refs.len());
+        let target = category.route.as_ref().and_then(|name| config.routes.iter().flatten().find(|r| &r.name == name));
+        if let Err(e) = webhooks::send(config, target, &cluster::digest_payload(config, store, &title, &refs), None) {
+            // Kept, so the digest is retried next cycle
+            error!("Failed to send the {} digest: {}", category.name, e);
+            continue;
+        }
+        store.delete(&key)?;
+    }
+    Ok(())
+}
+
+// The latest scheduled time at or before `now`
+pub fn last_due(schedule: &str, tz: Tz, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    let (weekday, time) = parse_schedule(schedule)?;
+    let today = now.with_timezone(&tz).date_naive();
+    // Today, or the last day it falls on when weekly; a week back covers every case
+    for back in 0..8 {
+        let day = today - chrono::Duration::days(back);
+        if weekday.is_some_and(|w| day.weekday() != w) {
+            continue;
+        }
+        if let Some(at) = tz.from_local_datetime(&day.and_time(time)).earliest().map(|t| t.with_timezone(&Utc))
+            && at <= now
+        {
+            return Ok(at);
+        }
+    }
+    Err(format!("No scheduled time found for {:?}", schedule))
+}
@@ -0,0 +1,118 @@
+use crate::config::{Config, SummarizeConfig};
+use crate::discord;
+use crate::error::Error;
+use crate::mail::Email;
+use crate::state::StateStore;
+use crate::usage;
+use serde_json::{Value, json};
+use tracing::warn;
+
+// Fewer emails than this are listed as they are
+const MIN_EMAILS: usize = 4;
+
+pub struct Topic<'a> {
+    pub title: String,
+    pub emails: Vec<&'a Email>,
+}
+
+// A digest of `emails`, grouped by topic when `summarize.cluster_digests` is on and the
+// embeddings request succeeds, or as a flat list otherwise.
+pub fn digest_payload(config: &Config, store: &dyn StateStore, title: &str, emails: &[&Email]) -> Value {
+    let Some(summarize) = config.summarize.as_ref().filter(|s| s.enabled && s.cluster_digests) else {
+        return discord::build_digest_payload(title, emails);
+    };
+    if emails.len() < MIN_EMAILS || !usage::within_budget(config, store, summarize) {
+        return discord::build_digest_payload(title, emails);
+    }
+    let _span = crate::otel::span("cluster");
+    match embed(store, summarize, emails) {
+        Ok(vectors) => discord::build_topic_digest_payload(title, &cluster(emails, &vectors, summarize.cluster_threshold())),
+        Err(e) => {
+            warn!("Topic clustering failed, posting a flat digest: {}", e);
+            discord::build_digest_payload(title, emails)
+        }
+    }
+}
+
+fn embed(store: &dyn StateStore, summarize: &SummarizeConfig, emails: &[&Email]) -> Result<Vec<Vec<f64>>, Error> {
+    let input: Vec<String> = emails
+        .iter()
+        .map(|e| format!("{}\n{}", e.subject, e.body.chars().take(2000).collect::<String>()))
+        .collect();
+    let url = format!("{}/embeddings", summarize.endpoint().trim_end_matches('/'));
+    let response = crate::http::client()
+        .post(&url)
+        .bearer_auth(&summarize.api_key)
+        .json(&json!({ "model": summarize.embedding_model(), "input": input }))
+        .send()?;
+    if !response.status().is_success() {
+        return Err(Error::Network(format!("Status {}", response.status())));
+    }
+    let response: Value = response.json()?;
+    let tokens = response["usage"]["prompt_tokens"].as_u64().unwrap_or(0);
+    usage::record(store, tokens, 0, tokens as f64 * summarize.embedding_price() / 1_000_000.0);
+
+    let mut vectors = vec![Vec::new(); emails.len()];
+    for item in response["data"].as_array().ok_or_else(|| Error::parse("No embeddings in response"))? {
+        let index = item["index"].as_u64().unwrap_or(0) as usize;
+        if let Some(slot) = vectors.get_mut(index) {
+            *slot = item["embedding"].as_array().into_iter().flatten().filter_map(Value::as_f64).collect();
+        }
+    }
+    if vectors.iter().any(Vec::is_empty) {
+        return Err(Error::parse("Missing embeddings in response"));
+    }
+    Ok(vectors)
+}
+
+// Single pass: each email joins the most similar topic above the threshold, or starts a new
+// one. Topics are titled after the email closest to their centroid, largest topic first.
+fn cluster<'a>(emails: &[&'a Email], vectors: &[Vec<f64>], threshold: f64) -> Vec<Topic<'a>> {
+    let mut centroids: Vec<Vec<f64>> = Vec::new();
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    for (i, vector) in vectors.iter().enumerate() {
+        let best = centroids
+            .iter()
+            .enumerate()
+            .map(|(c, centroid)| (c, cosine(centroid, vector)))
+            .filter(|(_, similarity)| *similarity >= threshold)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match best {
+            Some((c, _)) => {
+                let n = members[c].len() as f64;
+                for (value, x) in centroids[c].iter_mut().zip(vector) {
+                    *value = (*value * n + x) / (n + 1.0);
+                }
+                members[c].push(i);
+            }
+            None => {
+                centroids.push(vector.clone());
+                members.push(vec![i]);
+            }
+        }
+    }
+
+    let mut topics: Vec<Topic> = centroids
+        .iter()
+        .zip(members)
+        .map(|(centroid, members)| {
+            let central = members
+                .iter()
+                .copied()
+                .max_by(|a, b| cosine(centroid, &vectors[*a]).total_cmp(&cosine(centroid, &vectors[*b])))
+                .unwrap_or(members[0]);
+            Topic {
+                title: emails[central].subject.chars().take(80).collect(),
+                emails: members.iter().map(|i| emails[*i]).collect(),
+            }
+        })
+        .collect();
+    topics.sort_by_key(|t| std::cmp::Reverse(t.emails.len()));
+    topics
+}
+
+fn cosine(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = a.iter().map(|x| x * x).sum::<f64>().sqrt() * b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm == 0.0 { 0.0 } else { dot / norm }
+}
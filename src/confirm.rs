@@ -0,0 +1,117 @@
+use crate::discord::Posted;
+use crate::error::Error;
+use crate::mail::Email;
+use crate::state::StateStore;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::OnceLock;
+use tracing::{error, warn};
+
+const PREFIX: &str = "delivery:";
+
+// Discord's epoch (2015-01-01) in Unix milliseconds, for building snowflakes from times
+const DISCORD_EPOCH_MS: i64 = 1_420_070_400_000;
+
+static BOT_TOKEN: OnceLock<String> = OnceLock::new();
+
+// A webhook call that timed out or failed with a server error may still have posted. Before
+// such a delivery is retried, the channel's recent messages are read through the bot API and
+// a matching post from the same webhook counts as delivered, so a retry never shows twice.
+// Without `discord_bot_token` there is nothing to check with and the retry goes ahead.
+pub fn init(bot_token: Option<&str>) {
+    if let Some(token) = bot_token {
+        let _ = BOT_TOKEN.set(token.to_string());
+    }
+}
+
+// Idempotency record for an email whose delivery has started but not been confirmed
+#[derive(Serialize, Deserialize)]
+struct Attempt {
+    since: DateTime<Utc>,
+}
+
+// Starts (or resumes) a delivery. Returns when the first attempt was made, and whether an
+// earlier attempt may already have posted, in which case it is checked for before posting.
+pub fn begin(store: &dyn StateStore, email: &Email) -> Result<(DateTime<Utc>, bool), Error> {
+    let key = format!("{}{}", PREFIX, email.trace_id);
+    if let Some(attempt) = store.get_json::<Attempt>(&key)? {
+        return Ok((attempt.since, true));
+    }
+    let since = Utc::now();
+    store.put_json(&key, &Attempt { since })?;
+    Ok((since, false))
+}
+
+// The delivery was confirmed, or will never be retried
+pub fn finish(store: &dyn StateStore, email: &Email) {
+    if let Err(e) = store.delete(&format!("{}{}", PREFIX, email.trace_id)) {
+        error!("Failed to clear delivery record: {}", e);
+    }
+}
+
+// Looks for a message the webhook posted since `since` that carries this payload. Posts
+// that start a new forum thread can't be looked for, as their channel isn't known.
+pub fn find(webhook_url: &str, thread_id: Option<&str>, payload: &Value, since: DateTime<Utc>) -> Option<Posted> {
+    let token = BOT_TOKEN.get()?;
+    if payload.get("thread_name").is_some() && thread_id.is_none() {
+        return None;
+    }
+    checked((|| {
+        let webhook: Value = crate::http::client().get(webhook_url).send()?.error_for_status()?.json()?;
+        let webhook_id = webhook["id"].as_str().ok_or_else(|| Error::parse("Webhook has no id"))?;
+        let channel_id = thread_id.or(webhook["channel_id"].as_str()).ok_or_else(|| Error::parse("Webhook has no channel"))?;
+        find_in(token, channel_id, payload, since, |m| m["webhook_id"].as_str() == Some(webhook_id))
+    })())
+}
+
+// The same for a post the bot made itself (see `discord::send_to_channel`)
+pub fn find_own(channel_id: &str, payload: &Value, since: DateTime<Utc>) -> Option<Posted> {
+    let token = BOT_TOKEN.get()?;
+    if payload.get("thread_name").is_some() {
+        return None;
+    }
+    checked(find_in(token, channel_id, payload, since, |m| {
+        m["webhook_id"].is_null() && m["author"]["bot"].as_bool() == Some(true)
+    }))
+}
+
+fn checked(result: Result<Option<Posted>, Error>) -> Option<Posted> {
+    result.unwrap_or_else(|e| {
+        warn!("Could not check for an earlier delivery: {}", e);
+        None
+    })
+}
+
+fn find_in(
+    token: &str,
+    channel_id: &str,
+    payload: &Value,
+    since: DateTime<Utc>,
+    ours: impl Fn(&Value) -> bool,
+) -> Result<Option<Posted>, Error> {
+    // Allow for some clock skew between us and Discord
+    let after = ((since.timestamp_millis() - 5_000 - DISCORD_EPOCH_MS).max(0) as u64) << 22;
+    let messages: Vec<Value> = crate::http::client()
+        .get(format!("https://discord.com/api/v10/channels/{}/messages", channel_id))
+        .query(&[("after", after.to_string()), ("limit", "50".to_string())])
+        .header("Authorization", format!("Bot {}", token))
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(messages.iter().find(|m| ours(m) && same_post(m, payload)).map(|m| Posted {
+        id: m["id"].as_str().unwrap_or_default().to_string(),
+        channel_id: m["channel_id"].as_str().unwrap_or(channel_id).to_string(),
+    }))
+}
+
+// Compares what stays the same across re-renders and shrinking: the message text, or the
+// first embed's title and author. Timestamps and (summarized) descriptions may differ.
+fn same_post(message: &Value, payload: &Value) -> bool {
+    let content = |v: &Value| v["content"].as_str().unwrap_or_default().to_string();
+    let embed = |v: &Value| {
+        let embed = &v["embeds"][0];
+        (embed["title"].as_str().map(str::to_string), embed["author"]["name"].as_str().map(str::to_string))
+    };
+    content(message) == content(payload) && embed(message) == embed(payload)
+}
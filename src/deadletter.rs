@@ -0,0 +1,35 @@
+use crate::error::Error;
+use crate::mail::Email;
+use crate::state::StateStore;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const PREFIX: &str = "dead-letter:";
+
+// Emails Discord kept rejecting (bad or oversized payloads). They are taken out of the
+// delivery loop and kept here, with what was sent and why it failed, for inspection.
+#[derive(Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub trace_id: String,
+    pub subject: String,
+    pub from: String,
+    pub error: String,
+    pub payload: Value,
+    // Base64 of the original message, if there was one
+    pub raw: Option<String>,
+    pub failed_at: DateTime<Utc>,
+}
+
+pub fn save(store: &dyn StateStore, email: &Email, payload: &Value, error: &str) -> Result<(), Error> {
+    let letter = DeadLetter {
+        trace_id: email.trace_id.clone(),
+        subject: email.subject.clone(),
+        from: email.from.clone(),
+        error: error.to_string(),
+        payload: payload.clone(),
+        raw: email.raw.as_deref().map(openssl::base64::encode_block),
+        failed_at: Utc::now(),
+    };
+    store.put_json(&format!("{}{}", PREFIX, email.trace_id), &letter)
+}
@@ -0,0 +1,52 @@
+// Line-based diff via longest common subsequence. Newsletter bodies are a few hundred lines,
+// so the quadratic table is fine; callers skip diffing above `MAX_LINES`.
+pub const MAX_LINES: usize = 2000;
+
+pub enum Change<'a> {
+    Same,
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+pub fn lines<'a>(old: &'a str, new: &'a str) -> Vec<Change<'a>> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] = length of the LCS of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            changes.push(Change::Same);
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            changes.push(Change::Removed(a[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(b[j]));
+            j += 1;
+        }
+    }
+    changes.extend(a[i..].iter().map(|l| Change::Removed(l)));
+    changes.extend(b[j..].iter().map(|l| Change::Added(l)));
+    changes
+}
+
+// Share of lines the two versions have in common, 0.0 to 1.0
+pub fn similarity(changes: &[Change]) -> f64 {
+    let same = changes.iter().filter(|c| matches!(c, Change::Same)).count();
+    let total = changes.len() + same; // each Same line counts once per side
+    if total == 0 { 1.0 } else { 2.0 * same as f64 / total as f64 }
+}
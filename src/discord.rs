@@ -0,0 +1,672 @@
+use crate::error::Error;
+use crate::mail::Email;
+use chrono::{DateTime, Utc};
+use reqwest::blocking::multipart::{Form, Part};
+use serde_json::Value;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+// `color` overrides the stripe color derived from the sender's domain; `marker` ends a
+// body that had to be cut ("..." by default)
+pub fn build_payload(email: &Email, color: Option<u32>, marker: Option<&str>) -> Value {
+    let _span = crate::otel::span("render");
+    embed_payload(email, color, marker, Utc::now())
+}
+
+// The embed for an email as posted at `now`. Depends on nothing else, so the golden tests
+// can pin its output.
+pub fn embed_payload(email: &Email, color: Option<u32>, marker: Option<&str>, now: DateTime<Utc>) -> Value {
+    // Truncate body if too long for Discord (limit is 2000 chars)
+    let display_body = if email.body.len() > 1500 {
+        let mut end = 1500;
+        while !email.body.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}{}", &email.body[..end], marker.unwrap_or("..."))
+    } else {
+        email.body.clone()
+    };
+
+    let title = embed_title(&email.subject);
+    let mut payload = serde_json::json!({
+        "embeds": [{
+            "title": title,
+            "author": {
+                "name": email.from
+            },
+            "description": display_body,
+            "color": color.unwrap_or_else(|| sender_color(&email.from)),
+            "timestamp": now.to_rfc3339(),
+            "footer": {
+                "text": match email.folder {
+                    Some(ref folder) => format!("📰 Newsletter · {}", folder),
+                    None => "📰 Newsletter".to_string(),
+                }
+            }
+        }]
+    });
+    // The first inline image goes in the embed; the rest show as attachments below it.
+    // Without one, the HTML's first remote image is shown (Discord fetches it).
+    if let Some(first) = email.images.first() {
+        payload["embeds"][0]["image"] = serde_json::json!({ "url": format!("attachment://{}", first.filename) });
+    } else if let Some(ref url) = email.image_url {
+        payload["embeds"][0]["image"] = serde_json::json!({ "url": url });
+    }
+    let mut fields = Vec::new();
+    // A cut title keeps the whole subject below it (field values are capped at 1024 chars)
+    let subject = strip_bidi(&email.subject);
+    if title != subject.trim() {
+        fields.push(serde_json::json!({ "name": "Subject", "value": cut(subject.trim(), 1024) }));
+    }
+    let left_out = attach_files(&mut payload, email);
+    if !left_out.is_empty() {
+        fields.push(serde_json::json!({ "name": "Not uploaded", "value": left_out }));
+    }
+    if !fields.is_empty() {
+        payload["embeds"][0]["fields"] = Value::Array(fields);
+    }
+    payload
+}
+
+// Embed titles are capped at 256 chars
+const MAX_TITLE: usize = 256;
+
+// Explicit direction marks, embeddings, overrides and isolates. An unbalanced override in a
+// subject flips the rest of the embed (or the whole message line in a client) right to
+// left; Arabic or Hebrew text itself displays fine without them.
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200E}' | '\u{200F}' | '\u{061C}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+// The text without bidi controls, and with line breaks and other control characters as spaces
+pub fn strip_bidi(text: &str) -> String {
+    text.chars().filter(|&c| !is_bidi_control(c)).map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+// A subject made fit for an embed title: bidi controls stripped and, when too long, cut at
+// a word boundary with an ellipsis
+pub fn embed_title(subject: &str) -> String {
+    cut(strip_bidi(subject).trim(), MAX_TITLE)
+}
+
+// At most `max` chars, breaking after the last word that fits when there is one in the
+// last quarter, and ending in "…" when anything was cut
+fn cut(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max - 1).collect();
+    let kept = match kept.rfind(char::is_whitespace) {
+        Some(i) if kept[..i].chars().count() >= max * 3 / 4 => &kept[..i],
+        _ => kept.as_str(),
+    };
+    format!("{}…", kept.trim_end())
+}
+
+// Files to upload with a payload travel inside it under this key, as base64, so they pass
+// through routing and failover untouched. `post` takes them out and sends multipart.
+pub const FILES_KEY: &str = "_files";
+
+// Discord's limits on uploads with one message (for servers without boosts)
+const MAX_FILES: usize = 10;
+const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024;
+
+// Adds the inline images and as many attachments as fit within Discord's limits, in order.
+// Returns the attachments left out, one "name (size)" per line, for the message to list.
+fn attach_files(payload: &mut Value, email: &Email) -> String {
+    let mut files = Vec::new();
+    let mut total = 0;
+    let mut file = |filename: &str, content_type: &str, data: &[u8]| {
+        if files.len() >= MAX_FILES || total + data.len() > MAX_UPLOAD_BYTES {
+            return false;
+        }
+        total += data.len();
+        files.push(serde_json::json!({
+            "filename": filename,
+            "content_type": content_type,
+            "data": openssl::base64::encode_block(data),
+        }));
+        true
+    };
+    for image in &email.images {
+        file(&image.filename, &image.content_type, &image.data);
+    }
+    let mut left_out = String::new();
+    for attachment in &email.attachments {
+        if !file(&attachment.filename, &attachment.content_type, &attachment.data) {
+            let line = format!("{} ({} KB)\n", attachment.filename, attachment.data.len().div_ceil(1024));
+            // Embed field values are capped at 1024 chars
+            if left_out.chars().count() + line.chars().count() <= 1000 {
+                left_out.push_str(&line);
+            }
+        }
+    }
+    if !files.is_empty() {
+        payload[FILES_KEY] = Value::Array(files);
+    }
+    left_out.trim_end().to_string()
+}
+
+// Adds a text file to the payload's uploads, if it still fits within the limits
+pub fn attach_text(payload: &mut Value, filename: &str, text: &str) -> bool {
+    let files = payload.get(FILES_KEY).and_then(Value::as_array);
+    let count = files.map_or(0, Vec::len);
+    // Base64 is 4 chars for every 3 bytes
+    let total: usize = files.into_iter().flatten().filter_map(|f| f["data"].as_str()).map(|d| d.len() / 4 * 3).sum();
+    if count >= MAX_FILES || total + text.len() > MAX_UPLOAD_BYTES {
+        return false;
+    }
+    let file = serde_json::json!({
+        "filename": filename,
+        "content_type": "text/plain; charset=utf-8",
+        "data": openssl::base64::encode_block(text.as_bytes()),
+    });
+    match payload[FILES_KEY].as_array_mut() {
+        Some(files) => files.push(file),
+        None => payload[FILES_KEY] = Value::Array(vec![file]),
+    }
+    true
+}
+
+// Set on an email's payload by the pipeline so `send_to` can check for an earlier post
+// before retrying: when the delivery first started, and whether an earlier attempt may
+// already have posted.
+const DELIVERY_KEY: &str = "_delivery";
+
+pub fn mark_delivery(payload: &mut Value, since: DateTime<Utc>, retried: bool) {
+    payload[DELIVERY_KEY] = serde_json::json!({ "since": since, "retried": retried });
+}
+
+// The payload without its files and delivery details, as sent and stored in the archive
+pub fn without_files(payload: &Value) -> Value {
+    let mut payload = payload.clone();
+    if let Some(object) = payload.as_object_mut() {
+        object.remove(FILES_KEY);
+        object.remove(DELIVERY_KEY);
+    }
+    payload
+}
+
+// A stable color per sending domain: the domain's hash picks the hue, with saturation and
+// lightness fixed so every source stays readable on both Discord themes.
+pub fn sender_color(from: &str) -> u32 {
+    let address = match (from.rfind('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &from[start + 1..end],
+        _ => from.trim(),
+    };
+    let domain = address.rsplit('@').next().unwrap_or(address).to_lowercase();
+    // FNV-1a, so colors don't change between versions or platforms
+    let hash = domain.bytes().fold(0x811c9dc5u32, |h, b| (h ^ b as u32).wrapping_mul(0x01000193));
+    hsl_to_rgb((hash % 360) as f64, 0.65, 0.55)
+}
+
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> u32 {
+    let c = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = c * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 / 60 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = lightness - c / 2.0;
+    let channel = |v: f64| ((v + m) * 255.0).round() as u32;
+    (channel(r) << 16) | (channel(g) << 8) | channel(b)
+}
+
+// The email as message content instead of an embed, for screen readers: a bold subject,
+// the sender, and as much of the body as fits, with a link to the full text when there is one.
+pub fn build_plain_payload(email: &Email, full_text_url: Option<&str>, marker: Option<&str>) -> Value {
+    let _span = crate::otel::span("render");
+    let header = format!("**{}**\nFrom: {}\n\n", strip_bidi(&email.subject).trim(), email.from);
+    let mut payload = serde_json::json!({ "allowed_mentions": { "parse": [] } });
+    let left_out = attach_files(&mut payload, email);
+    let mut footer = match full_text_url {
+        Some(url) => format!("\n\nFull text: <{}>", url),
+        None => String::new(),
+    };
+    if !left_out.is_empty() {
+        footer.push_str(&format!("\n\nNot uploaded:\n{}", left_out));
+    }
+    // Message content is capped at 2000 chars
+    let marker = marker.unwrap_or("…");
+    let room = 2000usize.saturating_sub(header.chars().count() + footer.chars().count() + marker.chars().count());
+    let body = email.body.trim();
+    let body = if body.chars().count() > room {
+        format!("{}{}", body.chars().take(room).collect::<String>(), marker)
+    } else {
+        body.to_string()
+    };
+
+    payload["content"] = Value::String(format!("{}{}{}", header, body, footer));
+    payload
+}
+
+// One embed listing several emails, used when a backlog is collapsed instead of posted
+// message by message.
+pub fn build_digest_payload(title: &str, emails: &[&Email]) -> Value {
+    let mut description = String::new();
+    for (i, email) in emails.iter().enumerate() {
+        let line = format!("• **{}** — {}\n", strip_bidi(&email.subject).trim(), email.from);
+        // Embed descriptions are capped at 4096 chars
+        if description.chars().count() + line.chars().count() > 4000 {
+            description.push_str(&format!("…and {} more", emails.len() - i));
+            break;
+        }
+        description.push_str(&line);
+    }
+
+    serde_json::json!({
+        "embeds": [{
+            "title": title,
+            "description": description,
+            "color": 0x5865F2, // Blurple
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "footer": {
+                "text": "📰 Newsletter"
+            }
+        }]
+    })
+}
+
+// A digest grouped by topic: one embed field per topic (Discord allows 25)
+pub fn build_topic_digest_payload(title: &str, topics: &[crate::cluster::Topic]) -> Value {
+    let mut fields = Vec::new();
+    for (i, topic) in topics.iter().enumerate() {
+        if i == 24 && topics.len() > 25 {
+            let rest: usize = topics[i..].iter().map(|t| t.emails.len()).sum();
+            fields.push(serde_json::json!({ "name": "Other", "value": format!("…and {} more", rest) }));
+            break;
+        }
+        let mut value = String::new();
+        for (j, email) in topic.emails.iter().enumerate() {
+            let line = format!("• **{}** — {}\n", strip_bidi(&email.subject).trim(), email.from);
+            // Field values are capped at 1024 chars
+            if value.chars().count() + line.chars().count() > 980 {
+                value.push_str(&format!("…and {} more", topic.emails.len() - j));
+                break;
+            }
+            value.push_str(&line);
+        }
+        fields.push(serde_json::json!({
+            "name": format!("{} ({})", topic.title, topic.emails.len()),
+            "value": value,
+        }));
+    }
+
+    serde_json::json!({
+        "embeds": [{
+            "title": title,
+            "fields": fields,
+            "color": 0x5865F2, // Blurple
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "footer": {
+                "text": "📰 Newsletter"
+            }
+        }]
+    })
+}
+
+// Re-expresses embed timestamps in another timezone. Discord shows each reader their own
+// local time, but the offset stays in the message for integrations and exports.
+pub fn localize_timestamps(payload: &mut Value, tz: chrono_tz::Tz) {
+    for embed in payload["embeds"].as_array_mut().into_iter().flatten() {
+        if let Some(time) = embed["timestamp"].as_str().and_then(|t| DateTime::parse_from_rfc3339(t).ok()) {
+            embed["timestamp"] = Value::String(time.with_timezone(&tz).to_rfc3339());
+        }
+    }
+}
+
+// The message Discord created, as returned when the webhook is executed with `wait=true`.
+// For a new forum post, `channel_id` is the ID of the thread.
+pub struct Posted {
+    pub id: String,
+    pub channel_id: String,
+}
+
+// Rate limits, server errors and network failures are retried with backoff (a 429 waits as
+// long as Discord says, with its own, larger budget of retries), and a payload Discord
+// rejects as too large is shrunk and re-sent. Anything else is returned as a
+// `WebhookError` for the caller's policy. For payloads marked with `mark_delivery`, a
+// retry after a failure that may have posted anyway first looks for that post.
+pub fn send_to(webhook_url: &str, payload: &Value, thread_id: Option<&str>) -> Result<Posted, Error> {
+    let target = format!("discord:{}", webhook_url);
+    send_with(
+        "webhook.send",
+        &target,
+        payload,
+        |payload| post(&target, webhook_url, payload, thread_id),
+        |payload, since| crate::confirm::find(webhook_url, thread_id, payload, since),
+    )
+}
+
+// Posts as the bot (`discord_bot_token`) to a channel, or to a thread in it, with the same
+// payloads and retry policy as webhooks. Unlike a webhook, the bot can post wherever it has
+// been given permission to, forum channels included.
+pub fn send_to_channel(token: &str, channel_id: &str, payload: &Value, thread_id: Option<&str>) -> Result<Posted, Error> {
+    let channel_id = thread_id.unwrap_or(channel_id);
+    let target = format!("discord:{}", channel_id);
+    send_with(
+        "discord.send",
+        &target,
+        payload,
+        |payload| post_as_bot(&target, token, channel_id, payload),
+        |payload, since| crate::confirm::find_own(channel_id, payload, since),
+    )
+}
+
+// The retry loop shared by every backend: `post` makes one attempt, and `find` looks for
+// the post of an earlier attempt that may have gone through. `target` names the rate limit
+// bucket, "<service>:<webhook, channel or chat>", so a 429 from one Slack workspace only
+// holds up the posts to that workspace.
+pub fn send_with(
+    span_name: &'static str,
+    target: &str,
+    payload: &Value,
+    post: impl Fn(&Value) -> Result<Posted, WebhookError>,
+    find: impl Fn(&Value, DateTime<Utc>) -> Option<Posted>,
+) -> Result<Posted, Error> {
+    let mut span = crate::otel::span(span_name);
+    let mut payload = payload.clone();
+    let since = payload[DELIVERY_KEY]["since"].as_str().and_then(|s| s.parse::<DateTime<Utc>>().ok());
+    let mut check = payload[DELIVERY_KEY]["retried"].as_bool().unwrap_or(false);
+    let (mut attempt, mut limited) = (0, 0);
+    let result = loop {
+        if let Err(err) = wait_for_bucket(target) {
+            break Err(err);
+        }
+        if check
+            && let Some(since) = since
+            && let Some(posted) = find(&payload, since)
+        {
+            info!("Found the message from an earlier attempt, not posting again");
+            break Ok(posted);
+        }
+        let started = Instant::now();
+        let attempt_result = post(&payload);
+        crate::metrics::observe("newsletter_webhook_duration_seconds", Some(("target", span_name)), started.elapsed().as_secs_f64());
+        let err = match attempt_result {
+            Ok(posted) => break Ok(posted),
+            Err(err) => err,
+        };
+        match err.failure() {
+            // A burst of newsletters runs into these; nothing was posted, so it's only a
+            // matter of waiting
+            Failure::RateLimited if limited < MAX_RATE_LIMITED => {
+                limited += 1;
+                check = false;
+                let backoff = Duration::from_secs(1 << limited.min(5));
+                let wait = err.retry_after.unwrap_or(backoff).min(MAX_RETRY_WAIT);
+                warn!("Webhook {}, retrying in {:.1}s ({}/{})", err, wait.as_secs_f64(), limited, MAX_RATE_LIMITED);
+                note_bucket(target, wait);
+            }
+            Failure::Server | Failure::Network if attempt + 1 < MAX_ATTEMPTS => {
+                attempt += 1;
+                // A timeout or a 5xx can come after Discord created the message
+                check = true;
+                let backoff = Duration::from_secs(1 << (attempt - 1));
+                let wait = err.retry_after.unwrap_or(backoff).min(MAX_RETRY_WAIT);
+                warn!("Webhook {}, retrying in {:.1}s", err, wait.as_secs_f64());
+                thread::sleep(wait);
+            }
+            Failure::TooLarge if shrink(&mut payload) => warn!("Webhook payload too large, retrying shortened"),
+            _ => break Err(err),
+        }
+    };
+    if let (Some(span), Err(e)) = (span.as_mut(), &result) {
+        span.fail(e);
+    }
+    result.map_err(Into::into)
+}
+
+const MAX_ATTEMPTS: u32 = 4;
+// 429s retried before the message is left for the next cycle
+const MAX_RATE_LIMITED: u32 = 10;
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+// An email's delivery waits this long at most for its target's bucket when the retry queue
+// can take it over (see `deferring`), so the mailbox worker moves on to emails bound for
+// other targets
+const MAX_DELIVERY_WAIT: Duration = Duration::from_secs(5);
+
+thread_local! {
+    static DEFERRABLE: Cell<bool> = const { Cell::new(false) };
+}
+
+// Set by the pipeline around an email's post when [retry] is on and the email has its
+// message to resend from. Anything else (alerts, digests, emails that would only be left
+// with the source) waits the bucket out.
+pub struct Deferring(bool);
+
+pub fn deferring(on: bool) -> Deferring {
+    Deferring(DEFERRABLE.replace(on))
+}
+
+impl Drop for Deferring {
+    fn drop(&mut self) {
+        DEFERRABLE.set(self.0);
+    }
+}
+
+// When each rate limit bucket (a webhook, a bot channel, a chat, or a service's "global")
+// has requests again. Set from a 429, or from the X-RateLimit-* headers when a response
+// says the bucket is empty, so the next request waits for the reset instead of running
+// into a 429.
+static BUCKETS: Mutex<Option<HashMap<String, Instant>>> = Mutex::new(None);
+
+fn wait_for_bucket(target: &str) -> Result<(), WebhookError> {
+    let reset = {
+        let buckets = BUCKETS.lock().unwrap();
+        let reset_of = |key: &str| buckets.as_ref().and_then(|b| b.get(key).copied());
+        let service = target.split(':').next().unwrap_or(target);
+        reset_of(target).max(reset_of(&format!("{}:global", service)))
+    };
+    let Some(wait) = reset.map(|r| r.saturating_duration_since(Instant::now())).filter(|w| !w.is_zero()) else {
+        return Ok(());
+    };
+    if wait > MAX_DELIVERY_WAIT && DEFERRABLE.get() {
+        return Err(WebhookError {
+            status: Some(429),
+            retry_after: Some(wait),
+            message: format!("Rate limited for another {:.1}s, leaving it for later", wait.as_secs_f64()),
+        });
+    }
+    debug!("Rate limit bucket empty, waiting {:.1}s", wait.as_secs_f64());
+    thread::sleep(wait.min(MAX_RETRY_WAIT));
+    Ok(())
+}
+
+fn note_bucket(bucket: &str, wait: Duration) {
+    BUCKETS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(bucket.to_string(), Instant::now() + wait.min(MAX_RETRY_WAIT));
+}
+
+#[derive(Debug)]
+pub struct WebhookError {
+    // None when the request never got a response
+    pub status: Option<u16>,
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Failure {
+    // 400 (422 from Discourse): the payload was rejected; re-sending it won't help
+    BadPayload,
+    // 401/403/404/410: the webhook was deleted or its token revoked (410: a Slack channel
+    // was archived)
+    Dead,
+    // 413
+    TooLarge,
+    // 429
+    RateLimited,
+    // 5xx
+    Server,
+    Network,
+}
+
+impl WebhookError {
+    pub fn failure(&self) -> Failure {
+        match self.status {
+            None => Failure::Network,
+            Some(400 | 422) => Failure::BadPayload,
+            Some(401 | 403 | 404 | 410) => Failure::Dead,
+            Some(413) => Failure::TooLarge,
+            Some(429) => Failure::RateLimited,
+            Some(_) => Failure::Server,
+        }
+    }
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "Status {}: {}", status, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+fn network(e: &dyn fmt::Display) -> WebhookError {
+    WebhookError {
+        status: None,
+        retry_after: None,
+        message: e.to_string(),
+    }
+}
+
+fn post(target: &str, webhook_url: &str, payload: &Value, thread_id: Option<&str>) -> Result<Posted, WebhookError> {
+    let mut url = reqwest::Url::parse(webhook_url).map_err(|e| network(&e))?;
+    url.query_pairs_mut().append_pair("wait", "true");
+    if let Some(thread_id) = thread_id {
+        url.query_pairs_mut().append_pair("thread_id", thread_id);
+    }
+    let message = submit(crate::http::client().post(url), target, payload)?;
+    Ok(Posted {
+        id: message["id"].as_str().unwrap_or_default().to_string(),
+        channel_id: message["channel_id"].as_str().unwrap_or_default().to_string(),
+    })
+}
+
+// A payload naming a `thread_name` starts a forum post, as it does through a webhook
+fn post_as_bot(target: &str, token: &str, channel_id: &str, payload: &Value) -> Result<Posted, WebhookError> {
+    let request = |path: &str| {
+        crate::http::client()
+            .post(format!("https://discord.com/api/v10/channels/{}/{}", channel_id, path))
+            .header("Authorization", format!("Bot {}", token))
+    };
+    let Some(name) = payload.get("thread_name").and_then(Value::as_str) else {
+        let message = submit(request("messages"), target, payload)?;
+        return Ok(Posted {
+            id: message["id"].as_str().unwrap_or_default().to_string(),
+            channel_id: message["channel_id"].as_str().unwrap_or(channel_id).to_string(),
+        });
+    };
+    let mut message = without_files(payload);
+    if let Some(object) = message.as_object_mut() {
+        object.remove("thread_name");
+    }
+    // Files go with the request, not inside the message
+    let mut body = serde_json::json!({ "name": name, "message": message });
+    if let Some(files) = payload.get(FILES_KEY) {
+        body[FILES_KEY] = files.clone();
+    }
+    let thread = submit(request("threads"), target, &body)?;
+    // A forum post's starter message has the thread's ID
+    let thread_id = thread["id"].as_str().unwrap_or_default().to_string();
+    Ok(Posted {
+        id: thread["message"]["id"].as_str().map_or_else(|| thread_id.clone(), str::to_string),
+        channel_id: thread_id,
+    })
+}
+
+// Sends the payload as JSON, or multipart when it carries files, and returns the response body.
+// `bucket` names the rate limit the request counts against.
+fn submit(request: reqwest::blocking::RequestBuilder, bucket: &str, payload: &Value) -> Result<Value, WebhookError> {
+    let request = match payload.get(FILES_KEY).and_then(Value::as_array) {
+        Some(files) => request.multipart(multipart(payload, files).map_err(|e| network(&e))?),
+        None => request.json(&without_files(payload)),
+    };
+    let response = request.send().map_err(|e| network(&e))?;
+    let status = response.status();
+    let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<f64>().ok());
+    if header("x-ratelimit-remaining") == Some(0.0)
+        && let Some(reset_after) = header("x-ratelimit-reset-after")
+    {
+        note_bucket(bucket, Duration::from_secs_f64(reset_after.max(0.0)));
+    }
+    if !status.is_success() {
+        let header_wait = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<f64>().ok());
+        let body: Value = response.json().unwrap_or_default();
+        // Discord puts the precise wait in the body, in (fractional) seconds
+        let retry_after = body["retry_after"].as_f64().or(header_wait).map(|s| Duration::from_secs_f64(s.max(0.0)));
+        // A global limit holds up every other Discord webhook and channel too
+        if let Some(wait) = retry_after.filter(|_| body["global"].as_bool() == Some(true)) {
+            note_bucket("discord:global", wait);
+        }
+        return Err(WebhookError {
+            status: Some(status.as_u16()),
+            retry_after,
+            message: body["message"].as_str().unwrap_or(status.canonical_reason().unwrap_or_default()).to_string(),
+        });
+    }
+    Ok(response.json().unwrap_or_default())
+}
+
+// https://discord.com/developers/docs/reference#uploading-files
+fn multipart(payload: &Value, files: &[Value]) -> Result<Form, Error> {
+    let mut form = Form::new().text("payload_json", without_files(payload).to_string());
+    for (i, file) in files.iter().enumerate() {
+        let data = openssl::base64::decode_block(file["data"].as_str().unwrap_or_default())?;
+        let part = || Part::bytes(data.clone()).file_name(file["filename"].as_str().unwrap_or("file").to_string());
+        // The type comes from the email, and one that doesn't parse would fail every retry
+        let part = part()
+            .mime_str(file["content_type"].as_str().unwrap_or_default())
+            .or_else(|_| part().mime_str("application/octet-stream"))?;
+        form = form.part(format!("files[{}]", i), part);
+    }
+    Ok(form)
+}
+
+// Drops the uploads, which are what's too large when there are any (a proxy or server can
+// take less than Discord), else halves every embed description and drops embed fields.
+// Returns false once there is nothing left worth cutting.
+fn shrink(payload: &mut Value) -> bool {
+    if let Some(object) = payload.as_object_mut()
+        && object.remove(FILES_KEY).is_some()
+    {
+        return true;
+    }
+    let mut shrunk = false;
+    for embed in payload["embeds"].as_array_mut().into_iter().flatten() {
+        if let Some(embed) = embed.as_object_mut()
+            && embed.remove("fields").is_some()
+        {
+            shrunk = true;
+        }
+        if let Some(description) = embed["description"].as_str() {
+            let len = description.chars().count();
+            if len > 200 {
+                let kept: String = description.chars().take(len / 2).collect();
+                embed["description"] = Value::String(format!("{}…", kept));
+                shrunk = true;
+            }
+        }
+    }
+    shrunk
+}
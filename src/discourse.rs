@@ -0,0 +1,68 @@
+use crate::config::DiscourseConfig;
+use crate::discord::{self, Posted, WebhookError};
+use crate::error::Error;
+use crate::notify;
+use serde_json::{Value, json};
+use std::time::Duration;
+
+// Discourse's default min_topic_title_length and max_post_length
+const MIN_TITLE: usize = 15;
+const MAX_RAW: usize = 32000;
+
+// Creates a topic in the category for the email: the subject as the title, the rest of the
+// payload as the first post. A subject too short for Discourse gets the sender added.
+// Recurring subjects need the site's "allow duplicate topic titles" setting.
+pub fn send(discourse: &DiscourseConfig, category: u64, payload: &Value) -> Result<Posted, Error> {
+    let embed = &payload["embeds"][0];
+    let (mut title, mut raw) = match embed["title"].as_str() {
+        Some(title) => (title.to_string(), notify::markdown(payload, false)),
+        None => ("Newsletter".to_string(), notify::markdown(payload, true)),
+    };
+    if title.chars().count() < MIN_TITLE {
+        let from = embed["author"]["name"].as_str().unwrap_or("newsletter");
+        title = format!("{} · {}", title, from.split('<').next().map(str::trim).filter(|s| !s.is_empty()).unwrap_or(from));
+    }
+    if raw.len() > MAX_RAW {
+        let mut end = MAX_RAW - 3;
+        while !raw.is_char_boundary(end) {
+            end -= 1;
+        }
+        raw.truncate(end);
+        raw.push('…');
+    }
+    let topic = json!({ "title": title, "raw": raw, "category": category });
+    discord::send_with("discourse.send", &format!("discourse:{}", discourse.url), &topic, |topic| post(discourse, topic), |_, _| None)
+}
+
+fn post(discourse: &DiscourseConfig, topic: &Value) -> Result<Posted, WebhookError> {
+    let response = crate::http::client()
+        .post(format!("{}/posts.json", discourse.url.trim_end_matches('/')))
+        .header("Api-Key", &discourse.api_key)
+        .header("Api-Username", discourse.api_username.as_deref().unwrap_or("system"))
+        .json(topic)
+        .send()
+        .map_err(|e| WebhookError {
+            status: None,
+            retry_after: None,
+            message: e.to_string(),
+        })?;
+    let status = response.status();
+    let header_wait = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<f64>().ok());
+    let body: Value = response.json().unwrap_or_default();
+    if !status.is_success() {
+        let errors: Vec<&str> = body["errors"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+        return Err(WebhookError {
+            status: Some(status.as_u16()),
+            retry_after: body["extras"]["wait_seconds"].as_f64().or(header_wait).map(Duration::from_secs_f64),
+            message: if errors.is_empty() { status.to_string() } else { errors.join("; ") },
+        });
+    }
+    Ok(Posted {
+        id: body["id"].as_u64().map(|id| id.to_string()).unwrap_or_default(),
+        channel_id: body["topic_id"].as_u64().map(|id| id.to_string()).unwrap_or_default(),
+    })
+}
@@ -0,0 +1,53 @@
+use crate::series::sender_address;
+
+// Public suffixes of more than one label, from the ICANN section of the Public Suffix List
+// (https://publicsuffix.org), for the countries newsletters commonly come from. Anything
+// else is taken to have a one-label suffix (.com, .de, .io).
+const MULTI_LABEL: &[&str] = &[
+    "ac.jp", "ac.kr", "ac.nz", "ac.uk", "ac.za", "co.at", "co.id", "co.il", "co.in", "co.jp", "co.ke", "co.kr",
+    "co.nz", "co.th", "co.uk", "co.za", "com.ar", "com.au", "com.br", "com.cn", "com.co", "com.eg", "com.hk",
+    "com.mx", "com.my", "com.ng", "com.pe", "com.ph", "com.pk", "com.pl", "com.sa", "com.sg", "com.tr", "com.tw",
+    "com.ua", "com.vn", "edu.au", "gov.au", "gov.uk", "go.jp", "go.kr", "ltd.uk", "me.uk", "ne.jp", "ne.kr",
+    "net.au", "net.br", "net.cn", "net.nz", "or.jp", "or.kr", "org.au", "org.br", "org.cn", "org.nz", "org.uk",
+    "org.za", "plc.uk",
+];
+
+// The host part of the From header's address, lowercased
+pub fn host(from: &str) -> String {
+    let address = sender_address(from);
+    address.rsplit_once('@').map_or(address.as_str(), |(_, host)| host).trim_end_matches('.').to_string()
+}
+
+// The part of a host name a registrant controls: news.example.co.uk -> example.co.uk,
+// writer.substack.com -> substack.com. A bare suffix comes back as it is.
+pub fn registered(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_lowercase();
+    let labels: Vec<&str> = host.split('.').collect();
+    let suffix = if labels.len() > 2 && MULTI_LABEL.contains(&labels[labels.len() - 2..].join(".").as_str()) {
+        2
+    } else {
+        1
+    };
+    if labels.len() <= suffix {
+        return host;
+    }
+    labels[labels.len() - suffix - 1..].join(".")
+}
+
+// The sender's registered domain, for routes' `domains`, templates and the stats rollups
+pub fn of_sender(from: &str) -> String {
+    registered(&host(from))
+}
+
+pub fn is_public_suffix(domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_lowercase();
+    !domain.contains('.') || MULTI_LABEL.contains(&domain.as_str())
+}
+
+// A route's `domains` entry covers the domain itself and every host under it, so
+// "substack.com" takes in writer@news.substack.com
+pub fn matches(domain: &str, from: &str) -> bool {
+    let domain = domain.trim().trim_start_matches('@').trim_start_matches("*.").to_lowercase();
+    let host = host(from);
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
@@ -0,0 +1,20 @@
+use crate::config::Config;
+use regex::Regex;
+use tracing::warn;
+
+// Emoji for a subject from the `subject_emoji` table: the first pattern that matches, in
+// the order they are written. Applies to every email, whatever its route.
+pub fn for_subject<'a>(config: &'a Config, subject: &str) -> Option<&'a str> {
+    for (pattern, emoji) in config.subject_emoji.iter().flatten() {
+        let Some(emoji) = emoji.as_str() else {
+            warn!("subject_emoji value for {:?} must be a string", pattern);
+            continue;
+        };
+        match Regex::new(pattern) {
+            Ok(re) if re.is_match(subject) => return Some(emoji),
+            Ok(_) => {}
+            Err(e) => warn!("Invalid subject_emoji pattern {:?}: {}", pattern, e),
+        }
+    }
+    None
+}
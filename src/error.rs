@@ -0,0 +1,142 @@
+use crate::auth::AuthError;
+use crate::discord::{Failure, WebhookError};
+use crate::leader::LostLeadership;
+use std::net::TcpStream;
+use thiserror::Error;
+
+// Every fallible operation reports one of these kinds, so callers can decide on retries and
+// alerts by kind (a broken config won't fix itself on reconnect; a dropped IMAP connection
+// will) and logs say which part of the system failed.
+#[derive(Debug, Error)]
+pub enum Error {
+    // Connecting to, or talking with, the IMAP server
+    #[error("IMAP error: {0}")]
+    Imap(String),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error(transparent)]
+    LostLeadership(#[from] LostLeadership),
+    // Malformed input: an email, a JSON document, a signature or an encoded value
+    #[error("Parse error: {0}")]
+    Parse(String),
+    // A Discord webhook refused or failed a post
+    #[error(transparent)]
+    Delivery(#[from] WebhookError),
+    // Any other outgoing call: AI endpoints, shorteners, SMTP, AWS, the Discord bot API
+    #[error("Network error: {0}")]
+    Network(String),
+    // A setting is missing or invalid; retrying won't help until it's fixed
+    #[error("Configuration error: {0}")]
+    Config(String),
+    // The state store couldn't be read or written, or holds something unreadable
+    #[error("State store error: {0}")]
+    State(String),
+    // Local files other than the state store (the static site, ...)
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    pub fn imap(e: impl ToString) -> Error {
+        Error::Imap(e.to_string())
+    }
+
+    pub fn parse(e: impl ToString) -> Error {
+        Error::Parse(e.to_string())
+    }
+
+    pub fn network(e: impl ToString) -> Error {
+        Error::Network(e.to_string())
+    }
+
+    pub fn config(e: impl ToString) -> Error {
+        Error::Config(e.to_string())
+    }
+
+    pub fn state(e: impl ToString) -> Error {
+        Error::State(e.to_string())
+    }
+
+    // How a webhook post failed, for delivery errors
+    pub fn delivery_failure(&self) -> Option<Failure> {
+        match self {
+            Error::Delivery(e) => Some(e.failure()),
+            _ => None,
+        }
+    }
+
+    // Short label for logs and metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Imap(_) => "imap",
+            Error::Auth(_) => "auth",
+            Error::LostLeadership(_) => "leadership",
+            Error::Parse(_) => "parse",
+            Error::Delivery(_) => "delivery",
+            Error::Network(_) => "network",
+            Error::Config(_) => "config",
+            Error::State(_) => "state",
+            Error::Io(_) => "io",
+        }
+    }
+}
+
+impl From<imap::error::Error> for Error {
+    fn from(e: imap::error::Error) -> Error {
+        Error::imap(e)
+    }
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(e: native_tls::Error) -> Error {
+        Error::imap(e)
+    }
+}
+
+impl From<native_tls::HandshakeError<TcpStream>> for Error {
+    fn from(e: native_tls::HandshakeError<TcpStream>) -> Error {
+        Error::imap(e)
+    }
+}
+
+impl From<mailparse::MailParseError> for Error {
+    fn from(e: mailparse::MailParseError) -> Error {
+        Error::parse(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::parse(e)
+    }
+}
+
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Error {
+        Error::parse(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::network(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Error {
+        Error::state(e)
+    }
+}
+
+impl From<redis::RedisError> for Error {
+    fn from(e: redis::RedisError) -> Error {
+        Error::state(e)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Error {
+        Error::config(e)
+    }
+}
@@ -0,0 +1,97 @@
+use crate::config::EventsConfig;
+use crate::mail::Email;
+use crate::schema;
+use serde_json::{Value, json};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::sync::{Mutex, OnceLock};
+use tracing::{error, warn};
+
+// A JSON line per lifecycle step of every email (fetched, filtered, routed, delivered,
+// failed, deleted), for external tools to tail and automate on. Lines are appended to a
+// file and/or written to a Unix socket some other process listens on; events that can't
+// be written are dropped rather than holding up deliveries.
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+struct Sink {
+    path: Option<String>,
+    socket: Option<String>,
+    // None until connected, and again after the listener goes away
+    stream: Mutex<Option<UnixStream>>,
+}
+
+pub fn init(config: Option<&EventsConfig>) {
+    let Some(config) = config.filter(|c| c.path.is_some() || c.socket.is_some()) else {
+        return;
+    };
+    let _ = SINK.set(Sink {
+        path: config.path.clone(),
+        socket: config.socket.clone(),
+        stream: Mutex::new(None),
+    });
+}
+
+// An event about an email; `details` (an object) is merged into the line
+pub fn emit(event: &str, email: &Email, details: Value) {
+    crate::metrics::count("newsletter_events_total", Some(("event", event)));
+    if SINK.get().is_none() {
+        return;
+    }
+    let mut line = json!({
+        "schema_version": schema::VERSION,
+        "ts": chrono::Utc::now().to_rfc3339(),
+        "event": event,
+        "trace_id": email.trace_id,
+        "message_id": email.message_id,
+        "from": email.from,
+        "subject": email.subject,
+    });
+    if let (Some(line), Value::Object(details)) = (line.as_object_mut(), details) {
+        line.extend(details);
+    }
+    write(&line);
+}
+
+// An event known only by trace ID, e.g. once the message is removed from the mailbox
+pub fn emit_trace(event: &str, trace_id: &str, details: Value) {
+    if SINK.get().is_none() {
+        return;
+    }
+    let mut line = json!({
+        "schema_version": schema::VERSION,
+        "ts": chrono::Utc::now().to_rfc3339(),
+        "event": event,
+        "trace_id": trace_id,
+    });
+    if let (Some(line), Value::Object(details)) = (line.as_object_mut(), details) {
+        line.extend(details);
+    }
+    write(&line);
+}
+
+fn write(line: &Value) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let line = format!("{}\n", line);
+    if let Some(ref path) = sink.path {
+        let result = OpenOptions::new().create(true).append(true).open(path).and_then(|mut f| f.write_all(line.as_bytes()));
+        if let Err(e) = result {
+            error!("Failed to write event to {}: {}", path, e);
+        }
+    }
+    if let Some(ref socket) = sink.socket {
+        let mut stream = sink.stream.lock().unwrap();
+        if stream.is_none() {
+            // Nobody listening is normal; try again with the next event
+            *stream = UnixStream::connect(socket).ok();
+        }
+        if let Some(ref mut s) = *stream
+            && let Err(e) = s.write_all(line.as_bytes())
+        {
+            warn!("Event stream {} closed: {}", socket, e);
+            *stream = None;
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::config::{Config, Experiment, Route, ShortenerProvider, Variant, parse_duration};
+use crate::discord::Posted;
+use crate::error::Error;
+use crate::mail::Email;
+use crate::series::sender_address;
+use crate::state::StateStore;
+use crate::history::Status;
+use crate::{domains, history, reactions, shortener};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tracing::error;
+
+const PREFIX: &str = "experiment:";
+
+// A post made as part of an experiment, kept so its engagement can be looked up later
+#[derive(Serialize, Deserialize)]
+struct Exposure {
+    experiment: String,
+    variant: String,
+    route: String,
+    channel_id: String,
+    message_id: String,
+    // Short links in the post, whose clicks the shortener counts
+    links: Vec<String>,
+    posted_at: DateTime<Utc>,
+}
+
+// The variant an email is rendered as. The pick looks random across senders but always
+// falls the same way for a given sender, so readers see one newsletter consistently.
+pub fn assign<'a>(route: Option<&'a Route>, email: &Email) -> Option<(&'a Experiment, &'a Variant)> {
+    let experiment = route?.experiment.as_ref()?;
+    let digest = openssl::sha::sha256(format!("{}|{}", experiment.name, sender_address(&email.from)).as_bytes());
+    let n = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) as usize;
+    let variant = experiment.variants.get(n % experiment.variants.len().max(1))?;
+    Some((experiment, variant))
+}
+
+// Tags the delivered email with its variant in the history and remembers the post
+pub fn record(config: &Config, store: &dyn StateStore, route: Option<&Route>, email: &Email, posted: &Posted, payload: &Value) {
+    let Some((experiment, variant)) = assign(route, email) else {
+        return;
+    };
+    if let Err(e) = history::tag_variant(store, &email.trace_id, &variant.name) {
+        error!("Failed to tag the variant in the history: {}", e);
+    }
+    let exposure = Exposure {
+        experiment: experiment.name.clone(),
+        variant: variant.name.clone(),
+        route: route.map(|r| r.name.clone()).unwrap_or_default(),
+        channel_id: posted.channel_id.clone(),
+        message_id: posted.id.clone(),
+        links: config.shortener.as_ref().map(|_| shortener::links_in(config, &payload.to_string())).unwrap_or_default(),
+        posted_at: Utc::now(),
+    };
+    if let Err(e) = store.put_json(&format!("{}{}", PREFIX, email.trace_id), &exposure) {
+        error!("Failed to record the experiment post: {}", e);
+    }
+}
+
+#[derive(Default)]
+struct Tally {
+    posts: u64,
+    reactions: Option<u64>,
+    clicks: Option<u64>,
+}
+
+// `newsletter stats`: posts, reactions (needs discord_bot_token) and short link clicks
+// (needs [shortener]) per variant, for the experiments' posts since `since`. Then the
+// deliveries per sending domain, and with the built-in redirector, the clicks per sender.
+pub fn report(config: &Config, store: &dyn StateStore, since: &str) -> Result<(), Error> {
+    let since = Utc::now() - parse_duration(since).map_err(Error::Config)?;
+    report_variants(config, store, since)?;
+    report_domains(store, since)?;
+    if config.shortener.as_ref().is_some_and(|s| s.provider == ShortenerProvider::Builtin) {
+        let senders = shortener::clicks_by_sender(store)?;
+        println!();
+        println!("Link clicks by sender (all time):");
+        if senders.is_empty() {
+            println!("  none yet");
+        }
+        for (sender, clicks) in senders {
+            println!("  {:<40} {:>6}", sender, clicks);
+        }
+    }
+    Ok(())
+}
+
+// Delivered emails and link clicks (built-in redirector) rolled up by sending domain
+fn report_domains(store: &dyn StateStore, since: DateTime<Utc>) -> Result<(), Error> {
+    let mut domains: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for entry in history::since(store, since)? {
+        if matches!(entry.status, Status::Delivered | Status::Updated) {
+            let tally = domains.entry(domains::of_sender(&entry.from)).or_default();
+            tally.0 += 1;
+            tally.1 += entry.clicks;
+        }
+    }
+    if domains.is_empty() {
+        return Ok(());
+    }
+    let mut domains: Vec<(String, (u64, u64))> = domains.into_iter().collect();
+    domains.sort_by(|a, b| b.1.0.cmp(&a.1.0).then_with(|| a.0.cmp(&b.0)));
+    println!();
+    println!("Delivered by sender domain since {}:", since.format("%Y-%m-%d"));
+    println!("  {:<32} {:>6} {:>6}", "domain", "emails", "clicks");
+    for (domain, (emails, clicks)) in domains {
+        println!("  {:<32} {:>6} {:>6}", domain, emails, clicks);
+    }
+    Ok(())
+}
+
+fn report_variants(config: &Config, store: &dyn StateStore, since: DateTime<Utc>) -> Result<(), Error> {
+    let mut exposures: Vec<Exposure> = store
+        .entries(PREFIX)?
+        .into_iter()
+        .filter_map(|(_, raw)| serde_json::from_str::<Exposure>(&raw).ok())
+        .filter(|e| e.posted_at >= since)
+        .collect();
+    if exposures.is_empty() {
+        println!("No experiment posts since {}", since.to_rfc3339());
+        return Ok(());
+    }
+    exposures.sort_by_key(|e| e.posted_at);
+
+    let mut tallies: BTreeMap<(String, String), BTreeMap<String, Tally>> = BTreeMap::new();
+    for exposure in &exposures {
+        let tally = tallies
+            .entry((exposure.experiment.clone(), exposure.route.clone()))
+            .or_default()
+            .entry(exposure.variant.clone())
+            .or_default();
+        tally.posts += 1;
+        if let Some(ref token) = config.discord_bot_token {
+            match reactions::count(token, &exposure.channel_id, &exposure.message_id) {
+                Ok(n) => *tally.reactions.get_or_insert(0) += n,
+                Err(e) => error!("Failed to count reactions on {}: {}", exposure.message_id, e),
+            }
+        }
+        if let Some(ref shortener) = config.shortener {
+            for link in &exposure.links {
+                match shortener::clicks(shortener, store, link) {
+                    Ok(n) => *tally.clicks.get_or_insert(0) += n,
+                    Err(e) => error!("Failed to count clicks on {}: {}", link, e),
+                }
+            }
+        }
+    }
+
+    let per_post = |total: Option<u64>, posts: u64| match total {
+        Some(total) => format!("{:>6} ({:.1}/post)", total, total as f64 / posts as f64),
+        None => format!("{:>6}", "-"),
+    };
+    for ((experiment, route), variants) in tallies {
+        println!();
+        println!("Experiment {} (route {}) since {}:", experiment, route, since.format("%Y-%m-%d"));
+        println!("  {:<16} {:>6} {:>17} {:>17}", "variant", "posts", "reactions", "clicks");
+        for (variant, tally) in variants {
+            println!(
+                "  {:<16} {:>6} {:>17} {:>17}",
+                variant,
+                tally.posts,
+                per_post(tally.reactions, tally.posts),
+                per_post(tally.clicks, tally.posts)
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use crate::config::{AutoReplyAction, CategoryDelivery, Config, FilterAction, NotifierKind, WebhookStrategy};
+use crate::error::Error;
+use crate::mail::Email;
+use crate::{categories, domains, experiments, filters, loops, monitor, notices, pipeline, routes, series};
+use crate::snooze::Snooze;
+use crate::state::StateStore;
+
+// Walks the same rules the monitor applies, printing each one and whether it matched. The
+// verdict lines come from the real pipeline functions so the trace can't drift from them.
+pub fn explain(config: &Config, store: &dyn StateStore, email: &Email) -> Result<(), Error> {
+    println!("From:    {}", email.from);
+    println!("Subject: {}", email.subject);
+    if let Some(reason) = loops::detect(config, store, email)? {
+        println!();
+        println!("  => mail loop ({}): our own mail coming back, not processed", reason);
+        return Ok(());
+    }
+
+    println!();
+    println!("Filters (first match decides):");
+    let filters = config.filters.as_deref().unwrap_or_default();
+    if filters.is_empty() {
+        println!("  (none configured)");
+    }
+    for (i, filter) in filters.iter().enumerate() {
+        let rule = match (filter.field, filter.value.as_deref()) {
+            (Some(_), Some(value)) => format!("{:?}", value),
+            _ => "(any email)".to_string(),
+        };
+        println!("  [{}] {} {}: {}", i, filter.action.as_str(), rule, verdict(filters::matches(filter, email)));
+    }
+    match filters::first_match(config, email) {
+        Some((i, filter)) if filter.action == FilterAction::Ignore => {
+            println!("  => ignored by filter [{}]: the message would be deleted without posting", i);
+            return Ok(());
+        }
+        Some((i, filter)) => println!("  => filter [{}]: {}, the ignore lists are skipped", i, filter.action.as_str()),
+        None => println!("  => no filter matched"),
+    }
+
+    println!();
+    println!("Ignore rules:");
+    print_rules("ignored_senders", config.ignored_senders.as_deref(), &email.from);
+    print_rules("ignored_subjects", config.ignored_subjects.as_deref(), &email.subject);
+    if monitor::is_ignored(config, email) {
+        println!("  => ignored: the message would be deleted without posting");
+        return Ok(());
+    }
+    println!("  => not ignored");
+    if let Some(reason) = email.auto_reply
+        && config.auto_replies.unwrap_or_default() != AutoReplyAction::Forward
+    {
+        println!("  => bounce/autoreply ({}): handled per auto_replies, not routed", reason);
+        return Ok(());
+    }
+    if let Some(notice) = notices::detect(config, email) {
+        println!("  => mailbox notice ({}): escalated to the ops webhook, not routed", notice.kind);
+        return Ok(());
+    }
+
+    println!();
+    println!("Routes (first match wins):");
+    let routes = config.routes.as_deref().unwrap_or_default();
+    if routes.is_empty() {
+        println!("  (none configured)");
+    }
+    for route in routes {
+        println!("  {}:", route.name);
+        print_rules("  senders", route.senders.as_deref(), &email.from);
+        print_rules("  subjects", route.subjects.as_deref(), &email.subject);
+        for recipient in route.recipients.iter().flatten() {
+            println!("    recipients {:?}: {}", recipient, verdict(routes::recipient_matches(recipient, email)));
+        }
+        for domain in route.domains.iter().flatten() {
+            println!("    domains {:?}: {}", domain, verdict(domains::matches(domain, &email.from)));
+        }
+        for (key, pattern, text) in [
+            ("sender_regex", &route.sender_regex, &email.from),
+            ("subject_regex", &route.subject_regex, &email.subject),
+        ] {
+            if let Some(pattern) = pattern {
+                println!("    {} {:?}: {}", key, pattern, verdict(routes::regex_matches(route, key, pattern, text)));
+            }
+        }
+    }
+    match routes::find(config, email) {
+        Some(route) => {
+            println!("  => route {}", route.name);
+            if let Some(snooze) = store.get_json::<Snooze>(&format!("snooze:{}", route.name))?
+                && snooze.until > chrono::Utc::now()
+            {
+                println!("  => snoozed until {}: the message would be held", snooze.until.to_rfc3339());
+                return Ok(());
+            }
+            if let Some(category) = categories::find(config, Some(route)) {
+                match category.delivery {
+                    CategoryDelivery::Immediate => println!("  => category {}: posted immediately", category.name),
+                    CategoryDelivery::Digest => {
+                        println!(
+                            "  => category {}: collected for the digest at {}",
+                            category.name,
+                            category.schedule.as_deref().unwrap_or_default()
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        None => println!("  => no route matched"),
+    }
+
+    println!();
+    let route = routes::find(config, email);
+    if let Some(channel_id) = route.and_then(|r| r.channel_id.as_deref()) {
+        println!("Renderer: Discord embed -> bot post to channel {}", channel_id);
+    } else if config.notifier(route) == NotifierKind::Telegram
+        && let Some(ref telegram) = config.telegram
+    {
+        let chat_id = route.and_then(|r| r.chat_id.as_deref()).unwrap_or(&telegram.chat_id);
+        println!("Renderer: Telegram MarkdownV2 -> chat {}", chat_id);
+    } else if config.notifier(route) == NotifierKind::Zulip
+        && let Some(ref zulip) = config.zulip
+    {
+        let stream = route.and_then(|r| r.zulip_stream.as_deref()).unwrap_or(&zulip.stream);
+        let topic = route.and_then(|r| r.zulip_topic.as_deref()).or(zulip.topic.as_deref()).unwrap_or("{sender}");
+        println!("Renderer: Markdown -> Zulip stream {}, topic {}", stream, topic);
+    } else if config.notifier(route) == NotifierKind::Discourse
+        && let Some(ref discourse) = config.discourse
+    {
+        let category = route.and_then(|r| r.discourse_category).unwrap_or(discourse.category);
+        println!("Renderer: Markdown -> new Discourse topic in category {}", category);
+    } else if config.notifier(route) == NotifierKind::Matrix
+        && let Some(ref matrix) = config.matrix
+    {
+        let room_id = route.and_then(|r| r.matrix_room.as_deref()).unwrap_or(&matrix.room_id);
+        println!("Renderer: HTML -> Matrix room {}", room_id);
+    } else {
+        match routes::find(config, email).and_then(|r| r.webhooks.as_ref().filter(|w| !w.is_empty()).map(|w| (r, w))) {
+            Some((route, webhooks)) => println!(
+                "Renderer: Discord embed -> {} route webhook(s), {}",
+                webhooks.len(),
+                match route.strategy.unwrap_or_default() {
+                    WebhookStrategy::Failover => "failover",
+                    WebhookStrategy::RoundRobin => "round robin",
+                }
+            ),
+            None => println!("Renderer: Discord embed -> discord_webhook_url"),
+        }
+    }
+    let stages: Vec<&str> = pipeline::stages(config, routes::find(config, email), email).iter().map(|s| s.as_str()).collect();
+    println!("Pipeline: {}", stages.join(" -> "));
+    if let Some((experiment, variant)) = experiments::assign(routes::find(config, email), email) {
+        println!("Variant:  {} (experiment {})", variant.name, experiment.name);
+    }
+    if config.series.as_ref().is_some_and(|s| s.enabled) {
+        match series::series_key(email) {
+            Some(key) => println!("Series:   {}", key),
+            None => println!("Series:   (no issue number or date in the subject)"),
+        }
+    }
+    Ok(())
+}
+
+fn print_rules(label: &str, patterns: Option<&[String]>, text: &str) {
+    let patterns = patterns.unwrap_or_default();
+    if patterns.is_empty() {
+        println!("  {}: (none)", label);
+    }
+    for pattern in patterns {
+        println!("  {} {:?}: {}", label, pattern, verdict(text.contains(pattern.as_str())));
+    }
+}
+
+fn verdict(matched: bool) -> &'static str {
+    if matched { "MATCH" } else { "no match" }
+}
@@ -0,0 +1,44 @@
+use crate::config::{Config, Filter, FilterField, FilterMatch};
+use crate::mail::Email;
+use crate::series::sender_address;
+use regex::Regex;
+use tracing::warn;
+
+// The first of the [[filters]] the email matches, with its index. Its action decides:
+// `ignore` drops the email, `deliver` posts it past the ignore lists, and `route` sends it to
+// the named route. When none matches, ignored_senders / ignored_subjects and the routes'
+// own rules apply as before.
+pub fn first_match<'a>(config: &'a Config, email: &Email) -> Option<(usize, &'a Filter)> {
+    config.filters.iter().flatten().enumerate().find(|(_, filter)| matches(filter, email))
+}
+
+// A filter without a field matches every email, for a catch-all at the end of the list
+pub fn matches(filter: &Filter, email: &Email) -> bool {
+    let (Some(field), Some(value)) = (filter.field, filter.value.as_deref()) else {
+        return true;
+    };
+    let values: Vec<&str> = match field {
+        FilterField::From => vec![email.from.as_str()],
+        FilterField::To => email.recipients.iter().map(String::as_str).collect(),
+        FilterField::Subject => vec![email.subject.as_str()],
+        FilterField::ListId => email.list.id.as_deref().into_iter().collect(),
+        FilterField::Body => vec![email.body.as_str()],
+    };
+    match filter.matching {
+        FilterMatch::Contains => {
+            let value = value.to_lowercase();
+            values.iter().any(|v| v.to_lowercase().contains(&value))
+        }
+        // The whole header, or the address in its angle brackets
+        FilterMatch::Exact => {
+            values.iter().any(|v| v.trim().eq_ignore_ascii_case(value) || sender_address(v) == value.to_lowercase())
+        }
+        FilterMatch::Regex => match Regex::new(value) {
+            Ok(re) => values.iter().any(|v| re.is_match(v)),
+            Err(e) => {
+                warn!("Invalid filter regex {:?}: {}", value, e);
+                false
+            }
+        },
+    }
+}
@@ -0,0 +1,127 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::ops;
+use native_tls::TlsStream;
+use regex::Regex;
+use std::net::TcpStream;
+use std::sync::LazyLock;
+use tracing::info;
+
+pub type Session = imap::Session<TlsStream<TcpStream>>;
+
+// First personal namespace: `* NAMESPACE (("INBOX." ".")) NIL NIL`
+static NAMESPACE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\* NAMESPACE \(\("([^"]*)" (?:"((?:[^"\\]|\\.)*)"|NIL)\)"#).unwrap());
+
+// How the server lays out folders. Configured folder paths always use `/`, and are mapped
+// onto the server's hierarchy delimiter and personal namespace prefix (Dovecot and Courier
+// commonly use `.` and `INBOX.`).
+pub struct Folders {
+    prefix: String,
+    delimiter: String,
+}
+
+impl Folders {
+    pub fn discover(session: &mut Session) -> Result<Folders, Error> {
+        let capabilities = session.capabilities()?;
+        let namespace = if capabilities.has_str("NAMESPACE") {
+            let response = session.run_command_and_read_response("NAMESPACE")?;
+            NAMESPACE.captures(&String::from_utf8_lossy(&response)).map(|c| {
+                let delimiter = c.get(2).map(|d| d.as_str().replace("\\\\", "\\"));
+                (c[1].to_string(), delimiter)
+            })
+        } else {
+            None
+        };
+        let (prefix, delimiter) = match namespace {
+            Some((prefix, Some(delimiter))) => (prefix, delimiter),
+            // LIST "" "" returns just the hierarchy delimiter
+            other => {
+                let names = session.list(Some(""), Some("\"\""))?;
+                let delimiter = names.iter().find_map(|n| n.delimiter()).unwrap_or("/").to_string();
+                (other.map(|(prefix, _)| prefix).unwrap_or_default(), delimiter)
+            }
+        };
+        info!("IMAP folders: namespace prefix {:?}, delimiter {:?}", prefix, delimiter);
+        Ok(Folders { prefix, delimiter })
+    }
+
+    // "Newsletter/Processed" -> "INBOX.Newsletter.Processed" on a server with the `INBOX.`
+    // namespace. Paths already under the prefix, and INBOX itself, are left alone.
+    pub fn resolve(&self, path: &str) -> String {
+        let path = path.trim_matches('/');
+        if path.eq_ignore_ascii_case("INBOX") {
+            return "INBOX".to_string();
+        }
+        let path = path.replace('/', &self.delimiter);
+        if self.prefix.is_empty() || path.starts_with(&self.prefix) {
+            path
+        } else {
+            format!("{}{}", self.prefix, path)
+        }
+    }
+}
+
+// Resolves a configured folder and creates it if it doesn't exist yet. When the server
+// refuses to create it, `fallback` is tried the same way, with an ops warning; with no usable
+// folder at all the caller carries on without one rather than failing every cycle.
+pub fn ensure(
+    config: &Config,
+    session: &mut Session,
+    folders: &Folders,
+    path: &str,
+    fallback: Option<&str>,
+) -> Option<String> {
+    let folder = folders.resolve(path);
+    let error = match create_if_missing(session, &folder) {
+        Ok(()) => return Some(folder),
+        Err(e) => e,
+    };
+    let Some(fallback) = fallback else {
+        ops::alert_once(
+            config,
+            &format!("folder:{}", folder),
+            "IMAP folder unavailable",
+            &format!("Could not create {} ({}). Messages will be deleted instead of moved there.", folder, error),
+        );
+        return None;
+    };
+    let alternative = folders.resolve(fallback);
+    match create_if_missing(session, &alternative) {
+        Ok(()) => {
+            ops::alert_once(
+                config,
+                &format!("folder:{}", folder),
+                "IMAP folder unavailable",
+                &format!("Could not create {} ({}). Using {} instead.", folder, error, alternative),
+            );
+            Some(alternative)
+        }
+        Err(e) => {
+            ops::alert_once(
+                config,
+                &format!("folder:{}", folder),
+                "IMAP folder unavailable",
+                &format!(
+                    "Could not create {} ({}) nor the fallback {} ({}). Messages will be deleted instead.",
+                    folder, error, alternative, e
+                ),
+            );
+            None
+        }
+    }
+}
+
+fn create_if_missing(session: &mut Session, folder: &str) -> Result<(), Error> {
+    if !session.list(Some(""), Some(&quote(folder)))?.is_empty() {
+        return Ok(());
+    }
+    session.create(folder)?;
+    info!("Created IMAP folder {}", folder);
+    Ok(())
+}
+
+// Mailbox names as IMAP quoted strings, for commands that don't quote them themselves
+pub fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,31 @@
+use crate::mail::Email;
+use regex::Regex;
+use std::sync::LazyLock;
+use tracing::debug;
+
+// Boilerplate that ends most newsletters: unsubscribe and preference links, "you are
+// receiving this because", copyright and postal address lines.
+static FOOTER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)(unsubscribe|opt[ -]out|manage (your )?(preferences|subscription)|update your preferences|you('re| are) receiving this|no longer wish to receive|view (this email )?in (your |a )?browser|sent to .+@|©|\(c\) \d{4}|copyright|all rights reserved)",
+    )
+    .unwrap()
+});
+
+// Drops trailing paragraphs that look like footer boilerplate. Only the end of the body is
+// touched, so an "unsubscribe" mentioned in the content itself stays.
+pub fn strip(email: &Email) -> Email {
+    let mut stripped = email.clone();
+    let mut paragraphs: Vec<&str> = email.body.trim_end().split("\n\n").collect();
+    let total = paragraphs.len();
+    while paragraphs.len() > 1
+        && paragraphs.last().is_some_and(|p| p.trim().is_empty() || FOOTER.is_match(p))
+    {
+        paragraphs.pop();
+    }
+    if paragraphs.len() < total {
+        debug!("Stripped {} footer paragraph(s)", total - paragraphs.len());
+        stripped.body = paragraphs.join("\n\n");
+    }
+    stripped
+}
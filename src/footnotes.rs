@@ -0,0 +1,88 @@
+use crate::mail::clean_body;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+// `[1]: https://...` lines, as html2text lists the links of an HTML body
+static DEFINITION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)^\[(\d+)\]:[ \t]*(\S+)[ \t]*$").unwrap());
+// `[text][1]` (a reference to a definition) or `[text](https://...)`
+static LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[([^\]\n]+)\](?:\[(\d+)\]|\((https?://[^)\s]+)\))").unwrap());
+
+// Embed descriptions are capped at 4096 chars
+const MAX_DESCRIPTION: usize = 4096;
+
+// Rewrites the body's links as numbered references, `text[1]`, and returns the URLs in
+// order of first appearance. The same URL keeps one number.
+pub fn extract(body: &str) -> (String, Vec<String>) {
+    let definitions: HashMap<&str, &str> =
+        DEFINITION.captures_iter(body).map(|c| (c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str())).collect();
+    let text = DEFINITION.replace_all(body, "");
+    let mut urls: Vec<String> = Vec::new();
+    let text = LINK.replace_all(&text, |caps: &regex::Captures| {
+        let url = match (caps.get(2), caps.get(3)) {
+            (Some(n), _) => definitions.get(n.as_str()).copied(),
+            (_, Some(url)) => Some(url.as_str()),
+            _ => None,
+        };
+        let Some(url) = url else {
+            return caps[0].to_string();
+        };
+        let n = match urls.iter().position(|u| u == url) {
+            Some(i) => i + 1,
+            None => {
+                urls.push(url.to_string());
+                urls.len()
+            }
+        };
+        format!("{}[{}]", &caps[1], n)
+    });
+    (clean_body(&text), urls)
+}
+
+// The footnotes for the references left in `text` (a long body is cut before the list is
+// added), within `room` characters
+pub fn list(urls: &[String], text: &str, room: usize) -> String {
+    let mut list = String::new();
+    for (i, url) in urls.iter().enumerate() {
+        let n = i + 1;
+        if !text.contains(&format!("[{}]", n)) {
+            continue;
+        }
+        let line = format!("\n[{}]: {}", n, url);
+        if list.chars().count() + line.chars().count() > room {
+            break;
+        }
+        list.push_str(&line);
+    }
+    list
+}
+
+// Adds the footnotes at the bottom of the embed's description
+pub fn append(payload: &mut Value, urls: &[String]) {
+    let Some(description) = payload["embeds"][0]["description"].as_str() else {
+        return;
+    };
+    let room = MAX_DESCRIPTION.saturating_sub(description.chars().count() + 1);
+    let list = list(urls, description, room);
+    if !list.is_empty() {
+        payload["embeds"][0]["description"] = Value::String(format!("{}\n{}", description, list));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn footnotes_replace_link_references() {
+        let email = crate::mail::Email::parse(crate::samples::find("html-digest").unwrap()).unwrap();
+        let (body, urls) = super::extract(&email.body);
+        assert!(body.contains("* Stocks edge higher as inflation cools[1]"));
+        assert!(!body.contains("[1]: "));
+        assert_eq!(urls[4], "https://morning.example/unsub");
+        let mut payload = serde_json::json!({ "embeds": [{ "description": body }] });
+        super::append(&mut payload, &urls);
+        let description = payload["embeds"][0]["description"].as_str().unwrap();
+        assert!(description.ends_with("\n\n[1]: https://morning.example/r/1\n[2]: https://morning.example/r/2\n[3]: https://morning.example/r/3\n[4]: https://morning.example/r/4\n[5]: https://morning.example/unsub"));
+    }
+}
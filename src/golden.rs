@@ -0,0 +1,97 @@
+// Golden-file tests for the embed builder: each bundled sample is rendered and compared
+// with samples/golden/<name>.json (and its Slack and Telegram translations with
+// <name>.slack.json and <name>.telegram.json), so any change to a payload shows up in the diff.
+// After an intended change, regenerate the files with `UPDATE_GOLDEN=1 cargo test`.
+use crate::discord;
+use crate::mail::Email;
+use crate::samples::SAMPLES;
+use crate::{slack, telegram};
+use chrono::DateTime;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+fn render(raw: &[u8]) -> Value {
+    let email = Email::parse(raw).unwrap();
+    // A fixed clock, so the embed timestamp doesn't change between runs
+    let now = DateTime::from_timestamp(1_790_000_000, 0).unwrap();
+    discord::embed_payload(&email, None, None, now)
+}
+
+#[test]
+fn samples_match_golden_payloads() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples/golden");
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut mismatched = Vec::new();
+    let cases = SAMPLES.iter().flat_map(|(name, raw)| {
+        let payload = render(raw);
+        let slack = slack::message(&payload);
+        let telegram = serde_json::json!(telegram::messages(&payload));
+        [(name.to_string(), payload), (format!("{}.slack", name), slack), (format!("{}.telegram", name), telegram)]
+    });
+    for (name, actual) in cases {
+        let path = dir.join(format!("{}.json", name));
+        if update {
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(&path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+            continue;
+        }
+        let expected: Value = serde_json::from_str(
+            &fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {} (run with UPDATE_GOLDEN=1)", path.display(), e)),
+        )
+        .unwrap();
+        if actual != expected {
+            eprintln!("{} differs:\n{}", name, serde_json::to_string_pretty(&actual).unwrap());
+            mismatched.push(name);
+        }
+    }
+    assert!(mismatched.is_empty(), "payloads changed for {:?}", mismatched);
+}
+
+#[test]
+fn long_bodies_are_cut_on_a_character_boundary() {
+    let mut email = Email::parse(crate::samples::find("cjk").unwrap()).unwrap();
+    email.body = "뉴스".repeat(1000);
+    let payload = discord::embed_payload(&email, None, None, DateTime::UNIX_EPOCH);
+    let description = payload["embeds"][0]["description"].as_str().unwrap();
+    assert!(description.ends_with("..."));
+    assert!(description.len() <= 1503);
+}
+
+#[test]
+fn long_subjects_are_cut_and_kept_in_a_field() {
+    let mut email = Email::parse(crate::samples::find("cjk").unwrap()).unwrap();
+    email.subject = format!("\u{202E}{}", "Weekly market recap ".repeat(20));
+    let payload = discord::embed_payload(&email, None, None, DateTime::UNIX_EPOCH);
+    let title = payload["embeds"][0]["title"].as_str().unwrap();
+    // Cut between words, with the override gone
+    let kept = title.strip_suffix('…').unwrap();
+    assert!(email.subject[3..].starts_with(&format!("{} ", kept)));
+    assert!(title.chars().count() <= 256);
+    assert_eq!(payload["embeds"][0]["fields"][0]["name"], "Subject");
+    assert_eq!(payload["embeds"][0]["fields"][0]["value"], email.subject[3..].trim());
+}
+
+#[test]
+fn telegram_splits_long_text_into_messages() {
+    let line = "Read more at example.com (it's free!)";
+    let payload = serde_json::json!({
+        "embeds": [{ "title": "Weekly", "description": vec![line; 300].join("\n") }]
+    });
+    let messages = telegram::messages(&payload);
+    assert!(messages.len() > 1);
+    for message in &messages {
+        assert!(message.encode_utf16().count() <= 4096);
+    }
+    assert!(messages[1].starts_with("Read more at example\\.com \\(it's free\\!\\)"));
+}
+
+#[test]
+fn attachments_over_the_upload_limit_are_listed() {
+    let mut email = Email::parse(crate::samples::find("attachments").unwrap()).unwrap();
+    email.attachments[0].data = vec![0; 11 * 1024 * 1024];
+    let payload = discord::embed_payload(&email, None, None, DateTime::UNIX_EPOCH);
+    let uploaded: Vec<&str> = payload["_files"].as_array().unwrap().iter().map(|f| f["filename"].as_str().unwrap()).collect();
+    assert_eq!(uploaded, ["growth-chart.png", "subscribers.csv"]);
+    assert_eq!(payload["embeds"][0]["fields"][0]["value"], "q3-update.pdf (11264 KB)");
+}
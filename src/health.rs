@@ -0,0 +1,97 @@
+use crate::config::{Config, Protocol};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// What each account's worker has been up to, for the probes (GET /healthz and /readyz on
+// [server]) and the last-poll gauge in the metrics
+#[derive(Default)]
+struct Account {
+    logged_in: bool,
+    last_cycle: Option<Instant>,
+    // Waiting for leadership, which is healthy but doesn't check mail
+    standby: bool,
+}
+
+static ACCOUNTS: Mutex<BTreeMap<String, Account>> = Mutex::new(BTreeMap::new());
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+fn name(config: &Config) -> String {
+    config.account.clone().unwrap_or_else(|| "default".to_string())
+}
+
+fn update(config: &Config, f: impl FnOnce(&mut Account)) {
+    f(ACCOUNTS.lock().unwrap().entry(name(config)).or_default());
+}
+
+// Called for each account at startup, so one that never gets going shows up as unhealthy
+pub fn register(config: &Config) {
+    STARTED.get_or_init(Instant::now);
+    update(config, |_| {});
+}
+
+pub fn logged_in(config: &Config) {
+    update(config, |a| a.logged_in = true);
+}
+
+// A mailbox check went through
+pub fn polled(config: &Config) {
+    update(config, |a| a.last_cycle = Some(Instant::now()));
+}
+
+pub fn standby(config: &Config, standby: bool) {
+    update(config, |a| a.standby = standby);
+}
+
+// Account -> when its last mailbox check completed
+pub fn polls() -> Vec<(String, Instant)> {
+    let accounts = ACCOUNTS.lock().unwrap();
+    accounts.iter().filter_map(|(name, a)| Some((name.clone(), a.last_cycle?))).collect()
+}
+
+// Live while every account has finished a mailbox check within `server.health_max_age_seconds`
+// (by default a little over the IDLE renewal period or POP3 polling interval, the longest a
+// healthy worker goes without one). A wedged IMAP session stops the checks, so the probe
+// fails and the orchestrator restarts the process.
+pub fn live(config: &Config) -> (bool, Value) {
+    let max_age = Duration::from_secs(
+        config
+            .server
+            .as_ref()
+            .and_then(|s| s.health_max_age_seconds)
+            .unwrap_or_else(|| match config.protocol() {
+                Protocol::Imap | Protocol::Jmap => config.idle.as_ref().map_or(10, |i| i.renew_minutes()) * 60 + 120,
+                Protocol::Pop3 => config.pop3_poll_seconds() + 120,
+            }),
+    );
+    let uptime = STARTED.get().map_or(Duration::ZERO, Instant::elapsed);
+    let mut ok = true;
+    let mut details = serde_json::Map::new();
+    for (name, account) in ACCOUNTS.lock().unwrap().iter() {
+        let age = account.last_cycle.map(|t| t.elapsed());
+        let status = match age {
+            _ if account.standby => "standby",
+            Some(age) if age <= max_age => "ok",
+            None if uptime <= max_age => "starting",
+            _ => {
+                ok = false;
+                "stale"
+            }
+        };
+        details.insert(
+            name.clone(),
+            json!({ "status": status, "seconds_since_last_cycle": age.map(|a| a.as_secs()) }),
+        );
+    }
+    (ok, json!({ "ok": ok, "max_age_seconds": max_age.as_secs(), "accounts": details }))
+}
+
+// Ready once every account has logged in at least once
+pub fn ready() -> (bool, Value) {
+    let accounts = ACCOUNTS.lock().unwrap();
+    let ok = accounts.values().all(|a| a.logged_in);
+    let details: serde_json::Map<String, Value> =
+        accounts.iter().map(|(name, a)| (name.clone(), json!({ "logged_in": a.logged_in }))).collect();
+    (ok, json!({ "ok": ok, "accounts": details }))
+}
@@ -0,0 +1,131 @@
+use crate::error::Error;
+use crate::mail::Email;
+use crate::state::StateStore;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+const PREFIX: &str = "history:";
+
+// What happened to each message, keyed by trace ID. Re-processing a message (e.g. after a
+// failed delivery) updates its entry rather than adding a new one.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub trace_id: String,
+    pub message_id: Option<String>,
+    pub subject: String,
+    pub from: String,
+    pub first_seen: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub attempts: u32,
+    pub status: Status,
+    pub detail: Option<String>,
+    // The experiment variant the email was rendered as, if its route runs one
+    #[serde(default)]
+    pub variant: Option<String>,
+    // Clicks on the email's links through the built-in redirector
+    #[serde(default)]
+    pub clicks: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Delivered,
+    Ignored,
+    AutoReply,
+    // Our own mail coming back
+    Looped,
+    Notice,
+    Snoozed,
+    Digested,
+    Duplicate,
+    Updated,
+    Failed,
+    DeadLettered,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Delivered => "delivered",
+            Status::Ignored => "ignored",
+            Status::AutoReply => "auto_reply",
+            Status::Looped => "looped",
+            Status::Notice => "notice",
+            Status::Snoozed => "snoozed",
+            Status::Digested => "digested",
+            Status::Duplicate => "duplicate",
+            Status::Updated => "updated",
+            Status::Failed => "failed",
+            Status::DeadLettered => "dead_lettered",
+        }
+    }
+}
+
+pub fn record(store: &dyn StateStore, email: &Email, status: Status, detail: Option<String>) {
+    crate::otel::count("newsletter.messages", Some(("status", status.as_str())));
+    crate::metrics::count("newsletter_emails_total", Some(("status", status.as_str())));
+    if let Err(e) = try_record(store, email, status, detail) {
+        error!("Failed to record history: {}", e);
+    }
+}
+
+// Entries last updated since `since`
+pub fn since(store: &dyn StateStore, since: DateTime<Utc>) -> Result<Vec<Entry>, Error> {
+    Ok(store
+        .entries(PREFIX)?
+        .into_iter()
+        .filter_map(|(_, raw)| serde_json::from_str::<Entry>(&raw).ok())
+        .filter(|e| e.updated >= since)
+        .collect())
+}
+
+pub fn get(store: &dyn StateStore, trace_id: &str) -> Result<Option<Entry>, Error> {
+    store.get_json(&format!("{}{}", PREFIX, trace_id))
+}
+
+fn try_record(
+    store: &dyn StateStore,
+    email: &Email,
+    status: Status,
+    detail: Option<String>,
+) -> Result<(), Error> {
+    let key = format!("{}{}", PREFIX, email.trace_id);
+    let now = Utc::now();
+    let previous = store.get_json::<Entry>(&key)?;
+    let entry = Entry {
+        trace_id: email.trace_id.clone(),
+        message_id: email.message_id.clone(),
+        subject: email.subject.clone(),
+        from: email.from.clone(),
+        first_seen: previous.as_ref().map(|p| p.first_seen).unwrap_or(now),
+        updated: now,
+        attempts: previous.as_ref().map(|p| p.attempts).unwrap_or(0) + 1,
+        status,
+        detail,
+        clicks: previous.as_ref().map_or(0, |p| p.clicks),
+        variant: previous.and_then(|p| p.variant),
+    };
+    store.put_json(&key, &entry)
+}
+
+// Notes the experiment variant on the email's entry
+pub fn tag_variant(store: &dyn StateStore, trace_id: &str, variant: &str) -> Result<(), Error> {
+    let key = format!("{}{}", PREFIX, trace_id);
+    let Some(mut entry) = store.get_json::<Entry>(&key)? else {
+        return Ok(());
+    };
+    entry.variant = Some(variant.to_string());
+    store.put_json(&key, &entry)
+}
+
+// Counts a click on one of the email's links
+pub fn add_click(store: &dyn StateStore, trace_id: &str) -> Result<(), Error> {
+    let key = format!("{}{}", PREFIX, trace_id);
+    let Some(mut entry) = store.get_json::<Entry>(&key)? else {
+        return Ok(());
+    };
+    entry.clicks += 1;
+    store.put_json(&key, &entry)
+}
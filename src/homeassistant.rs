@@ -0,0 +1,38 @@
+use crate::archive;
+use crate::config::{Config, Route};
+use crate::error::Error;
+use crate::mail::Email;
+use serde_json::json;
+use tracing::error;
+
+// Sends the subject as a Home Assistant notification (usually a push to the companion app)
+// through the REST API's notify service, so it goes wherever HA's notifications already go.
+// The route's `ha_service` picks the device, else home_assistant.service; with neither,
+// nothing is sent. Failures are logged and don't hold up the delivery.
+pub fn notify(config: &Config, route: Option<&Route>, email: &Email) {
+    let Some(ref ha) = config.home_assistant else {
+        return;
+    };
+    let Some(service) = route.and_then(|r| r.ha_service.as_deref()).or(ha.service.as_deref()) else {
+        return;
+    };
+    let mut body = json!({ "title": email.subject, "message": email.from });
+    // Tapping the notification opens the archived copy when there is one
+    if let Some(url) = archive::url(config, &email.trace_id) {
+        body["data"] = json!({ "url": url, "clickAction": url });
+    }
+    let service = service.trim_start_matches("notify.");
+    let result = crate::http::client()
+        .post(format!("{}/api/services/notify/{}", ha.url.trim_end_matches('/'), service))
+        .bearer_auth(&ha.token)
+        .json(&body)
+        .send()
+        .map_err(Error::from)
+        .and_then(|response| match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(Error::Network(format!("Status {}: {}", status, response.text().unwrap_or_default()))),
+        });
+    if let Err(e) = result {
+        error!("Failed to notify Home Assistant ({}): {}", service, e);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::config::HttpConfig;
+use crate::error::Error;
+use reqwest::blocking::Client;
+use reqwest::header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Shared outbound HTTP layer. Everything that talks to third-party sites goes through
+// `get`, which keeps at most `max_per_domain` requests in flight per host, spaces requests
+// to the same host by `min_interval_ms`, and honours ETag / Cache-Control: max-age.
+static HTTP: OnceLock<Http> = OnceLock::new();
+
+struct Http {
+    client: Client,
+    config: HttpConfig,
+    domains: Mutex<HashMap<String, DomainSlot>>,
+    released: Condvar,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+#[derive(Default)]
+struct DomainSlot {
+    in_flight: usize,
+    last_request: Option<Instant>,
+}
+
+struct CacheEntry {
+    body: Arc<Vec<u8>>,
+    etag: Option<String>,
+    fresh_until: Instant,
+}
+
+pub fn init(config: Option<&HttpConfig>) {
+    let _ = HTTP.set(build(config.cloned().unwrap_or_default()));
+}
+
+fn http() -> &'static Http {
+    HTTP.get_or_init(|| build(HttpConfig::default()))
+}
+
+fn build(config: HttpConfig) -> Http {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(config.timeout_seconds()))
+        .user_agent(concat!("newsletter/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("Failed to build HTTP client");
+    Http {
+        client,
+        config,
+        domains: Mutex::new(HashMap::new()),
+        released: Condvar::new(),
+        cache: Mutex::new(HashMap::new()),
+    }
+}
+
+// The client carrying the global timeout policy, for requests that don't need the
+// per-domain limits (webhook deliveries to our own endpoints).
+pub fn client() -> &'static Client {
+    &http().client
+}
+
+pub fn get(url: &str) -> Result<Arc<Vec<u8>>, Error> {
+    let http = http();
+    let cached_etag = {
+        let cache = http.cache.lock().unwrap();
+        match cache.get(url) {
+            Some(entry) if entry.fresh_until > Instant::now() => return Ok(entry.body.clone()),
+            Some(entry) => entry.etag.clone(),
+            None => None,
+        }
+    };
+
+    let host = reqwest::Url::parse(url).map_err(Error::parse)?.host_str().unwrap_or_default().to_string();
+    let _slot = http.acquire(&host);
+
+    let mut request = http.client.get(url);
+    if let Some(ref etag) = cached_etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    let response = request.send()?;
+    let max_age = response
+        .headers()
+        .get(CACHE_CONTROL)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_max_age)
+        .unwrap_or(Duration::ZERO);
+
+    let mut cache = http.cache.lock().unwrap();
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED
+        && let Some(entry) = cache.get_mut(url)
+    {
+        entry.fresh_until = Instant::now() + max_age;
+        return Ok(entry.body.clone());
+    }
+    if !response.status().is_success() {
+        return Err(Error::Network(format!("Status {} fetching {}", response.status(), url)));
+    }
+
+    let etag = response.headers().get(ETAG).and_then(|v| v.to_str().ok()).map(String::from);
+    let body = Arc::new(response.bytes()?.to_vec());
+    if etag.is_some() || !max_age.is_zero() {
+        if cache.len() >= http.config.cache_entries() {
+            cache.retain(|_, entry| entry.fresh_until > Instant::now());
+        }
+        if cache.len() < http.config.cache_entries() {
+            cache.insert(
+                url.to_string(),
+                CacheEntry {
+                    body: body.clone(),
+                    etag,
+                    fresh_until: Instant::now() + max_age,
+                },
+            );
+        }
+    }
+    Ok(body)
+}
+
+fn parse_max_age(header: &str) -> Option<Duration> {
+    if header.contains("no-store") || header.contains("no-cache") {
+        return None;
+    }
+    header
+        .split(',')
+        .filter_map(|directive| directive.trim().strip_prefix("max-age="))
+        .find_map(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+}
+
+struct SlotGuard<'a> {
+    http: &'a Http,
+    host: String,
+}
+
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        let mut domains = self.http.domains.lock().unwrap();
+        if let Some(slot) = domains.get_mut(&self.host) {
+            slot.in_flight -= 1;
+        }
+        self.http.released.notify_all();
+    }
+}
+
+impl Http {
+    fn acquire(&self, host: &str) -> SlotGuard<'_> {
+        let min_interval = Duration::from_millis(self.config.min_interval_ms());
+        let mut domains = self.domains.lock().unwrap();
+        loop {
+            let slot = domains.entry(host.to_string()).or_default();
+            let wait = slot
+                .last_request
+                .map(|last| min_interval.saturating_sub(last.elapsed()))
+                .unwrap_or(Duration::ZERO);
+            if slot.in_flight < self.config.max_per_domain() && wait.is_zero() {
+                slot.in_flight += 1;
+                slot.last_request = Some(Instant::now());
+                return SlotGuard {
+                    http: self,
+                    host: host.to_string(),
+                };
+            }
+            if wait.is_zero() {
+                domains = self.released.wait(domains).unwrap();
+            } else {
+                drop(domains);
+                thread::sleep(wait);
+                domains = self.domains.lock().unwrap();
+            }
+        }
+    }
+}
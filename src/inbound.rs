@@ -0,0 +1,127 @@
+use crate::config::Config;
+use crate::crypto::{hex, hmac_sha256, secure_eq};
+use crate::error::Error;
+use crate::ingest::{self, IncomingEmail};
+use crate::mail::Email;
+use crate::server::{HttpResponse, json_response};
+use crate::state::StateStore;
+use mailparse::MailHeaderMap;
+use serde_json::json;
+use std::collections::HashMap;
+
+// Inbound-parse webhooks of transactional mail providers, mapped onto `Email`:
+//   POST /inbound/mailgun   Mailgun routes (forward() action), signed with the webhook key
+//   POST /inbound/sendgrid  SendGrid Inbound Parse, authenticated by a `token` query parameter
+#[derive(Clone, Copy)]
+pub enum Provider {
+    Mailgun,
+    Sendgrid,
+}
+
+pub fn handle(
+    config: &Config,
+    store: &dyn StateStore,
+    provider: Provider,
+    query: &str,
+    content_type: Option<&str>,
+    body: &[u8],
+) -> HttpResponse {
+    let Some(ref inbound) = config.inbound else {
+        return json_response(404, json!({ "error": "not found" }));
+    };
+    let form = match parse_form(content_type.unwrap_or_default(), body) {
+        Ok(form) => form,
+        Err(e) => return json_response(400, json!({ "error": format!("Invalid form: {}", e) })),
+    };
+    let field = |name: &str| form.get(name).map(|v| String::from_utf8_lossy(v).into_owned());
+
+    let authorized = match provider {
+        Provider::Mailgun => inbound.mailgun_signing_key.as_deref().is_some_and(|key| {
+            verify_mailgun(key, &field("timestamp").unwrap_or_default(), &field("token").unwrap_or_default(), &field("signature").unwrap_or_default())
+        }),
+        Provider::Sendgrid => inbound.sendgrid_token.as_deref().is_some_and(|expected| {
+            form_urlencoded::parse(query.as_bytes())
+                .any(|(k, v)| k == "token" && secure_eq(v.as_bytes(), expected.as_bytes()))
+        }),
+    };
+    if !authorized {
+        return json_response(401, json!({ "error": "unauthorized" }));
+    }
+
+    // Prefer the full MIME message when the provider was set up to send it
+    let raw = match provider {
+        Provider::Mailgun => form.get("body-mime"),
+        Provider::Sendgrid => form.get("email"),
+    };
+    let email = match raw {
+        Some(raw) => match Email::parse(raw) {
+            Ok(email) => email,
+            Err(e) => return json_response(400, json!({ "error": format!("Invalid email: {}", e) })),
+        },
+        None => match provider {
+            Provider::Mailgun => IncomingEmail {
+                subject: field("subject").unwrap_or_default(),
+                from: field("from").or_else(|| field("sender")).unwrap_or_default(),
+                body: field("body-plain").unwrap_or_default(),
+                html: field("body-html"),
+                date: field("Date"),
+                message_id: field("Message-Id"),
+            }
+            .into_email(),
+            Provider::Sendgrid => {
+                let headers = field("headers").unwrap_or_default();
+                let (parsed_headers, _) = mailparse::parse_headers(headers.as_bytes()).unwrap_or_default();
+                IncomingEmail {
+                    subject: field("subject").unwrap_or_default(),
+                    from: field("from").unwrap_or_default(),
+                    body: field("text").unwrap_or_default(),
+                    html: field("html"),
+                    date: parsed_headers.get_first_value("Date"),
+                    message_id: parsed_headers.get_first_value("Message-ID"),
+                }
+                .into_email()
+            }
+        },
+    };
+    ingest::respond(ingest::process(config, store, &email), &email)
+}
+
+// https://documentation.mailgun.com/docs/mailgun/user-manual/webhooks/securing-webhooks
+fn verify_mailgun(key: &str, timestamp: &str, token: &str, signature: &str) -> bool {
+    // Reject replays of old requests
+    let fresh = timestamp
+        .parse::<i64>()
+        .is_ok_and(|ts| (chrono::Utc::now().timestamp() - ts).abs() < 15 * 60);
+    let expected = hmac_sha256(key.as_bytes(), format!("{}{}", timestamp, token).as_bytes()).map(|mac| hex(&mac));
+    fresh && expected.is_ok_and(|expected| secure_eq(expected.as_bytes(), signature.as_bytes()))
+}
+
+// multipart/form-data is MIME, so it's parsed with the mail parser by putting the request's
+// Content-Type in front of the body.
+fn parse_form(content_type: &str, body: &[u8]) -> Result<HashMap<String, Vec<u8>>, Error> {
+    let mut fields = HashMap::new();
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        for (key, value) in form_urlencoded::parse(body) {
+            fields.insert(key.into_owned(), value.into_owned().into_bytes());
+        }
+        return Ok(fields);
+    }
+    if !content_type.starts_with("multipart/form-data") {
+        return Err(Error::Parse(format!("Unsupported Content-Type {}", content_type)));
+    }
+
+    let mut mime = format!("Content-Type: {}\r\n\r\n", content_type).into_bytes();
+    mime.extend_from_slice(body);
+    let parsed = mailparse::parse_mail(&mime)?;
+    for part in &parsed.subparts {
+        let disposition = part.get_content_disposition();
+        // Attachments aren't forwarded (yet)
+        if disposition.params.contains_key("filename") {
+            continue;
+        }
+        if let Some(name) = disposition.params.get("name") {
+            fields.insert(name.clone(), part.get_body_raw()?);
+        }
+    }
+    Ok(fields)
+}
@@ -0,0 +1,85 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::mail::{Email, clean_body};
+use crate::{events, pipeline, trace};
+use crate::server::{HttpResponse, json_response};
+use crate::state::StateStore;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+// An email given as fields rather than a raw message: the JSON body of `POST /ingest`, and
+// what the inbound-parse webhooks are mapped to
+#[derive(Deserialize)]
+pub struct IncomingEmail {
+    pub subject: String,
+    pub from: String,
+    #[serde(default)]
+    pub body: String,
+    pub html: Option<String>,
+    pub date: Option<String>,
+    pub message_id: Option<String>,
+}
+
+impl IncomingEmail {
+    pub fn into_email(self) -> Email {
+        let (body, body_source) = match self.html {
+            Some(ref html) if self.body.is_empty() => (
+                clean_body(&html2text::from_read(html.as_bytes(), 80).unwrap_or_else(|_| html.clone())),
+                "html",
+            ),
+            _ => (clean_body(&self.body), "text/plain"),
+        };
+        let mut email = Email::new(self.subject, self.from, body);
+        email.body_source = body_source;
+        email.date = self.date.as_deref().and_then(|d| {
+            DateTime::parse_from_rfc3339(d)
+                .map(|d| d.with_timezone(&Utc))
+                .ok()
+                .or_else(|| mailparse::dateparse(d).ok().and_then(|ts| DateTime::from_timestamp(ts, 0)))
+        });
+        email.trace_id = trace::id_for(self.message_id.as_deref(), email.body.as_bytes());
+        email.message_id = self.message_id;
+        email
+    }
+}
+
+// `POST /ingest`: a raw message (any content type) or a JSON email object
+// (`application/json`), fed through the same pipeline as IMAP mail.
+pub fn handle(config: &Config, store: &dyn StateStore, content_type: Option<&str>, body: &[u8]) -> HttpResponse {
+    let is_json = content_type.is_some_and(|ct| ct.starts_with("application/json"));
+    let email = if is_json {
+        serde_json::from_slice::<IncomingEmail>(body)
+            .map(IncomingEmail::into_email)
+            .map_err(|e| e.to_string())
+    } else {
+        Email::parse(body).map_err(|e| e.to_string())
+    };
+    match email {
+        Ok(email) => respond(process(config, store, &email), &email),
+        Err(e) => json_response(400, json!({ "error": format!("Invalid email: {}", e) })),
+    }
+}
+
+pub fn respond(result: Result<&'static str, Error>, email: &Email) -> HttpResponse {
+    match result {
+        Ok(status) => json_response(202, json!({ "trace_id": email.trace_id, "status": status })),
+        // 502 so the caller retries, like a failed delivery leaves the message in the mailbox
+        Err(e) => json_response(502, json!({ "trace_id": email.trace_id, "error": e.to_string() })),
+    }
+}
+
+pub fn process(config: &Config, store: &dyn StateStore, email: &Email) -> Result<&'static str, Error> {
+    let _trace = trace::enter(email);
+    info!("Received via HTTP from {}", email.from);
+    events::emit("fetched", email, json!({ "source": "ingest" }));
+    if pipeline::screen(config, store, email)? {
+        return Ok("screened");
+    }
+    if pipeline::deliver(config, store, email)? {
+        Ok("delivered")
+    } else {
+        Err(Error::network("delivery failed"))
+    }
+}
@@ -0,0 +1,185 @@
+use crate::archive;
+use crate::config::{Config, IrcConfig, Route};
+use crate::error::Error;
+use crate::mail::Email;
+use native_tls::TlsConnector;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use tracing::error;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+// IRC limits a line to 512 bytes including the command, the channel and the CRLF
+const MAX_LINE: usize = 510;
+
+// Announces each delivered email in an IRC channel: subject, sender and the archive link
+// when there is one. Like MQTT, a connection is made per message: register (with SASL PLAIN
+// when a password is set), join, one PRIVMSG, quit. The route's `irc_channel` overrides
+// irc.channel. Failures are logged and don't hold up the delivery.
+pub fn announce(config: &Config, route: Option<&Route>, email: &Email) {
+    let Some(ref irc) = config.irc else {
+        return;
+    };
+    let channel = route.and_then(|r| r.irc_channel.as_deref()).unwrap_or(&irc.channel);
+    let text = message(email, archive::url(config, &email.trace_id).as_deref());
+    if let Err(e) = send(irc, channel, &text) {
+        error!("Failed to announce on IRC ({}): {}", channel, e);
+    }
+}
+
+// `📰 Subject — Sender · link`, on one line and cut to fit
+fn message(email: &Email, url: Option<&str>) -> String {
+    let clean = |s: &str| s.split(['\r', '\n']).map(str::trim).filter(|s| !s.is_empty()).collect::<Vec<_>>().join(" ");
+    let suffix = match url {
+        Some(url) => format!(" — {} · {}", clean(&email.from), url),
+        None => format!(" — {}", clean(&email.from)),
+    };
+    // Room for "PRIVMSG #channel :"; channel names are at most 50 bytes
+    let room = MAX_LINE.saturating_sub(60 + suffix.len());
+    let mut subject = format!("📰 {}", clean(&email.subject));
+    if subject.len() > room {
+        let mut end = room.saturating_sub(3);
+        while !subject.is_char_boundary(end) {
+            end -= 1;
+        }
+        subject.truncate(end);
+        subject.push('…');
+    }
+    subject + &suffix
+}
+
+fn send(irc: &IrcConfig, channel: &str, text: &str) -> Result<(), Error> {
+    let url = reqwest::Url::parse(&irc.server).map_err(Error::config)?;
+    let tls = match url.scheme() {
+        "irc" => false,
+        "ircs" => true,
+        scheme => return Err(Error::Config(format!("Unsupported IRC scheme {} (use irc:// or ircs://)", scheme))),
+    };
+    let host = url.host_str().ok_or_else(|| Error::config("The IRC server URL has no host"))?;
+    let port = url.port().unwrap_or(if tls { 6697 } else { 6667 });
+    let address = (host, port)
+        .to_socket_addrs()
+        .map_err(Error::network)?
+        .next()
+        .ok_or_else(|| Error::Network(format!("{} did not resolve", host)))?;
+    let tcp = TcpStream::connect_timeout(&address, TIMEOUT).map_err(Error::network)?;
+    tcp.set_read_timeout(Some(TIMEOUT)).map_err(Error::network)?;
+    tcp.set_write_timeout(Some(TIMEOUT)).map_err(Error::network)?;
+    if tls {
+        let connector = TlsConnector::new().map_err(Error::network)?;
+        let stream = connector.connect(host, tcp).map_err(Error::network)?;
+        Session::new(irc, stream).announce(channel, text)
+    } else {
+        Session::new(irc, tcp).announce(channel, text)
+    }
+}
+
+struct Session<'a, S: Read + Write> {
+    irc: &'a IrcConfig,
+    stream: BufReader<S>,
+}
+
+impl<'a, S: Read + Write> Session<'a, S> {
+    fn new(irc: &'a IrcConfig, stream: S) -> Self {
+        Session { irc, stream: BufReader::new(stream) }
+    }
+
+    fn announce(mut self, channel: &str, text: &str) -> Result<(), Error> {
+        self.register()?;
+        self.write(&format!("JOIN {}", channel))?;
+        loop {
+            let (command, params) = self.read()?;
+            match command.as_str() {
+                // End of the member list: joined
+                "366" => break,
+                // No such channel, full, invite only, banned, needs a key, ...
+                "403" | "405" | "471" | "473" | "474" | "475" | "477" => {
+                    return Err(Error::Network(format!("Cannot join {}: {}", channel, params.last().cloned().unwrap_or_default())));
+                }
+                _ => {}
+            }
+        }
+        self.write(&format!("PRIVMSG {} :{}", channel, text))?;
+        self.write("QUIT :Delivered")
+    }
+
+    fn register(&mut self) -> Result<(), Error> {
+        let sasl = self.irc.password.is_some();
+        if sasl {
+            self.write("CAP REQ :sasl")?;
+        }
+        let mut nick = self.irc.nick.clone();
+        self.write(&format!("NICK {}", nick))?;
+        self.write(&format!("USER {} 0 * :newsletter", self.irc.nick))?;
+        loop {
+            let (command, params) = self.read()?;
+            let last = params.last().cloned().unwrap_or_default();
+            match command.as_str() {
+                "001" => return Ok(()),
+                "CAP" if params.get(1).is_some_and(|p| p == "ACK") => self.write("AUTHENTICATE PLAIN")?,
+                "CAP" if params.get(1).is_some_and(|p| p == "NAK") => {
+                    return Err(Error::network("The IRC server does not support SASL"));
+                }
+                "AUTHENTICATE" if last == "+" => {
+                    let user = self.irc.sasl_username.as_deref().unwrap_or(&self.irc.nick);
+                    let password = self.irc.password.as_deref().unwrap_or_default();
+                    let token = format!("{}\0{}\0{}", user, user, password);
+                    self.write(&format!("AUTHENTICATE {}", openssl::base64::encode_block(token.as_bytes())))?;
+                }
+                // SASL succeeded; registration completes once capability negotiation ends
+                "903" => self.write("CAP END")?,
+                "902" | "904" | "905" | "906" => return Err(Error::Network(format!("SASL authentication failed: {}", last))),
+                // Nickname in use: try again with an underscore, as clients do
+                "433" if nick.len() < 30 => {
+                    nick.push('_');
+                    self.write(&format!("NICK {}", nick))?;
+                }
+                "432" | "433" => return Err(Error::Network(format!("Nickname {} rejected: {}", nick, last))),
+                "ERROR" => return Err(Error::Network(format!("IRC server closed the connection: {}", last))),
+                _ => {}
+            }
+        }
+    }
+
+    fn write(&mut self, line: &str) -> Result<(), Error> {
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .and_then(|_| stream.flush())
+            .map_err(Error::network)
+    }
+
+    // The next line's command and parameters. PINGs are answered here.
+    fn read(&mut self) -> Result<(String, Vec<String>), Error> {
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).map_err(Error::network)? == 0 {
+                return Err(Error::network("IRC server closed the connection"));
+            }
+            let (command, params) = parse(line.trim_end_matches(['\r', '\n']));
+            if command == "PING" {
+                self.write(&format!("PONG :{}", params.first().cloned().unwrap_or_default()))?;
+                continue;
+            }
+            return Ok((command, params));
+        }
+    }
+}
+
+// `[:prefix] COMMAND param param :trailing param`
+fn parse(line: &str) -> (String, Vec<String>) {
+    let line = match line.strip_prefix(':') {
+        Some(rest) => rest.split_once(' ').map_or("", |(_, rest)| rest),
+        None => line,
+    };
+    let (head, trailing) = match line.split_once(" :") {
+        Some((head, trailing)) => (head, Some(trailing)),
+        None => (line, None),
+    };
+    let mut words = head.split(' ').filter(|w| !w.is_empty()).map(str::to_string);
+    let command = words.next().unwrap_or_default().to_uppercase();
+    let mut params: Vec<String> = words.collect();
+    params.extend(trailing.map(str::to_string));
+    (command, params)
+}
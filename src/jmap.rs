@@ -0,0 +1,277 @@
+use crate::auth::{AuthError, AuthHealth};
+use crate::cadence;
+use crate::config::Config;
+use crate::error::Error;
+use crate::leader::Leader;
+use crate::monitor::{self, POLL_INTERVAL};
+use crate::retention::Pruner;
+use crate::shutdown;
+use crate::source::{self, MailSource};
+use crate::state::StateStore;
+use crate::watchdog::Watchdog;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+const MAIL: &str = "urn:ietf:params:jmap:mail";
+const USING: &[&str] = &["urn:ietf:params:jmap:core", MAIL];
+
+// The event source sends a ping this often (seconds), so a shutdown is noticed in time
+const PING: u64 = 10;
+
+// A JMAP session (RFC 8620/8621), found from the host's /.well-known/jmap or the session URL
+// given as imap_server. The inbox is read with Email/query and Email/get, messages are
+// downloaded as blobs, and the handled ones are destroyed together when the check is done.
+pub struct Jmap {
+    token: String,
+    api_url: String,
+    download_url: String,
+    event_source_url: Option<String>,
+    account_id: String,
+    inbox: String,
+    // The listed messages' IDs and blob IDs, by the position `list` gave them
+    listed: Vec<(String, String)>,
+    removed: Vec<String>,
+}
+
+impl Jmap {
+    pub fn connect(config: &Config) -> Result<Jmap, Error> {
+        let server = config.imap_server.trim_end_matches('/');
+        let url = match server.starts_with("https://") || server.starts_with("http://") {
+            true => server.to_string(),
+            false => format!("https://{}/.well-known/jmap", server),
+        };
+        let token = config.imap_password.clone();
+        let response = crate::http::client().get(&url).bearer_auth(&token).send()?;
+        let session: Value = checked(response)?.json()?;
+        let account_id = session["primaryAccounts"][MAIL]
+            .as_str()
+            .ok_or_else(|| Error::Imap(format!("{} has no mail account", url)))?
+            .to_string();
+        let field = |name: &str| {
+            session[name].as_str().map(str::to_string).ok_or_else(|| Error::Imap(format!("JMAP session has no {}", name)))
+        };
+        let mut jmap = Jmap {
+            token,
+            api_url: field("apiUrl")?,
+            download_url: field("downloadUrl")?,
+            event_source_url: session["eventSourceUrl"].as_str().map(str::to_string),
+            account_id,
+            inbox: String::new(),
+            listed: Vec::new(),
+            removed: Vec::new(),
+        };
+        let found = jmap.call(json!([
+            ["Mailbox/query", { "accountId": jmap.account_id, "filter": { "role": "inbox" } }, "0"],
+        ]))?;
+        let inbox = found.first().and_then(|r| r["ids"][0].as_str());
+        jmap.inbox = inbox.ok_or_else(|| Error::imap("The JMAP account has no inbox"))?.to_string();
+        Ok(jmap)
+    }
+
+    // A message by the number `list` gave it
+    fn email(&self, id: u32) -> Result<&(String, String), Error> {
+        let index = (id as usize).checked_sub(1);
+        index.and_then(|i| self.listed.get(i)).ok_or_else(|| Error::Imap(format!("No JMAP message {}", id)))
+    }
+
+    // Makes the method calls and returns their results, in order
+    fn call(&self, calls: Value) -> Result<Vec<Value>, Error> {
+        let response = crate::http::client()
+            .post(&self.api_url)
+            .bearer_auth(&self.token)
+            .json(&json!({ "using": USING, "methodCalls": calls }))
+            .send()?;
+        let body: Value = checked(response)?.json()?;
+        let mut results = Vec::new();
+        for response in body["methodResponses"].as_array().into_iter().flatten() {
+            if response[0] == "error" {
+                let kind = response[1]["type"].as_str().unwrap_or("error");
+                let description = response[1]["description"].as_str().unwrap_or_default();
+                return Err(Error::Imap(format!("JMAP server said: {} {}", kind, description).trim_end().to_string()));
+            }
+            results.push(response[1].clone());
+        }
+        Ok(results)
+    }
+
+    // Waits up to `max` for the server to report a change to the account's emails. Returns
+    // early on a shutdown; without an event source it only sleeps.
+    fn wait(&self, config: &Config, max: Duration) -> Result<(), Error> {
+        let Some(ref template) = self.event_source_url else {
+            shutdown::sleep(max.min(Duration::from_secs(config.pop3_poll_seconds())));
+            return Ok(());
+        };
+        let url = template.replace("{types}", "Email").replace("{closeafter}", "state").replace("{ping}", &PING.to_string());
+        let started = Instant::now();
+        let response = crate::http::client()
+            .get(&url)
+            .bearer_auth(&self.token)
+            .header("Accept", "text/event-stream")
+            .timeout(max + Duration::from_secs(PING))
+            .send()?;
+        let mut events = BufReader::new(checked(response)?);
+        let mut line = String::new();
+        let mut event = String::new();
+        loop {
+            line.clear();
+            match events.read_line(&mut line) {
+                // The server closed the stream (after the change, with closeafter=state)
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(_) if started.elapsed() >= max => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            let line = line.trim_end();
+            if let Some(name) = line.strip_prefix("event:") {
+                event = name.trim().to_string();
+            } else if line.starts_with("data:") && event == "state" {
+                debug!("JMAP push: {}", line);
+                return Ok(());
+            }
+            if shutdown::requested() || started.elapsed() >= max {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// The response, or the error for its status: a refused token is an authentication failure
+fn checked(response: reqwest::blocking::Response) -> Result<reqwest::blocking::Response, Error> {
+    match response.status() {
+        status if status.is_success() => Ok(response),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            Err(AuthError(format!("JMAP server refused the token ({})", response.status())).into())
+        }
+        status => Err(Error::Imap(format!("JMAP server answered {} for {}", status, response.url()))),
+    }
+}
+
+impl MailSource for Jmap {
+    fn protocol(&self) -> &'static str {
+        "jmap"
+    }
+
+    fn list(&mut self) -> Result<Vec<(u32, u64)>, Error> {
+        let results = self.call(json!([
+            ["Email/query", {
+                "accountId": self.account_id,
+                "filter": { "inMailbox": self.inbox },
+                "sort": [{ "property": "receivedAt", "isAscending": true }],
+            }, "0"],
+            ["Email/get", {
+                "accountId": self.account_id,
+                "#ids": { "resultOf": "0", "name": "Email/query", "path": "/ids" },
+                "properties": ["id", "blobId", "size"],
+            }, "1"],
+        ]))?;
+        let emails = results.get(1).and_then(|r| r["list"].as_array()).cloned().unwrap_or_default();
+        self.listed.clear();
+        self.removed.clear();
+        let mut messages = Vec::new();
+        for email in emails {
+            let (Some(id), Some(blob_id)) = (email["id"].as_str(), email["blobId"].as_str()) else {
+                continue;
+            };
+            self.listed.push((id.to_string(), blob_id.to_string()));
+            messages.push((self.listed.len() as u32, email["size"].as_u64().unwrap_or(0)));
+        }
+        Ok(messages)
+    }
+
+    // Only the headers come from Email/get; the whole message is the email's blob
+    fn fetch(&mut self, id: u32, headers_only: bool) -> Result<Vec<u8>, Error> {
+        let (email_id, blob_id) = self.email(id)?.clone();
+        if headers_only {
+            let results = self.call(json!([
+                ["Email/get", { "accountId": self.account_id, "ids": [email_id], "properties": ["headers"] }, "0"],
+            ]))?;
+            let mut raw = String::new();
+            let headers = results.first().and_then(|r| r["list"][0]["headers"].as_array());
+            for header in headers.into_iter().flatten() {
+                let (name, value) = (header["name"].as_str().unwrap_or_default(), header["value"].as_str().unwrap_or_default());
+                raw.push_str(&format!("{}:{}\r\n", name, value));
+            }
+            raw.push_str("\r\n");
+            return Ok(raw.into_bytes());
+        }
+        let url = self
+            .download_url
+            .replace("{accountId}", &self.account_id)
+            .replace("{blobId}", &blob_id)
+            .replace("{type}", "message/rfc822")
+            .replace("{name}", "message.eml");
+        let response = crate::http::client().get(&url).bearer_auth(&self.token).send()?;
+        Ok(checked(response)?.bytes()?.to_vec())
+    }
+
+    fn remove(&mut self, id: u32) -> Result<(), Error> {
+        let (email_id, _) = self.email(id)?.clone();
+        self.removed.push(email_id);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        if self.removed.is_empty() {
+            return Ok(());
+        }
+        let results = self.call(json!([["Email/set", { "accountId": self.account_id, "destroy": self.removed }, "0"]]))?;
+        self.removed.clear();
+        match results.first().and_then(|r| r["notDestroyed"].as_object()).filter(|n| !n.is_empty()) {
+            Some(failed) => Err(Error::Imap(format!("JMAP server didn't delete {} message(s)", failed.len()))),
+            None => Ok(()),
+        }
+    }
+}
+
+// The worker for a JMAP account. A check runs at startup and whenever the event source
+// reports new mail, and at least every idle.renew_minutes in case a push went missing.
+pub fn run_monitor(
+    config: &Config,
+    store: &dyn StateStore,
+    leader: Option<&Leader>,
+    watchdog: &Watchdog,
+    health: &mut AuthHealth,
+) -> Result<(), Error> {
+    let mut session = Jmap::connect(config)?;
+    info!("Connected to JMAP as {}", config.imap_username);
+    health.record_success(config);
+    crate::health::logged_in(config);
+
+    let mut renew = Duration::from_secs(config.idle.as_ref().map_or(10, |i| i.renew_minutes()) * 60);
+    if let Some(leader) = leader {
+        renew = renew.min(leader.renew_interval());
+    }
+    let mut catching_up = true;
+    let mut pruner = Pruner::default();
+    let mut watcher = cadence::Watcher::default();
+
+    loop {
+        if let Some(leader) = leader {
+            leader.renew()?;
+        }
+        let more_pending = source::cycle(config, store, &mut session, leader, catching_up)?;
+        // A backlog cut short by max_messages_per_cycle is still being caught up on
+        catching_up = catching_up && more_pending;
+
+        monitor::housekeeping(config, store, &mut pruner, &mut watcher);
+        watchdog.beat();
+        crate::health::polled(config);
+
+        match more_pending {
+            true => {
+                watchdog.beat_after(POLL_INTERVAL);
+                shutdown::sleep(POLL_INTERVAL);
+            }
+            false => {
+                watchdog.beat_after(renew);
+                session.wait(config, renew)?;
+            }
+        }
+        if shutdown::requested() {
+            return Ok(());
+        }
+    }
+}
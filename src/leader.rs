@@ -0,0 +1,69 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::state::StateStore;
+use std::time::Duration;
+use tracing::{error, info};
+
+const LEASE: &str = "leader";
+
+// Optional active/standby coordination for running several replicas against the same
+// mailbox. The leader renews its lease every monitor cycle and before each delivery; a
+// standby polls until the lease lapses and then takes over.
+pub struct Leader<'a> {
+    store: &'a dyn StateStore,
+    id: String,
+    ttl: Duration,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Lost leadership to another instance")]
+pub struct LostLeadership;
+
+impl<'a> Leader<'a> {
+    pub fn from_config(config: &Config, store: &'a dyn StateStore) -> Option<Leader<'a>> {
+        let leader = config.leader.as_ref().filter(|l| l.enabled)?;
+        let id = leader.instance_id.clone().unwrap_or_else(|| {
+            let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "newsletter".to_string());
+            format!("{}-{}", host, std::process::id())
+        });
+        Some(Leader {
+            store,
+            id,
+            ttl: Duration::from_secs(leader.ttl_seconds()),
+        })
+    }
+
+    // How often the lease must be renewed to keep it
+    pub fn renew_interval(&self) -> Duration {
+        self.ttl / 3
+    }
+
+    pub fn renew(&self) -> Result<(), Error> {
+        if self.store.try_lease(LEASE, &self.id, self.ttl)? {
+            Ok(())
+        } else {
+            Err(LostLeadership.into())
+        }
+    }
+
+    pub fn wait(&self) {
+        let mut announced = false;
+        loop {
+            match self.store.try_lease(LEASE, &self.id, self.ttl) {
+                Ok(true) => {
+                    info!("Acquired leadership as {}", self.id);
+                    return;
+                }
+                Ok(false) if !announced => {
+                    info!("Another instance is leader, standing by as {}", self.id);
+                    announced = true;
+                }
+                Ok(false) => {}
+                Err(e) => error!("Failed to check leadership: {}", e),
+            }
+            if !crate::shutdown::sleep(self.renew_interval()) {
+                return;
+            }
+        }
+    }
+}
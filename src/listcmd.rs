@@ -0,0 +1,137 @@
+use crate::archive;
+use crate::config::Config;
+use crate::error::Error;
+use crate::loops;
+use crate::state::StateStore;
+use clap::ValueEnum;
+use lettre::message::Mailbox;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ListCommand {
+    Subscribe,
+    Unsubscribe,
+    Help,
+}
+
+// Sends the mailing-list command email advertised in the List-* headers of the latest
+// archived email from `sender`.
+pub fn run(config: &Config, store: &dyn StateStore, sender: &str, command: ListCommand) -> Result<(), Error> {
+    let archived = archive::latest_from(store, sender)?
+        .ok_or_else(|| Error::Config(format!("No archived email from {} (is [archive] enabled?)", sender)))?;
+    let (header, value) = match command {
+        ListCommand::Subscribe => ("List-Subscribe", &archived.list.subscribe),
+        ListCommand::Unsubscribe => ("List-Unsubscribe", &archived.list.unsubscribe),
+        ListCommand::Help => ("List-Help", &archived.list.help),
+    };
+    let value = value
+        .as_deref()
+        .ok_or_else(|| Error::Parse(format!("{} has no {} header", archived.from, header)))?;
+
+    let targets = parse_targets(value);
+    let Some(mailto) = targets.iter().find_map(|t| t.strip_prefix("mailto:")) else {
+        match targets.first() {
+            Some(url) => println!("{} offers no mailto: address. Open this link instead:\n{}", header, url),
+            None => println!("Could not parse {}: {}", header, value),
+        }
+        return Ok(());
+    };
+
+    let (to, subject, body) = parse_mailto(mailto);
+    send(config, store, &to, &subject, &body)?;
+    println!("Sent {} to {} (subject: {:?})", header, to, subject);
+    Ok(())
+}
+
+// `<mailto:a@b?subject=x>, <https://...>` -> ["mailto:a@b?subject=x", "https://..."]
+fn parse_targets(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter_map(|part| {
+            let part = part.trim();
+            part.strip_prefix('<')?.split('>').next().map(|t| t.trim().to_string())
+        })
+        .collect()
+}
+
+fn parse_mailto(mailto: &str) -> (String, String, String) {
+    let (address, query) = mailto.split_once('?').unwrap_or((mailto, ""));
+    let mut subject = String::new();
+    let mut body = String::new();
+    for (key, value) in url_decode_pairs(query) {
+        match key.to_lowercase().as_str() {
+            "subject" => subject = value,
+            "body" => body = value,
+            _ => {}
+        }
+    }
+    (percent_decode(address), subject, body)
+}
+
+fn url_decode_pairs(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && let Some(b) = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(b);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// Marked as automatic (Auto-Submitted, X-Loop) and remembered by Message-ID, so that a bounce
+// or autoreply coming back to the monitored mailbox is recognised as our own (see loops.rs)
+fn send(config: &Config, store: &dyn StateStore, to: &str, subject: &str, body: &str) -> Result<(), Error> {
+    let smtp = config.smtp.as_ref().ok_or_else(|| Error::config("An [smtp] section is required to send list commands"))?;
+    // Unset SMTP settings fall back to the (first) IMAP account
+    let imap = config.accounts().swap_remove(0);
+    let from: Mailbox = smtp.from.as_deref().unwrap_or(&imap.imap_username).parse().map_err(Error::config)?;
+    let mut id = [0u8; 16];
+    openssl::rand::rand_bytes(&mut id)?;
+    let message_id = format!("<{}@{}>", crate::crypto::hex(&id), from.email.domain());
+    let address = loops::address(config).unwrap_or_default();
+    let message = Message::builder()
+        .from(from)
+        .to(to.parse().map_err(Error::parse)?)
+        .subject(subject)
+        .message_id(Some(message_id.clone()))
+        .raw_header(HeaderValue::new(HeaderName::new_from_ascii_str("Auto-Submitted"), "auto-generated".to_string()))
+        .raw_header(HeaderValue::new(HeaderName::new_from_ascii_str("X-Loop"), address))
+        .body(body.to_string())
+        .map_err(Error::parse)?;
+    loops::record_sent(store, &message_id)?;
+
+    let username = smtp.username.clone().unwrap_or(imap.imap_username);
+    let password = smtp.password.clone().unwrap_or(imap.imap_password);
+    let transport = if smtp.port() == 465 {
+        SmtpTransport::relay(&smtp.server).map_err(Error::network)?
+    } else {
+        SmtpTransport::starttls_relay(&smtp.server).map_err(Error::network)?
+    };
+    let transport = transport.port(smtp.port()).credentials(Credentials::new(username, password)).build();
+    transport.send(&message).map_err(Error::network)?;
+    Ok(())
+}
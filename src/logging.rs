@@ -0,0 +1,236 @@
+use crate::config::{Config, LogFormat};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write as _};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+// A minimal `tracing` subscriber: one line per event, with the fields of the spans it
+// happened in (the message being processed: trace_id, uid, from, subject, route). Text for
+// people, or JSON objects for Loki and the like. INFO and DEBUG go to stdout, warnings and
+// errors to stderr, as the println!/eprintln! calls this replaced did.
+pub fn init(config: &Config) {
+    let directives = std::env::var("RUST_LOG").ok().or_else(|| config.log_level.clone()).unwrap_or_default();
+    let logger = Logger {
+        filter: Filter::parse(&directives),
+        format: config.log_format.unwrap_or_default(),
+        next_id: AtomicU64::new(1),
+        spans: Mutex::new(HashMap::new()),
+    };
+    let _ = tracing::subscriber::set_global_default(logger);
+}
+
+// `RUST_LOG` syntax, without regexes: a default level and/or `target=level` pairs, e.g.
+// `debug` or `info,newsletter::pipeline=debug`. The longest matching target wins.
+struct Filter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn parse(directives: &str) -> Filter {
+        let mut filter = Filter { default: LevelFilter::INFO, targets: Vec::new() };
+        for directive in directives.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    if let Ok(level) = level.trim().parse() {
+                        filter.targets.push((target.trim().to_string(), level));
+                    }
+                }
+                None => {
+                    if let Ok(level) = directive.parse() {
+                        filter.default = level;
+                    }
+                }
+            }
+        }
+        filter.targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        filter
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, level)| *level).fold(self.default, LevelFilter::max)
+    }
+}
+
+struct SpanData {
+    fields: Map<String, Value>,
+    refs: usize,
+}
+
+struct Logger {
+    filter: Filter,
+    format: LogFormat,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+thread_local! {
+    // The spans entered on this thread, innermost last
+    static STACK: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.filter.level_for(metadata.target()) >= *metadata.level()
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.filter.max())
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut fields = Fields::default();
+        span.record(&mut fields);
+        self.spans.lock().unwrap().insert(id, SpanData { fields: fields.0, refs: 1 });
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.fields.extend(fields.0);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let mut fields = fields.0;
+        let message = match fields.remove("message") {
+            Some(Value::String(s)) => s,
+            _ => String::new(),
+        };
+
+        // Outer spans first, so the innermost value of a field wins
+        let mut context = Map::new();
+        {
+            let spans = self.spans.lock().unwrap();
+            STACK.with(|stack| {
+                for id in stack.borrow().iter() {
+                    if let Some(data) = spans.get(id) {
+                        context.extend(data.fields.clone());
+                    }
+                }
+            });
+        }
+        context.extend(fields);
+
+        let metadata = event.metadata();
+        let line = match self.format {
+            LogFormat::Text => text_line(*metadata.level(), &message, context),
+            LogFormat::Json => json_line(*metadata.level(), metadata.target(), message, context),
+        };
+        if *metadata.level() <= Level::WARN {
+            let _ = writeln!(io::stderr().lock(), "{}", line);
+        } else {
+            let _ = writeln!(io::stdout().lock(), "{}", line);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        STACK.with(|stack| stack.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some(i) = stack.iter().rposition(|id| *id == span.into_u64()) {
+                stack.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.refs -= 1;
+        if data.refs > 0 {
+            return false;
+        }
+        spans.remove(&span.into_u64());
+        true
+    }
+}
+
+// `LEVEL [trace] message key=value ...`. The trace ID keeps its old place in front of the
+// message; the other message fields are left to the JSON output, they'd repeat on every line.
+fn text_line(level: Level, message: &str, mut fields: Map<String, Value>) -> String {
+    let mut line = format!("{} {:>5} ", chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"), level);
+    if let Some(Value::String(trace_id)) = fields.remove("trace_id") {
+        let _ = write!(line, "[{}] ", trace_id);
+    }
+    line.push_str(message);
+    for key in ["uid", "from", "subject", "route"] {
+        fields.remove(key);
+    }
+    for (key, value) in fields {
+        match value {
+            Value::String(s) => write!(line, " {}={:?}", key, s),
+            value => write!(line, " {}={}", key, value),
+        }
+        .unwrap();
+    }
+    line
+}
+
+fn json_line(level: Level, target: &str, message: String, fields: Map<String, Value>) -> String {
+    let mut object = Map::new();
+    object.insert("timestamp".to_string(), Value::String(chrono::Utc::now().to_rfc3339()));
+    object.insert("level".to_string(), Value::String(level.to_string()));
+    object.insert("target".to_string(), Value::String(target.to_string()));
+    object.insert("message".to_string(), Value::String(message));
+    object.extend(fields);
+    Value::Object(object).to_string()
+}
+
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), Value::String(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::String(value.to_string()));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::Bool(value));
+    }
+}
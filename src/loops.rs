@@ -0,0 +1,63 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::mail::Email;
+use crate::state::StateStore;
+use chrono::{DateTime, Utc};
+use mailparse::MailHeaderMap;
+
+const SENT_PREFIX: &str = "sent:";
+
+// The address our own mail goes out as ([smtp] from, else the first account's username), put
+// in the X-Loop header of everything sent and looked for in what comes in
+pub fn address(config: &Config) -> Option<String> {
+    let smtp = config.smtp.as_ref()?;
+    let from = smtp.from.clone().unwrap_or_else(|| config.accounts().swap_remove(0).imap_username);
+    Some(crate::series::sender_address(&from))
+}
+
+// Remembers the Message-ID of a message we sent, so a copy coming back is recognised
+pub fn record_sent(store: &dyn StateStore, message_id: &str) -> Result<(), Error> {
+    store.put_json(&format!("{}{}", SENT_PREFIX, message_id.trim()), &Utc::now())
+}
+
+fn sent(store: &dyn StateStore, message_id: &str) -> Result<bool, Error> {
+    let at: Option<DateTime<Utc>> = store.get_json(&format!("{}{}", SENT_PREFIX, message_id.trim()))?;
+    Ok(at.is_some())
+}
+
+// Why the email looks like mail we generated ourselves coming back (a list command bounced
+// or auto-answered into the monitored mailbox), if it does. Only checked when [smtp] is set,
+// as nothing is sent without it.
+pub fn detect(config: &Config, store: &dyn StateStore, email: &Email) -> Result<Option<&'static str>, Error> {
+    let Some(address) = address(config) else {
+        return Ok(None);
+    };
+    if let Some(ref message_id) = email.message_id
+        && sent(store, message_id)?
+    {
+        return Ok(Some("our own Message-ID"));
+    }
+    let Some(headers) = email.raw.as_deref().and_then(|raw| mailparse::parse_headers(raw).ok()).map(|(h, _)| h) else {
+        return Ok(None);
+    };
+    if headers.get_all_values("X-Loop").iter().any(|v| v.trim().eq_ignore_ascii_case(&address)) {
+        return Ok(Some("X-Loop header"));
+    }
+    // Automatic mail is a loop when it's from us, or answers something we sent
+    let automatic = headers.get_first_value("Auto-Submitted").is_some_and(|v| !v.trim().eq_ignore_ascii_case("no"));
+    if automatic {
+        if crate::series::sender_address(&email.from) == address {
+            return Ok(Some("Auto-Submitted from our own address"));
+        }
+        let referenced = ["In-Reply-To", "References"]
+            .iter()
+            .flat_map(|name| headers.get_all_values(name))
+            .flat_map(|value| value.split_whitespace().map(str::to_string).collect::<Vec<_>>());
+        for message_id in referenced {
+            if sent(store, &message_id)? {
+                return Ok(Some("Auto-Submitted reply to our own mail"));
+            }
+        }
+    }
+    Ok(None)
+}
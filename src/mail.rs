@@ -0,0 +1,390 @@
+use crate::config::{HtmlRender, LimitsConfig};
+use chrono::{DateTime, Utc};
+use mailparse::MailHeaderMap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{LazyLock, OnceLock};
+
+#[derive(Clone)]
+pub struct Email {
+    pub subject: String,
+    pub from: String,
+    // Raw To, Cc, Delivered-To and X-Original-To values, for routing by recipient
+    pub recipients: Vec<String>,
+    pub body: String,
+    pub date: Option<DateTime<Utc>>,
+    pub message_id: Option<String>,
+    pub trace_id: String,
+    // Set for bounces and autoreplies, describing why the message was classified as one
+    pub auto_reply: Option<&'static str>,
+    pub list: ListHeaders,
+    // The message as received, when there is one (kept by the archive for replays)
+    pub raw: Option<Vec<u8>>,
+    // Which extraction strategy produced `body` (see `extract_body`)
+    pub body_source: &'static str,
+    // Images the HTML part shows inline through `cid:` references
+    pub images: Vec<InlineImage>,
+    // Files attached to the email (PDFs, images, ...), other than the inline images
+    pub attachments: Vec<Attachment>,
+    // The first remote image the HTML part shows, for the embed when there is no inline one
+    pub image_url: Option<String>,
+    // The IMAP folder it was found in, when several are monitored
+    pub folder: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct InlineImage {
+    // Unique within the email, used as the attachment name
+    pub filename: String,
+    pub content_id: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+// RFC 2369 list command headers, kept verbatim (`<mailto:...>, <https://...>`), and the
+// RFC 2919 List-Id (`Tech Weekly <tech.example.com>`)
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ListHeaders {
+    pub subscribe: Option<String>,
+    pub unsubscribe: Option<String>,
+    pub help: Option<String>,
+    pub id: Option<String>,
+}
+
+impl Email {
+    // For messages that don't come from a mailbox (test posts, summaries)
+    pub fn new(subject: String, from: String, body: String) -> Email {
+        Email {
+            subject,
+            from,
+            recipients: Vec::new(),
+            body,
+            date: None,
+            message_id: None,
+            trace_id: String::new(),
+            auto_reply: None,
+            list: ListHeaders::default(),
+            raw: None,
+            body_source: "text/plain",
+            images: Vec::new(),
+            attachments: Vec::new(),
+            image_url: None,
+            folder: None,
+        }
+    }
+
+    pub fn parse(raw: &[u8]) -> Result<Email, mailparse::MailParseError> {
+        let parsed = mailparse::parse_mail(raw)?;
+
+        let subject = parsed.headers.get_first_value("Subject").unwrap_or("No Subject".to_string());
+        let from = parsed.headers.get_first_value("From").unwrap_or("Unknown Sender".to_string());
+        let recipients = ["To", "Cc", "Delivered-To", "X-Original-To"]
+            .iter()
+            .flat_map(|name| parsed.headers.get_all_values(name))
+            .collect();
+        let date = parsed
+            .headers
+            .get_first_value("Date")
+            .and_then(|d| mailparse::dateparse(&d).ok())
+            .and_then(|ts| DateTime::from_timestamp(ts, 0));
+        let message_id = parsed.headers.get_first_value("Message-ID").map(|id| id.trim().to_string());
+        let trace_id = crate::trace::id_for(message_id.as_deref(), raw);
+        let auto_reply = detect_auto_reply(&parsed);
+        let list = ListHeaders {
+            subscribe: parsed.headers.get_first_value("List-Subscribe"),
+            unsubscribe: parsed.headers.get_first_value("List-Unsubscribe"),
+            help: parsed.headers.get_first_value("List-Help"),
+            id: parsed.headers.get_first_value("List-Id"),
+        };
+
+        let (images, cids) = inline_images(&parsed);
+        let (body, body_source) = extract_body(&parsed, &cids);
+        let attachments = extract_attachments(&parsed, &images);
+        let image_url = remote_image(&parsed);
+
+        Ok(Email {
+            subject,
+            from,
+            recipients,
+            body,
+            date,
+            message_id,
+            trace_id,
+            auto_reply,
+            list,
+            raw: Some(raw.to_vec()),
+            body_source,
+            images,
+            attachments,
+            image_url,
+            folder: None,
+        })
+    }
+}
+
+// Delivery status notifications (RFC 3464) and autoresponders (RFC 3834 plus common
+// vendor headers). `Auto-Submitted: auto-generated` is deliberately not treated as an
+// autoreply: plenty of legitimate notification mail carries it.
+fn detect_auto_reply(parsed: &mailparse::ParsedMail) -> Option<&'static str> {
+    if parsed.ctype.mimetype == "multipart/report" {
+        return Some("delivery status notification");
+    }
+    let headers = &parsed.headers;
+    if let Some(value) = headers.get_first_value("Auto-Submitted") {
+        let value = value.trim().to_lowercase();
+        if value.starts_with("auto-replied") || value.starts_with("auto-notified") {
+            return Some("Auto-Submitted header");
+        }
+    }
+    if headers.get_first_header("X-Autoreply").is_some() || headers.get_first_header("X-Autorespond").is_some() {
+        return Some("X-Autoreply header");
+    }
+    if headers
+        .get_first_value("Precedence")
+        .is_some_and(|p| p.trim().eq_ignore_ascii_case("auto_reply"))
+    {
+        return Some("Precedence: auto_reply");
+    }
+    None
+}
+
+pub fn clean_body(body: &str) -> String {
+    // Replace multiple newlines with double newline (max)
+    let re_newlines = Regex::new(r"\n{3,}").unwrap();
+    let body = re_newlines.replace_all(body, "\n\n");
+
+    // Trim trailing spaces from each line
+    let re_trailing_spaces = Regex::new(r"(?m)[ \t]+$").unwrap();
+    let body = re_trailing_spaces.replace_all(&body, "");
+
+    body.trim().to_string()
+}
+
+// Tries progressively rougher strategies until one yields readable text: the text/plain
+// part, the HTML part converted to markdown, the raw text of the first part, and finally a
+// notice describing what the message contained. Returns the body and the strategy used.
+// Set once at startup from `limits.max_body_parse_bytes` and `render`
+static MAX_BODY_BYTES: OnceLock<usize> = OnceLock::new();
+static RENDER: OnceLock<HtmlRender> = OnceLock::new();
+
+pub fn init(limits: Option<&LimitsConfig>, render: HtmlRender) {
+    if let Some(max) = limits.and_then(|l| l.max_body_parse_bytes) {
+        let _ = MAX_BODY_BYTES.set(max);
+    }
+    let _ = RENDER.set(render);
+}
+
+// Cuts a decoded body down to max_body_parse_bytes, on a character boundary
+fn capped(mut body: String) -> String {
+    let Some(&max) = MAX_BODY_BYTES.get() else {
+        return body;
+    };
+    if body.len() > max {
+        let mut end = max;
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        body.truncate(end);
+    }
+    body
+}
+
+fn extract_body(parsed: &mailparse::ParsedMail, cids: &HashMap<String, String>) -> (String, &'static str) {
+    if let Some(body) =
+        find_part(parsed, "text/plain").and_then(|p| p.get_body().ok()).map(|b| clean_body(&capped(b)))
+        && readable(&body)
+    {
+        return (body, "text/plain");
+    }
+    if let Some(html) = find_part(parsed, "text/html").and_then(|p| p.get_body().ok()).map(capped)
+        && let Some(md) = html_to_text(&mark_cid_images(&html, cids))
+    {
+        let body = clean_body(&md);
+        if readable(&body) {
+            return (body, "html");
+        }
+    }
+    let mut first = parsed;
+    while let Some(part) = first.subparts.first() {
+        first = part;
+    }
+    if let Ok(raw) = first.get_body_raw() {
+        let body = clean_body(&capped(String::from_utf8_lossy(&raw).into_owned()));
+        if readable(&body) {
+            return (body, "raw first part");
+        }
+    }
+    let notice = format!(
+        "(No readable text in this email: {} with {} part(s).)",
+        parsed.ctype.mimetype,
+        parsed.subparts.len().max(1)
+    );
+    (notice, "headers only")
+}
+
+fn html_to_text(html: &str) -> Option<String> {
+    match RENDER.get().copied().unwrap_or_default() {
+        HtmlRender::Plain => html2text::from_read(html.as_bytes(), 80).ok(),
+        HtmlRender::Markdown => crate::markdown::from_html(html),
+    }
+}
+
+// The HTML part of a raw message and the inline images it references, for the web archive
+pub fn html_part(raw: &[u8]) -> Option<(String, Vec<InlineImage>)> {
+    let parsed = mailparse::parse_mail(raw).ok()?;
+    let html = find_part(&parsed, "text/html")?.get_body().ok()?;
+    Some((html, inline_images(&parsed).0))
+}
+
+// Leaf parts marked as attachments, or carrying a file name without being text, except the
+// inline images already taken for the HTML
+fn extract_attachments(parsed: &mailparse::ParsedMail, images: &[InlineImage]) -> Vec<Attachment> {
+    let mut attachments = Vec::new();
+    let mut parts = vec![parsed];
+    while let Some(part) = parts.pop() {
+        parts.extend(part.subparts.iter().rev());
+        if !part.subparts.is_empty() {
+            continue;
+        }
+        let disposition = part.get_content_disposition();
+        let name = disposition.params.get("filename").or(part.ctype.params.get("name"));
+        let attached = disposition.disposition == mailparse::DispositionType::Attachment
+            || (name.is_some() && !part.ctype.mimetype.starts_with("text/"));
+        let cid = part.headers.get_first_value("Content-ID");
+        let cid = cid.as_deref().map(|c| c.trim().trim_start_matches('<').trim_end_matches('>'));
+        if !attached || images.iter().any(|image| Some(image.content_id.as_str()) == cid) {
+            continue;
+        }
+        let Ok(data) = part.get_body_raw() else {
+            continue;
+        };
+        // Only the last path component, as some clients send the full path
+        let filename = name
+            .and_then(|n| n.rsplit(['/', '\\']).next())
+            .filter(|n| !n.trim().is_empty())
+            .map_or_else(|| format!("attachment{}", attachments.len() + 1), |n| n.trim().to_string());
+        attachments.push(Attachment {
+            filename,
+            content_type: part.ctype.mimetype.clone(),
+            data,
+        });
+    }
+    attachments
+}
+
+// Most messages carry far fewer; this bounds the upload
+const MAX_IMAGES: usize = 10;
+const MAX_IMAGE_BYTES: usize = 8 * 1024 * 1024;
+
+static CID_REF: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?i)cid:([^"'\s>)]+)"#).unwrap());
+static CID_IMG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)<img\b[^>]*?\bsrc\s*=\s*["']cid:([^"']+)["'][^>]*>"#).unwrap());
+
+// The images of multipart/related parts that the HTML references by Content-ID, and the
+// map from Content-ID to attachment name.
+fn inline_images(parsed: &mailparse::ParsedMail) -> (Vec<InlineImage>, HashMap<String, String>) {
+    let Some(html) = find_part(parsed, "text/html").and_then(|p| p.get_body().ok()) else {
+        return (Vec::new(), HashMap::new());
+    };
+    let referenced: Vec<String> = CID_REF.captures_iter(&html).map(|c| c[1].to_string()).collect();
+    if referenced.is_empty() {
+        return (Vec::new(), HashMap::new());
+    }
+
+    let mut images = Vec::new();
+    let mut cids = HashMap::new();
+    let mut total = 0;
+    let mut parts = vec![parsed];
+    while let Some(part) = parts.pop() {
+        parts.extend(part.subparts.iter().rev());
+        let Some(cid) = part.headers.get_first_value("Content-ID") else {
+            continue;
+        };
+        let cid = cid.trim().trim_start_matches('<').trim_end_matches('>').to_string();
+        if !part.ctype.mimetype.starts_with("image/") || !referenced.contains(&cid) || cids.contains_key(&cid) {
+            continue;
+        }
+        let Ok(data) = part.get_body_raw() else {
+            continue;
+        };
+        if images.len() >= MAX_IMAGES || total + data.len() > MAX_IMAGE_BYTES {
+            break;
+        }
+        total += data.len();
+        let extension = part.ctype.mimetype.trim_start_matches("image/").split('+').next().unwrap_or("img").to_string();
+        let filename = format!("inline{}.{}", images.len() + 1, extension);
+        cids.insert(cid.clone(), filename.clone());
+        images.push(InlineImage {
+            filename,
+            content_id: cid,
+            content_type: part.ctype.mimetype.clone(),
+            data,
+        });
+    }
+    (images, cids)
+}
+
+// 0 or 1 pixel images are open-tracking beacons, not content
+pub static TRACKING_PIXEL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)<img\b[^>]*\b(width|height)\s*=\s*["']?[01](px)?\b[^>]*>"#).unwrap()
+});
+static IMG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<img\b[^>]*>").unwrap());
+static REMOTE_SRC: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\bsrc\s*=\s*["'](https?://[^"']+)["']"#).unwrap());
+
+// The `src` of the first `<img>` in the HTML part that loads over HTTP(S) and isn't a
+// tracking pixel, usually the newsletter's hero image
+fn remote_image(parsed: &mailparse::ParsedMail) -> Option<String> {
+    let html = find_part(parsed, "text/html")?.get_body().ok()?;
+    IMG.find_iter(&html)
+        .map(|tag| tag.as_str())
+        .filter(|tag| !TRACKING_PIXEL.is_match(tag))
+        .find_map(|tag| REMOTE_SRC.captures(tag))
+        .map(|caps| caps[1].replace("&amp;", "&"))
+}
+
+// Replaces `<img src="cid:...">` with a text marker naming the attachment, so the markdown
+// says where each image belonged.
+fn mark_cid_images(html: &str, cids: &HashMap<String, String>) -> String {
+    CID_IMG
+        .replace_all(html, |c: &regex::Captures| match cids.get(&c[1]) {
+            Some(filename) => format!("<span>[image: {}]</span>", filename),
+            None => String::new(),
+        })
+        .into_owned()
+}
+
+// First part of the given type, depth first
+fn find_part<'a>(parsed: &'a mailparse::ParsedMail<'a>, mimetype: &str) -> Option<&'a mailparse::ParsedMail<'a>> {
+    if parsed.ctype.mimetype == mimetype && parsed.get_content_disposition().disposition != mailparse::DispositionType::Attachment {
+        return Some(parsed);
+    }
+    parsed.subparts.iter().find_map(|part| find_part(part, mimetype))
+}
+
+// Rejects empty bodies, undecoded base64 blobs and binary junk
+fn readable(body: &str) -> bool {
+    if body.trim().is_empty() {
+        return false;
+    }
+    let total = body.chars().count();
+    let junk = body.chars().filter(|c| *c == '\u{FFFD}' || (c.is_control() && !c.is_whitespace())).count();
+    if junk * 10 > total {
+        return false;
+    }
+    // base64 comes in long unbroken lines of [A-Za-z0-9+/=]
+    let lines: Vec<&str> = body.lines().filter(|l| !l.trim().is_empty()).collect();
+    let encoded = lines
+        .iter()
+        .filter(|l| l.len() >= 60 && !l.contains(' ') && l.chars().all(|c| c.is_ascii_alphanumeric() || "+/=".contains(c)))
+        .count();
+    encoded * 2 <= lines.len()
+}
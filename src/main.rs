@@ -1,181 +1,438 @@
-use mailparse::MailHeaderMap;
-use native_tls::TlsConnector;
-use regex::Regex;
-use serde::Deserialize;
-use std::fs;
+mod archive;
+mod auth;
+mod cadence;
+mod categories;
+mod cluster;
+mod coerce;
+mod config;
+mod confirm;
+mod crypto;
+mod deadletter;
+mod diff;
+mod discord;
+mod discourse;
+mod domains;
+mod emoji;
+mod error;
+mod events;
+mod experiments;
+mod explain;
+mod filters;
+mod folders;
+mod health;
+mod footer;
+mod footnotes;
+#[cfg(test)]
+mod golden;
+mod history;
+mod homeassistant;
+mod inbound;
+mod ingest;
+mod irc;
+mod http;
+mod jmap;
+mod leader;
+mod listcmd;
+mod logging;
+mod loops;
+mod mail;
+mod markdown;
+mod matrix;
+mod metrics;
+mod migrate;
+mod monitor;
+mod mqtt;
+mod notes;
+mod notices;
+mod notify;
+mod ops;
+mod otel;
+mod outbox;
+mod pipeline;
+mod pop3;
+mod reactions;
+mod readlater;
+mod redact;
+mod replay;
+mod resend;
+mod retention;
+mod routes;
+mod samples;
+mod schema;
+mod search;
+mod series;
+mod ses;
+mod shortener;
+mod shutdown;
+mod site;
+mod slack;
+mod server;
+mod snooze;
+mod source;
+mod state;
+mod styles;
+mod subscriptions;
+mod summarize;
+mod telegram;
+mod tls;
+mod trace;
+mod usage;
+mod watchdog;
+mod webarchive;
+mod webhooks;
+mod xmpp;
+mod zulip;
+
+use auth::AuthHealth;
+use clap::{Parser, Subcommand};
+use config::{Config, Protocol};
+use error::Error;
+use leader::Leader;
+use mail::Email;
+use state::StateStore;
 use std::thread;
 use std::time::Duration;
+use tracing::{error, info, warn};
+use watchdog::Watchdog;
 
-#[derive(Deserialize, Clone)]
-struct Config {
-    imap_server: String,
-    imap_port: u16,
-    imap_username: String,
-    imap_password: String,
-    discord_webhook_url: String,
-    ignored_senders: Option<Vec<String>>,
-    ignored_subjects: Option<Vec<String>>,
+#[derive(Parser)]
+#[command(version, about = "Forward newsletters from an IMAP mailbox to Discord")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Configuration file to read (default config.toml, which is optional; environment
+    /// variables can provide everything)
+    #[arg(long, global = true)]
+    config: Option<String>,
+    /// Fetch and render pending mail, print the payloads, and exit without posting or
+    /// changing the mailbox (also `dry_run = true` in the config)
+    #[arg(long, global = true)]
+    dry_run: bool,
 }
 
-fn main() {
-    let config_content = fs::read_to_string("config.toml").expect("Failed to read config.toml");
-    let config: Config = toml::from_str(&config_content).expect("Failed to parse config.toml");
+#[derive(Subcommand)]
+enum Command {
+    /// Monitor the mailbox and forward new emails (default)
+    Run,
+    /// Post a test message to the configured webhook
+    SendTest {
+        /// Render one of the bundled sample emails through the real pipeline
+        #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(samples::names()))]
+        sample: Option<String>,
+    },
+    /// Hold back a route's messages for a while (e.g. `snooze vendor-status 48h`)
+    Snooze {
+        route: String,
+        /// How long to snooze for: minutes, hours or days (`90m`, `48h`, `7d`)
+        duration: String,
+        /// Don't post a summary of the held messages when the snooze expires
+        #[arg(long)]
+        no_summary: bool,
+    },
+    /// End a snooze early
+    Unsnooze { route: String },
+    /// Show which ignore rules and routes would apply to a message, rule by rule
+    Explain {
+        #[arg(long, default_value = "")]
+        from: String,
+        #[arg(long, default_value = "")]
+        subject: String,
+        /// Explain one of the bundled sample emails instead
+        #[arg(long, conflicts_with_all = ["from", "subject"], value_parser = clap::builder::PossibleValuesParser::new(samples::names()))]
+        sample: Option<String>,
+    },
+    /// Email a mailing list's subscribe/unsubscribe/help address, taken from the List-*
+    /// headers of the latest archived email from the sender
+    ListCmd {
+        /// Sender address (or part of it) of an archived newsletter
+        sender: String,
+        #[arg(value_enum)]
+        command: listcmd::ListCommand,
+    },
+    /// Forward older messages from a folder in one pass, leaving the folder untouched
+    Backfill {
+        /// Received on or after: `YYYY-MM-DD` or relative like `-30d`
+        #[arg(long, allow_hyphen_values = true)]
+        since: Option<String>,
+        /// Received before (exclusive), same formats
+        #[arg(long, allow_hyphen_values = true)]
+        before: Option<String>,
+        /// Folder to read, with `/` between levels
+        #[arg(long, default_value = "INBOX")]
+        folder: String,
+        /// Which of `[[accounts]]` to read from
+        #[arg(long)]
+        account: Option<String>,
+    },
+    /// List pending messages and the route each would take, without processing them
+    Peek {
+        #[arg(long, default_value = "INBOX")]
+        folder: String,
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        /// Which of `[[accounts]]` to read from
+        #[arg(long)]
+        account: Option<String>,
+    },
+    /// Re-render archived emails with the current pipeline and report payloads that changed
+    Replay {
+        /// How far back to go: minutes, hours or days (`90m`, `48h`, `30d`)
+        #[arg(long, default_value = "30d")]
+        since: String,
+        #[arg(long, value_enum, default_value = "dry-run")]
+        target: replay::Target,
+        /// Where `--target webhook` posts the new renders
+        #[arg(long)]
+        webhook_url: Option<String>,
+    },
+    /// Rebuild the static archive site in `archive.site_dir` from scratch
+    Site,
+    /// Compare the variants of the routes' experiments (posts, reactions and link clicks) and
+    /// sum up deliveries per sending domain
+    Stats {
+        /// How far back to go: minutes, hours or days (`90m`, `48h`, `30d`)
+        #[arg(long, default_value = "30d")]
+        since: String,
+    },
+    /// Rewrite the config file in the current format: [[accounts]] for the top-level
+    /// mailbox, [[filters]] for the ignore lists, current names for renamed keys
+    MigrateConfig {
+        /// Where to write the result (default: print it)
+        #[arg(long)]
+        output: Option<String>,
+    },
+}
 
-    loop {
-        println!("Connecting to IMAP server {}:{}...", config.imap_server, config.imap_port);
-        if let Err(e) = run_monitor(&config) {
-            eprintln!("Connection lost or error occurred: {}", e);
-            eprintln!("Retrying in 10 seconds...");
-            thread::sleep(Duration::from_secs(10));
+fn main() {
+    let cli = Cli::parse();
+    // Runs before loading, so a config that no longer loads can still be migrated
+    if let Some(Command::MigrateConfig { ref output }) = cli.command {
+        if let Err(e) = migrate::run(cli.config.as_deref().unwrap_or(config::DEFAULT_PATH), output.as_deref()) {
+            eprintln!("Failed to migrate the configuration: {}", e);
+            std::process::exit(1);
         }
+        return;
     }
-}
-
-fn run_monitor(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    let tls = TlsConnector::builder().build()?;
-    let client = imap::connect((&config.imap_server as &str, config.imap_port), &config.imap_server, &tls)?;
-    let mut imap_session = client.login(&config.imap_username, &config.imap_password).map_err(|e| e.0)?;
-
-    println!("Logged in as {}", config.imap_username);
-
-    loop {
-        imap_session.select("INBOX")?;
-
-        // Fetch all messages (including seen ones if we restart, assuming we delete processed ones)
-        let messages = imap_session.search("ALL")?;
-
-        if !messages.is_empty() {
-            println!("Found {} messages", messages.len());
-            
-            // Collect sequence numbers to process
-            let seqs: Vec<u32> = messages.into_iter().collect();
-
-            for seq_num in seqs {
-                // Fetch the message content
-                let fetches = imap_session.fetch(seq_num.to_string(), "RFC822")?;
-                
-                if let Some(msg) = fetches.iter().next() {
-                    let body = msg.body().unwrap_or(&[]);
-                    let parsed = mailparse::parse_mail(body)?;
-
-                    let subject = parsed.headers.get_first_value("Subject").unwrap_or("No Subject".to_string());
-                    let from = parsed.headers.get_first_value("From").unwrap_or("Unknown Sender".to_string());
-
-                    // Check ignore list
-                    let should_ignore = if let Some(ref senders) = config.ignored_senders {
-                        senders.iter().any(|s| from.contains(s))
-                    } else {
-                        false
-                    } || if let Some(ref subjects) = config.ignored_subjects {
-                        subjects.iter().any(|s| subject.contains(s))
-                    } else {
-                        false
-                    };
-
-                    if should_ignore {
-                        println!("Ignored email from: {}, Subject: {}", from, subject);
-                        // Delete ignored emails too, to prevent reprocessing? 
-                        // Or maybe just skip? If we skip, they remain in INBOX and will be fetched again because search is "ALL".
-                        // To avoid infinite loop of fetching ignored emails, we MUST delete them or mark them differently (and change search query).
-                        // Since user said "Sent messages can be deleted", I will assume ignored messages can also be deleted (skipped).
-                        // If this is risky, I could change search to "UNSEEN" and just mark as seen.
-                        // But let's stick to the previous flow: "Process = Delete". Ignoring is a form of processing.
-                        imap_session.store(seq_num.to_string(), "+FLAGS (\\Deleted)")?;
-                        continue;
-                    }
-                    
-                    // Simple body extraction (prioritize text/plain)
-                    let body_content = extract_body(&parsed).unwrap_or("Cannot parse body".to_string());
-
-                    // Truncate body if too long for Discord (limit is 2000 chars)
-                    let display_body = if body_content.len() > 1500 {
-                        let mut end = 1500;
-                        while !body_content.is_char_boundary(end) {
-                            end -= 1;
-                        }
-                        format!("{}...", &body_content[..end])
-                    } else {
-                        body_content
-                    };
-
-                    println!("Processing email: {}", subject);
-
-                    // Send to Discord
-                    let client = reqwest::blocking::Client::new();
-                    let payload = serde_json::json!({
-                        "embeds": [{
-                            "title": subject,
-                            "author": {
-                                "name": from
-                            },
-                            "description": display_body,
-                            "color": 0x5865F2, // Blurple
-                            "timestamp": chrono::Utc::now().to_rfc3339(),
-                            "footer": {
-                                "text": "📰 Newsletter"
-                            }
-                        }]
-                    });
-
-                    let res = client.post(&config.discord_webhook_url).json(&payload).send();
+    let config = Config::load(cli.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Failed to load configuration: {}", e);
+        std::process::exit(1);
+    });
+    logging::init(&config);
+    if config.env_only {
+        info!("{} not found, using environment variables only", config::DEFAULT_PATH);
+    }
+    migrate::warn_deprecated(&config);
+    http::init(config.http.as_ref());
+    otel::init(config.otlp.as_ref());
+    metrics::init(config.metrics_addr.as_deref());
+    confirm::init(config.discord_bot_token.as_deref());
+    mail::init(config.limits.as_ref(), config.render.unwrap_or_default());
+    events::init(config.events.as_ref());
+    let store = state::open(config.state.as_ref()).unwrap_or_else(|e| {
+        eprintln!("Failed to open state store: {}", e);
+        std::process::exit(1);
+    });
 
-                    match res {
-                        Ok(response) => {
-                            if response.status().is_success() {
-                                println!("Sent to Discord. Deleting email...");
-                                imap_session.store(seq_num.to_string(), "+FLAGS (\\Deleted)")?;
-                            } else {
-                                eprintln!("Failed to send to Discord: Status {}", response.status());
-                            }
-                        },
-                        Err(e) => {
-                            eprintln!("Failed to send request to Discord: {}", e);
-                            // Do not delete if failed to send
-                        }
+    let dry_run = cli.dry_run || config.dry_run;
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run if dry_run => {
+            for account in config.accounts() {
+                if let Err(e) = monitor::dry_run(&account) {
+                    eprintln!("Failed to dry-run {}: {}", account.imap_username, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Run => {
+            let store = store.as_ref();
+            let leader = Leader::from_config(&config, store);
+            let leader = leader.as_ref();
+            // One worker per account, each with its own backoff and watchdog
+            let accounts = config.accounts();
+            accounts.iter().for_each(health::register);
+            let watchdogs: Vec<Watchdog> =
+                accounts.iter().map(|a| Watchdog::from_config(a, monitor::POLL_INTERVAL)).collect();
+            shutdown::install();
+            thread::scope(|s| {
+                s.spawn(|| server::run(&config, store));
+                s.spawn(metrics::serve);
+                let workers: Vec<_> = accounts
+                    .iter()
+                    .zip(&watchdogs)
+                    .map(|(account, watchdog)| {
+                        s.spawn(move || watchdog.supervise(account));
+                        s.spawn(move || outbox::run_retries(account, store, leader));
+                        s.spawn(move || run(account, store, leader, watchdog))
+                    })
+                    .collect();
+                s.spawn(|| {
+                    shutdown::wait();
+                    for watchdog in &watchdogs {
+                        watchdog.interrupt_idle();
                     }
+                });
+                for worker in workers {
+                    let _ = worker.join();
                 }
+                // The HTTP server and the supervisors don't return on their own
+                info!("Shut down cleanly");
+                std::process::exit(0);
+            });
+        }
+        Command::SendTest { sample } => {
+            if let Err(e) = send_test(&config, sample.as_deref(), dry_run) {
+                eprintln!("Failed to send test message: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Snooze { route, duration, no_summary } => {
+            if let Err(e) = snooze::snooze(&config, store.as_ref(), &route, &duration, !no_summary) {
+                eprintln!("Failed to snooze: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Unsnooze { route } => {
+            if let Err(e) = snooze::unsnooze(store.as_ref(), &route) {
+                eprintln!("Failed to unsnooze: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::ListCmd { sender, command } => {
+            if let Err(e) = listcmd::run(&config, store.as_ref(), &sender, command) {
+                eprintln!("Failed to send list command: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Backfill { since, before, folder, account } => {
+            let result = config.select_account(account.as_deref()).and_then(|config| {
+                monitor::backfill(&config, store.as_ref(), &folder, since.as_deref(), before.as_deref())
+            });
+            if let Err(e) = result {
+                eprintln!("Failed to backfill: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Peek { folder, limit, account } => {
+            if let Err(e) = config.select_account(account.as_deref()).and_then(|config| monitor::peek(&config, &folder, limit)) {
+                eprintln!("Failed to peek: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Replay { since, target, webhook_url } => {
+            if let Err(e) = replay::run(&config, store.as_ref(), &since, target, webhook_url.as_deref()) {
+                eprintln!("Failed to replay: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Site => {
+            if let Err(e) = site::build(&config, store.as_ref()) {
+                eprintln!("Failed to build the archive site: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Stats { since } => {
+            if let Err(e) = experiments::report(&config, store.as_ref(), &since) {
+                eprintln!("Failed to report stats: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::MigrateConfig { .. } => unreachable!("handled before the config is loaded"),
+        Command::Explain { from, subject, sample } => {
+            let email = match sample {
+                Some(name) => Email::parse(samples::find(&name).unwrap_or_default()),
+                None => Ok(Email::new(subject, from, String::new())),
+            };
+            if let Err(e) = email.map_err(Into::into).and_then(|email| explain::explain(&config, store.as_ref(), &email)) {
+                eprintln!("Failed to explain: {}", e);
+                std::process::exit(1);
             }
-            // Permanently remove deleted messages
-            imap_session.expunge()?;
         }
-
-        // Wait before next check
-        thread::sleep(Duration::from_secs(5));
     }
 }
 
-fn clean_body(body: &str) -> String {
-    // Replace multiple newlines with double newline (max)
-    let re_newlines = Regex::new(r"\n{3,}").unwrap();
-    let body = re_newlines.replace_all(body, "\n\n");
-    
-    // Trim trailing spaces from each line
-    let re_trailing_spaces = Regex::new(r"(?m)[ \t]+$").unwrap();
-    let body = re_trailing_spaces.replace_all(&body, "");
-
-    body.trim().to_string()
+fn run(config: &Config, store: &dyn StateStore, leader: Option<&Leader>, watchdog: &Watchdog) {
+    let mut health = AuthHealth::default();
+    let mut recovered = false;
+    loop {
+        if let Some(leader) = leader {
+            health::standby(config, true);
+            leader.wait();
+            health::standby(config, false);
+        }
+        if shutdown::requested() {
+            return;
+        }
+        if !recovered {
+            outbox::recover(config, store);
+            recovered = true;
+        }
+        let result = match config.protocol() {
+            Protocol::Imap => {
+                let port = config.mail_port();
+                info!("Connecting to IMAP server {}:{} as {}...", config.imap_server, port, config.imap_username);
+                monitor::run_monitor(config, store, leader, watchdog, &mut health)
+            }
+            Protocol::Pop3 => {
+                let port = config.mail_port();
+                info!("Checking POP3 server {}:{} as {}...", config.imap_server, port, config.imap_username);
+                pop3::run_monitor(config, store, leader, watchdog, &mut health)
+            }
+            Protocol::Jmap => {
+                info!("Connecting to JMAP at {} as {}...", config.imap_server, config.imap_username);
+                jmap::run_monitor(config, store, leader, watchdog, &mut health)
+            }
+        };
+        watchdog.detach();
+        if let Err(e) = result {
+            let delay = match e {
+                Error::Auth(ref auth_err) => {
+                    error!("{}", auth_err);
+                    health.record_failure(config, auth_err)
+                }
+                // Neither fixes itself on reconnect, so someone has to be told
+                Error::Config(_) | Error::State(_) => {
+                    ops::alert_once(
+                        config,
+                        &format!("monitor:{}:{}", config.imap_username, e),
+                        "Monitor stopped",
+                        &format!("{}: {}", config.imap_username, e),
+                    );
+                    Duration::from_secs(60)
+                }
+                _ => {
+                    warn!("Connection lost or error occurred ({}): {}", e.kind(), e);
+                    Duration::from_secs(10)
+                }
+            };
+            warn!("Retrying in {} seconds...", delay.as_secs());
+            shutdown::sleep(delay);
+        }
+    }
 }
 
-fn extract_body(parsed: &mailparse::ParsedMail) -> Option<String> {
-    if parsed.ctype.mimetype == "text/plain" {
-        return parsed.get_body().ok().map(|s| clean_body(&s));
-    }
-    
-    // If multipart, search for text/plain
-    for part in &parsed.subparts {
-        if let Some(body) = extract_body(part) {
-            return Some(body);
+fn send_test(config: &Config, sample: Option<&str>, dry_run: bool) -> Result<(), Error> {
+    let email = match sample {
+        Some(name) => {
+            let raw = samples::find(name).ok_or_else(|| Error::Config(format!("Unknown sample: {}", name)))?;
+            let email = Email::parse(raw)?;
+            if monitor::is_ignored(config, &email) {
+                println!("Note: this sample would be ignored by the current filters");
+            }
+            email
         }
-    }
+        None => Email::new(
+            "Test message".to_string(),
+            "newsletter".to_string(),
+            "If you can see this, the webhook is configured correctly.".to_string(),
+        ),
+    };
 
-    // Fallback to text/html if no plain text found (or first part if nothing else)
-    if parsed.ctype.mimetype == "text/html" {
-         if let Ok(html_content) = parsed.get_body() {
-             if let Ok(md) = html2text::from_read(html_content.as_bytes(), 80) {
-                 return Some(clean_body(&md));
-             }
-         }
+    let payload = pipeline::render(config, &email);
+    if dry_run {
+        println!("{}", serde_json::to_string_pretty(&discord::without_files(&payload))?);
+        return Ok(());
     }
-
-    None
+    webhooks::send(config, None, &payload, None)?;
+    println!("Sent test message: {}", email.subject);
+    Ok(())
 }
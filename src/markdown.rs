@@ -0,0 +1,136 @@
+use html2text::render::TextDecorator;
+use regex::Regex;
+use std::sync::LazyLock;
+
+// Put in front of heading lines by the decorator, which can only prefix them, and turned
+// into bold afterwards
+const HEADING: char = '\u{E000}';
+
+// Wide enough that html2text never wraps a line, which would split `](url)` across lines;
+// Discord wraps for the reader anyway
+const WIDTH: usize = 10_000;
+
+// Linked images without alt text come out as `[](url)`: logos and tracking pixels
+static EMPTY_LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[\s*\]\([^)]*\)").unwrap());
+
+// The HTML as Discord markdown: **bold**, *italics*, ~~strikeout~~, `code`, [text](url)
+// links, quotes and lists, with headings as bold lines (Discord's own headings are too loud
+// for a newsletter's section titles).
+pub fn from_html(html: &str) -> Option<String> {
+    // Raw mode reads layout tables (most newsletters are built from them) cell by cell
+    let text = html2text::config::with_decorator(Decorator::default())
+        .raw_mode(true)
+        .string_from_read(html.as_bytes(), WIDTH)
+        .ok()?;
+    let text = EMPTY_LINK.replace_all(&text, "");
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| match line.strip_prefix(HEADING) {
+            Some(heading) if !heading.trim().is_empty() => format!("**{}**", heading.trim()),
+            Some(_) => String::new(),
+            // Headings inside quotes and lists stay plain
+            None => line.replace(HEADING, ""),
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+#[derive(Clone, Default)]
+struct Decorator {
+    // Targets of the links being rendered, innermost last
+    links: Vec<String>,
+}
+
+impl TextDecorator for Decorator {
+    type Annotation = ();
+
+    fn decorate_link_start(&mut self, url: &str) -> (String, ()) {
+        // mailto:, anchors and the like aren't clickable in Discord; their text stays plain
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            self.links.push(String::new());
+            return (String::new(), ());
+        }
+        // Parentheses would end the link early; spaces aren't allowed at all
+        self.links.push(url.replace('(', "%28").replace(')', "%29").replace(' ', "%20"));
+        ("[".to_string(), ())
+    }
+
+    fn decorate_link_end(&mut self) -> String {
+        match self.links.pop() {
+            Some(url) if !url.is_empty() => format!("]({})", url),
+            _ => String::new(),
+        }
+    }
+
+    fn decorate_em_start(&self) -> (String, ()) {
+        ("*".to_string(), ())
+    }
+
+    fn decorate_em_end(&self) -> String {
+        "*".to_string()
+    }
+
+    fn decorate_strong_start(&self) -> (String, ()) {
+        ("**".to_string(), ())
+    }
+
+    fn decorate_strong_end(&self) -> String {
+        "**".to_string()
+    }
+
+    fn decorate_strikeout_start(&self) -> (String, ()) {
+        ("~~".to_string(), ())
+    }
+
+    fn decorate_strikeout_end(&self) -> String {
+        "~~".to_string()
+    }
+
+    fn decorate_code_start(&self) -> (String, ()) {
+        ("`".to_string(), ())
+    }
+
+    fn decorate_code_end(&self) -> String {
+        "`".to_string()
+    }
+
+    fn decorate_preformat_first(&self) {}
+
+    fn decorate_preformat_cont(&self) {}
+
+    fn decorate_image(&mut self, _src: &str, title: &str) -> (String, ()) {
+        (title.to_string(), ())
+    }
+
+    fn header_prefix(&self, _level: usize) -> String {
+        HEADING.to_string()
+    }
+
+    fn quote_prefix(&self) -> String {
+        "> ".to_string()
+    }
+
+    fn unordered_item_prefix(&self) -> String {
+        "- ".to_string()
+    }
+
+    fn ordered_item_prefix(&self, i: i64) -> String {
+        format!("{}. ", i)
+    }
+
+    fn make_subblock_decorator(&self) -> Self {
+        Decorator::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn markdown_render_keeps_links_and_emphasis() {
+        let (html, _) = crate::mail::html_part(crate::samples::find("html-digest").unwrap()).unwrap();
+        let body = super::from_html(&html).unwrap();
+        assert!(body.contains("Here are today's **top stories**, picked for you."));
+        assert!(body.contains("**Markets**\n- [Stocks edge higher as inflation cools](https://morning.example/r/1)"));
+        assert!(body.contains("- *Opinion:* [Why small tools win](https://morning.example/r/4)"));
+    }
+}
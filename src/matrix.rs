@@ -0,0 +1,139 @@
+use crate::config::MatrixConfig;
+use crate::discord::{self, Posted, WebhookError};
+use crate::error::Error;
+use crate::notify;
+use regex::{Captures, Regex};
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+// Events are capped at 64 KiB; the plain and HTML bodies each get a share, in bytes
+const MAX_BODY: usize = 24000;
+
+// Discord's **bold** and [text](url), the markup the renderers produce
+static MARKUP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\*\*(.+?)\*\*|\[([^\]]+)\]\((https?://[^)\s]+)\)").unwrap());
+
+// Posts the payload to the room as an m.room.message with an HTML body, which Element and
+// most clients render with the links and formatting, and the Markdown as the plain fallback.
+// The transaction ID is derived from the message, so a retry after a timeout can't post it
+// twice.
+pub fn send(matrix: &MatrixConfig, room_id: &str, payload: &Value) -> Result<Posted, Error> {
+    let message = json!({
+        "msgtype": "m.text",
+        "body": cut_to(&notify::markdown(payload, true), MAX_BODY),
+        "format": "org.matrix.custom.html",
+        "formatted_body": html(payload),
+    });
+    let txn_id = crate::crypto::hex(&openssl::sha::sha256(format!("{}|{}", room_id, message).as_bytes()));
+    let target = format!("matrix:{}", matrix.homeserver);
+    let post = |message: &Value| post(matrix, room_id, &txn_id, message);
+    discord::send_with("matrix.send", &target, &message, post, |_, _| None)
+}
+
+fn post(matrix: &MatrixConfig, room_id: &str, txn_id: &str, message: &Value) -> Result<Posted, WebhookError> {
+    let network = |e: &dyn std::fmt::Display| WebhookError {
+        status: None,
+        retry_after: None,
+        message: e.to_string(),
+    };
+    let mut url = reqwest::Url::parse(&matrix.homeserver).map_err(|e| network(&e))?;
+    url.path_segments_mut()
+        .map_err(|_| network(&"homeserver is not a base URL"))?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3", "rooms", room_id, "send", "m.room.message", txn_id]);
+    let response = crate::http::client()
+        .put(url)
+        .bearer_auth(&matrix.access_token)
+        .json(message)
+        .send()
+        .map_err(|e| network(&e))?;
+    let status = response.status();
+    let body: Value = response.json().unwrap_or_default();
+    if !status.is_success() {
+        return Err(WebhookError {
+            status: Some(status.as_u16()),
+            retry_after: body["retry_after_ms"].as_u64().map(Duration::from_millis),
+            message: body["error"].as_str().map_or_else(|| status.to_string(), str::to_string),
+        });
+    }
+    Ok(Posted {
+        id: body["event_id"].as_str().unwrap_or_default().to_string(),
+        channel_id: room_id.to_string(),
+    })
+}
+
+// The Discord payload as HTML: per embed, the title as a heading (a link when the embed has
+// a URL), the author in italics, the description, fields and footer. Uploaded images have
+// no URL a client could show and are left out.
+pub fn html(payload: &Value) -> String {
+    let mut blocks = Vec::new();
+    if let Some(content) = payload["content"].as_str().filter(|c| !c.is_empty()) {
+        blocks.push(format!("<p>{}</p>", markup(content)));
+    }
+    let mut budget = MAX_BODY;
+    for embed in payload["embeds"].as_array().into_iter().flatten() {
+        if let Some(title) = embed["title"].as_str() {
+            blocks.push(match embed["url"].as_str() {
+                Some(url) => format!("<h3><a href=\"{}\">{}</a></h3>", escape(url), escape(title)),
+                None => format!("<h3>{}</h3>", escape(title)),
+            });
+        }
+        if let Some(author) = embed["author"]["name"].as_str() {
+            blocks.push(format!("<p><em>{}</em></p>", escape(author)));
+        }
+        if let Some(description) = embed["description"].as_str().filter(|d| !d.is_empty()) {
+            // Cut before converting, so no tag is left open
+            let description = cut_to(description, budget / 2);
+            budget -= description.len();
+            blocks.push(format!("<p>{}</p>", markup(&description)));
+        }
+        for field in embed["fields"].as_array().into_iter().flatten() {
+            let name = escape(field["name"].as_str().unwrap_or_default());
+            let value = markup(field["value"].as_str().unwrap_or_default());
+            blocks.push(format!("<p><strong>{}</strong><br>{}</p>", name, value));
+        }
+        if let Some(footer) = embed["footer"]["text"].as_str() {
+            blocks.push(format!("<p><em>{}</em></p>", escape(footer)));
+        }
+    }
+    blocks.join("\n")
+}
+
+// Escaped text with **bold** and [text](url) as tags, and line breaks kept
+fn markup(text: &str) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for caps in MARKUP.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        out.push_str(&escape(&text[last..whole.start()]));
+        out.push_str(&tag(&caps));
+        last = whole.end();
+    }
+    out.push_str(&escape(&text[last..]));
+    out.replace('\n', "<br>\n")
+}
+
+fn tag(caps: &Captures) -> String {
+    match (caps.get(1), caps.get(2), caps.get(3)) {
+        (Some(bold), _, _) => format!("<strong>{}</strong>", escape(bold.as_str())),
+        (_, Some(text), Some(url)) => format!("<a href=\"{}\">{}</a>", escape(url.as_str()), escape(text.as_str())),
+        _ => escape(&caps[0]),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// At most `max` bytes, on a character boundary
+fn cut_to(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_string();
+    }
+    let mut end = max.saturating_sub(3);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
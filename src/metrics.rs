@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use tiny_http::{Header, Response, Server};
+use tracing::{error, info};
+
+// Upper bounds (seconds) of the webhook latency buckets
+const BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+// Name and label values; each metric has at most one label
+type Key = (&'static str, Option<(&'static str, String)>);
+
+#[derive(Default)]
+struct Histogram {
+    // Per bucket, not cumulative; the +Inf bucket is `count`
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+struct Registry {
+    addr: String,
+    counters: Mutex<BTreeMap<Key, u64>>,
+    histograms: Mutex<BTreeMap<Key, Histogram>>,
+}
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+// Prometheus metrics on `metrics_addr`, for alerting when deliveries stop. Nothing is
+// collected without it.
+pub fn init(addr: Option<&str>) {
+    if let Some(addr) = addr {
+        let _ = REGISTRY.set(Registry {
+            addr: addr.to_string(),
+            counters: Mutex::new(BTreeMap::new()),
+            histograms: Mutex::new(BTreeMap::new()),
+        });
+    }
+}
+
+pub fn count(name: &'static str, label: Option<(&'static str, &str)>) {
+    let Some(registry) = REGISTRY.get() else {
+        return;
+    };
+    let key = (name, label.map(|(k, v)| (k, v.to_string())));
+    *registry.counters.lock().unwrap().entry(key).or_insert(0) += 1;
+}
+
+pub fn observe(name: &'static str, label: Option<(&'static str, &str)>, seconds: f64) {
+    let Some(registry) = REGISTRY.get() else {
+        return;
+    };
+    let key = (name, label.map(|(k, v)| (k, v.to_string())));
+    let mut histograms = registry.histograms.lock().unwrap();
+    let histogram = histograms.entry(key).or_default();
+    histogram.buckets.resize(BUCKETS.len(), 0);
+    if let Some(i) = BUCKETS.iter().position(|&le| seconds <= le) {
+        histogram.buckets[i] += 1;
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+}
+
+// Serves GET /metrics (any path, really) until the process exits
+pub fn serve() {
+    let Some(registry) = REGISTRY.get() else {
+        return;
+    };
+    let server = match Server::http(&registry.addr) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to start the metrics listener on {}: {}", registry.addr, e);
+            return;
+        }
+    };
+    info!("Metrics on http://{}/metrics", registry.addr);
+    let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+    for request in server.incoming_requests() {
+        let response = Response::from_string(render(registry)).with_header(content_type.clone());
+        if let Err(e) = request.respond(response) {
+            error!("Failed to send metrics: {}", e);
+        }
+    }
+}
+
+fn render(registry: &Registry) -> String {
+    let mut out = String::new();
+    let mut last = "";
+    for ((name, label), value) in registry.counters.lock().unwrap().iter() {
+        if *name != last {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            last = name;
+        }
+        let _ = writeln!(out, "{}{} {}", name, labels(label, None), value);
+    }
+    for ((name, label), histogram) in registry.histograms.lock().unwrap().iter() {
+        if *name != last {
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            last = name;
+        }
+        let mut cumulative = 0;
+        for (le, n) in BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += n;
+            let _ = writeln!(out, "{}_bucket{} {}", name, labels(label, Some(&le.to_string())), cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{} {}", name, labels(label, Some("+Inf")), histogram.count);
+        let _ = writeln!(out, "{}_sum{} {}", name, labels(label, None), histogram.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels(label, None), histogram.count);
+    }
+    let polls = crate::health::polls();
+    if !polls.is_empty() {
+        let _ = writeln!(out, "# HELP newsletter_seconds_since_last_poll Time since the mailbox was last checked");
+        let _ = writeln!(out, "# TYPE newsletter_seconds_since_last_poll gauge");
+    }
+    for (account, at) in polls {
+        let label = Some(("account", account));
+        let _ = writeln!(out, "newsletter_seconds_since_last_poll{} {:.3}", labels(&label, None), at.elapsed().as_secs_f64());
+    }
+    out
+}
+
+fn labels(label: &Option<(&'static str, String)>, le: Option<&str>) -> String {
+    let escape = |v: &str| v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    let mut pairs: Vec<String> = label.iter().map(|(k, v)| format!("{}=\"{}\"", k, escape(v))).collect();
+    pairs.extend(le.map(|le| format!("le=\"{}\"", le)));
+    if pairs.is_empty() { String::new() } else { format!("{{{}}}", pairs.join(",")) }
+}
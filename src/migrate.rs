@@ -0,0 +1,114 @@
+use crate::config::Config;
+use crate::error::Error;
+use std::fs;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Key, Table, value};
+use tracing::warn;
+
+// Keys that still load but have a newer form; `migrate-config` rewrites them
+pub const DEPRECATED: &[(&str, &str)] = &[
+    ("webhook_url", "discord_webhook_url"),
+    ("ignored_senders", "[[filters]] with field = \"from\""),
+    ("ignored_subjects", "[[filters]] with field = \"subject\""),
+];
+
+// The single mailbox at the top level, which [[accounts]] replaces
+const ACCOUNT_KEYS: &[&str] = &["imap_server", "imap_port", "imap_username", "imap_password"];
+
+pub fn warn_deprecated(config: &Config) {
+    for key in &config.deprecated {
+        if let Some((_, instead)) = DEPRECATED.iter().find(|(k, _)| k == key) {
+            warn!("{} is deprecated, use {} instead (`newsletter migrate-config` rewrites it)", key, instead);
+        }
+    }
+}
+
+// Reads a config file and writes it in the current format, to `output` or stdout. Comments
+// and everything that didn't change are kept as they are.
+pub fn run(path: &str, output: Option<&str>) -> Result<(), Error> {
+    let content = fs::read_to_string(path)?;
+    let mut doc: DocumentMut =
+        content.parse().map_err(|e| Error::Config(format!("Failed to parse {}: {}", path, e)))?;
+    let mut notes = Vec::new();
+
+    // webhook_url -> discord_webhook_url, keeping its place and comments
+    if let Some((key, item)) = doc.remove_entry("webhook_url") {
+        if doc.contains_key("discord_webhook_url") {
+            notes.push("webhook_url was dropped: discord_webhook_url is set too and wins".to_string());
+        } else {
+            let renamed = Key::new("discord_webhook_url").with_leaf_decor(key.leaf_decor().clone());
+            doc.insert_formatted(&renamed, item);
+        }
+    }
+
+    // The ignore lists become ignore filters, after any existing ones, which were checked
+    // first before too
+    let mut filters = Vec::new();
+    for (key, field) in [("ignored_senders", "from"), ("ignored_subjects", "subject")] {
+        let Some(item) = doc.remove(key) else {
+            continue;
+        };
+        for pattern in item.as_array().into_iter().flatten().filter_map(|v| v.as_str()) {
+            let mut filter = Table::new();
+            filter.insert("field", value(field));
+            filter.insert("value", value(pattern));
+            filter.insert("action", value("ignore"));
+            filters.push(filter);
+        }
+    }
+    if let Some(first) = filters.first_mut() {
+        first.decor_mut().set_prefix(
+            "\n# From ignored_senders and ignored_subjects. Like every `contains` filter, these now\n\
+             # match case-insensitively.\n",
+        );
+        let existing = doc.entry("filters").or_insert(Item::ArrayOfTables(ArrayOfTables::new()));
+        let Some(existing) = existing.as_array_of_tables_mut() else {
+            return Err(Error::config("filters is not a list of [[filters]] tables"));
+        };
+        for filter in filters {
+            existing.push(filter);
+        }
+    }
+
+    // The top-level mailbox becomes the first of [[accounts]], unless there are some already
+    let has_accounts = doc.get("accounts").and_then(Item::as_array_of_tables).is_some_and(|a| !a.is_empty());
+    if !has_accounts && doc.contains_key("imap_server") {
+        let mut account = Table::new();
+        let username = doc.get("imap_username").and_then(Item::as_str).unwrap_or_default();
+        let name = username.split('@').next().filter(|n| !n.is_empty()).unwrap_or("main").to_string();
+        account.insert("name", value(name));
+        for key in ACCOUNT_KEYS {
+            if let Some(item) = doc.remove(key) {
+                account.insert(key, item);
+            }
+        }
+        // IMAP_PASSWORD and imap_password_file only fill in the top-level password
+        if doc.remove("imap_password_file").is_some() || !account.contains_key("imap_password") {
+            notes.push(
+                "IMAP_PASSWORD and imap_password_file don't apply to [[accounts]]; set \
+                 NEWSLETTER_ACCOUNTS_0__IMAP_PASSWORD or the account's imap_password"
+                    .to_string(),
+            );
+        }
+        account.decor_mut().set_prefix("\n# The mailbox that was configured with the top-level imap_* keys\n");
+        let mut accounts = ArrayOfTables::new();
+        accounts.push(account);
+        doc.insert("accounts", Item::ArrayOfTables(accounts));
+    }
+
+    let mut migrated = format!("# Migrated from {} by `newsletter migrate-config`\n", path);
+    for note in &notes {
+        migrated.push_str(&format!("# NOTE: {}\n", note));
+    }
+    migrated.push_str(&doc.to_string());
+    match output {
+        Some(output) => {
+            fs::write(output, migrated)?;
+            println!("Wrote {}", output);
+        }
+        None => print!("{}", migrated),
+    }
+    for note in notes {
+        eprintln!("Note: {}", note);
+    }
+    Ok(())
+}
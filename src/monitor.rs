@@ -0,0 +1,806 @@
+use crate::auth::{self, AuthHealth};
+use crate::{cadence, categories, cluster, events, ops, otel, pipeline, search, shutdown, snooze, source, tls, trace, webhooks};
+use crate::config::{AutoReplyAction, CatchupConfig, CatchupOrder, Config, FilterAction, Mode, ObserveFrom, ProcessingMode, Protocol};
+use crate::error::Error;
+use crate::folders::{self, Folders};
+use crate::history::{self, Status};
+use crate::leader::Leader;
+use crate::mail::Email;
+use crate::retention::Pruner;
+use crate::state::StateStore;
+use crate::watchdog::Watchdog;
+use native_tls::{TlsConnector, TlsStream};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeSet, HashMap};
+use std::net::TcpStream;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+// Fetched messages paired with their sequence numbers (UIDs in observer mode)
+pub type Batch = Vec<(u32, Email)>;
+
+type Session = imap::Session<TlsStream<TcpStream>>;
+
+const WATERMARK_PREFIX: &str = "uid:";
+
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// How often folders other than the one in IDLE are checked
+const FOLDER_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+// Highest UID handled in observer mode. A UIDVALIDITY change means the UIDs were reassigned,
+// so the mark starts over, from the current end of the mailbox or (observe_from = "oldest")
+// from its first message.
+#[derive(Serialize, Deserialize)]
+struct Watermark {
+    uid_validity: u32,
+    last_uid: u32,
+    #[serde(skip)]
+    folder: String,
+}
+
+impl Watermark {
+    // `uid:<folder>`, or `uid:<account>:<folder>` with several accounts
+    fn key(config: &Config, folder: &str) -> String {
+        match config.account {
+            Some(ref account) => format!("{}{}:{}", WATERMARK_PREFIX, account, folder),
+            None => format!("{}{}", WATERMARK_PREFIX, folder),
+        }
+    }
+
+    fn load(
+        config: &Config,
+        store: &dyn StateStore,
+        folder: &str,
+        uid_validity: Option<u32>,
+        uid_next: Option<u32>,
+    ) -> Result<Watermark, Error> {
+        let uid_validity = uid_validity.ok_or_else(|| Error::imap("Server did not report UIDVALIDITY"))?;
+        if let Some(mut mark) = store.get_json::<Watermark>(&Watermark::key(config, folder))?
+            && mark.uid_validity == uid_validity
+        {
+            mark.folder = folder.to_string();
+            return Ok(mark);
+        }
+        let last_uid = match config.observe_from.unwrap_or_default() {
+            ObserveFrom::Newest => uid_next.unwrap_or(1).saturating_sub(1),
+            ObserveFrom::Oldest => 0,
+        };
+        let mark = Watermark { uid_validity, last_uid, folder: folder.to_string() };
+        info!("Observing {} from UID {} (UIDVALIDITY {})", folder, mark.last_uid + 1, uid_validity);
+        mark.save(config, store)?;
+        Ok(mark)
+    }
+
+    fn save(&self, config: &Config, store: &dyn StateStore) -> Result<(), Error> {
+        store.put_json(&Watermark::key(config, &self.folder), self)
+    }
+}
+
+fn connect(
+    config: &Config,
+    watchdog: Option<&Watchdog>,
+) -> Result<imap::Client<TlsStream<TcpStream>>, Error> {
+    // The commands that only work over IMAP (peek, backfill, dry-run) come through here too
+    if config.protocol() != Protocol::Imap {
+        let protocol = config.protocol().as_str();
+        return Err(Error::Config(format!("This needs IMAP, and the mailbox is set up with protocol = {:?}", protocol)));
+    }
+    let mut span = otel::span("imap.connect");
+    if let Some(span) = span.as_mut() {
+        span.attr("server.address", &config.imap_server);
+    }
+    let mut client = imap::Client::new(tls_connect(config, watchdog)?);
+    client.read_greeting()?;
+    Ok(client)
+}
+
+// A TLS connection to the mail server (imap_server and the protocol's port), with the key
+// checked against imap_pinned_keys
+pub fn tls_connect(config: &Config, watchdog: Option<&Watchdog>) -> Result<TlsStream<TcpStream>, Error> {
+    let connector = TlsConnector::builder().build()?;
+    let tcp = TcpStream::connect((&config.imap_server as &str, config.mail_port()))?;
+    if let Some(watchdog) = watchdog {
+        watchdog.attach(&tcp);
+    }
+    let stream = connector.connect(&config.imap_server, tcp)?;
+
+    // Check the pin before anything (including the password) is sent over the connection
+    if let Some(ref pins) = config.imap_pinned_keys {
+        let actual = tls::spki_sha256(&stream)?;
+        if !tls::pin_matches(pins, &actual) {
+            ops::alert_once(
+                config,
+                &format!("pin:{}", actual),
+                "IMAP certificate pin mismatch",
+                &format!(
+                    "{} presented key sha256/{} which matches none of the configured pins. Refusing to log in.",
+                    config.imap_server, actual
+                ),
+            );
+            return Err(Error::imap("IMAP server key does not match imap_pinned_keys"));
+        }
+    }
+    Ok(stream)
+}
+
+// The IMAP worker. Not a source::MailSource: see there for what IMAP does differently.
+pub fn run_monitor(
+    config: &Config,
+    store: &dyn StateStore,
+    leader: Option<&Leader>,
+    watchdog: &Watchdog,
+    health: &mut AuthHealth,
+) -> Result<(), Error> {
+    let client = connect(config, Some(watchdog))?;
+    let mut login_span = otel::span("imap.login");
+    let login = auth::login(config, client);
+
+    if let (Some(span), Err(e)) = (login_span.as_mut(), &login) {
+        span.fail(e);
+    }
+    drop(login_span);
+    let mut imap_session = login?;
+
+    info!("Logged in as {}", config.imap_username);
+    health.record_success(config);
+    crate::health::logged_in(config);
+
+    let idle = idle_interval(config, &mut imap_session, leader)?;
+    if let Some(interval) = idle {
+        info!("Waiting for new mail with IDLE, renewed every {} seconds", interval.as_secs());
+    }
+
+    let observe = config.mode.unwrap_or_default() == Mode::Observe;
+    if observe {
+        info!("Observer mode: the mailbox is opened read-only and never modified");
+    }
+
+    let processing = config.processing_mode();
+
+    // Folders are only ever created or written to outside observer mode
+    let folders = Folders::discover(&mut imap_session)?;
+    let archive_folder = match config.archive_folder {
+        Some(ref folder) if !observe && processing == ProcessingMode::Move => folders::ensure(
+            config,
+            &mut imap_session,
+            &folders,
+            folder,
+            config.archive_folder_fallback.as_deref(),
+        ),
+        _ => None,
+    };
+
+    // Configured name (for the embed footer) and the server's name for it
+    let monitored: Vec<(String, String)> = config.folders().into_iter().map(|f| (f.clone(), folders.resolve(&f))).collect();
+
+    // The first batch after connecting is whatever piled up while we were away
+    let mut catching_up = true;
+    let mut pruner = Pruner::default();
+    let mut watcher = cadence::Watcher::default();
+
+    loop {
+        // Message ids are sequence numbers, or UIDs above the high-water mark in observer
+        // mode, where nothing is deleted and "ALL" would return everything again. Relative
+        // search dates move with the clock, so the criteria are rebuilt every cycle.
+        let search = config.search.clone().unwrap_or_default();
+        let criteria = search::criteria(search.since.as_deref(), search.before.as_deref()).map_err(Error::Config)?;
+        let limits = config.limits.clone().unwrap_or_default();
+        // max_messages_per_cycle is shared by the folders, in the order they are listed
+        let mut budget = limits.max_messages_per_cycle;
+        let mut more_pending = false;
+        let mut left_over = false;
+
+        for (name, folder) in &monitored {
+            // Renewed per folder too, as a batch stops where the lease was lost
+            if let Some(leader) = leader {
+                leader.renew()?;
+            }
+            let (mut messages, mut mark) = if observe {
+                let mailbox = imap_session.examine(folder)?;
+                let mark = Watermark::load(config, store, folder, mailbox.uid_validity, mailbox.uid_next)?;
+                let _span = otel::span("imap.search");
+                let mut uids: Vec<u32> = imap_session
+                    .uid_search(format!("UID {}:* {}", mark.last_uid + 1, criteria))?
+                    .into_iter()
+                    // `n:*` always includes the newest message, even when its UID is below n
+                    .filter(|uid| *uid > mark.last_uid)
+                    .collect();
+                uids.sort();
+                (uids, Some(mark))
+            } else {
+                imap_session.select(folder)?;
+                // Handled messages are gone, or marked \Seen in mark_seen mode
+                let criteria = match processing {
+                    ProcessingMode::MarkSeen if criteria == "ALL" => "UNSEEN".to_string(),
+                    ProcessingMode::MarkSeen => format!("UNSEEN {}", criteria),
+                    _ => criteria.clone(),
+                };
+                let _span = otel::span("imap.search");
+                let mut seqs: Vec<u32> = imap_session.search(&criteria)?.into_iter().collect();
+                seqs.sort();
+                (seqs, None)
+            };
+            let mut done = BTreeSet::new();
+            // Trace IDs of the messages fetched, for the events once they are removed
+            let mut traces = HashMap::new();
+
+            if let Some(max) = budget {
+                more_pending |= messages.len() > max;
+                messages.truncate(max);
+                budget = Some(max - messages.len());
+            }
+
+            if !messages.is_empty() {
+                info!("Found {} messages in {} for {}", messages.len(), folder, config.imap_username);
+                let sizes = match limits.max_message_size {
+                    Some(max) => oversized(&mut imap_session, &messages, observe, max)?,
+                    None => HashMap::new(),
+                };
+
+                let mut emails = Vec::new();
+                for &id in &messages {
+                    // Stop after the message in hand; the rest stay in the mailbox for next time
+                    if shutdown::requested() {
+                        break;
+                    }
+                    // Fetch the message content; BODY.PEEK leaves \Seen alone in observer and
+                    // mark_seen mode, where it is only set once the message is handled. Only the
+                    // headers of oversized messages are downloaded.
+                    let size = sizes.get(&id).copied();
+                    let query = match size {
+                        Some(_) => "BODY.PEEK[HEADER]",
+                        None if observe || processing == ProcessingMode::MarkSeen => "BODY.PEEK[]",
+                        None => "RFC822",
+                    };
+                    let fetches = {
+                        let _span = otel::span("imap.fetch");
+                        if observe {
+                            imap_session.uid_fetch(id.to_string(), query)?
+                        } else {
+                            imap_session.fetch(id.to_string(), query)?
+                        }
+                    };
+
+                    if let Some(msg) = fetches.iter().next() {
+                        let mut email = match size {
+                            Some(size) => headers_only(msg.header().unwrap_or(&[]), size, limits.max_message_size)?,
+                            None => Email::parse(msg.body().unwrap_or(&[]))?,
+                        };
+                        // Only worth showing when there's more than INBOX to come from
+                        if config.folders.is_some() {
+                            email.folder = Some(name.clone());
+                        }
+                        let _trace = trace::enter(&email).uid(id);
+                        info!("Fetched message {} from {}", id, email.from);
+                        events::emit(
+                            "fetched",
+                            &email,
+                            json!({ "source": "imap", "account": config.account, "folder": name, "id": id }),
+                        );
+                        traces.insert(id, email.trace_id.clone());
+
+                        // Restarting from the oldest message (see Watermark) sees handled mail again
+                        if observe
+                            && history::get(store, &email.trace_id)?
+                                .is_some_and(|h| matches!(h.status, Status::Delivered | Status::Updated | Status::Duplicate))
+                        {
+                            info!("Already handled, skipping");
+                            done.insert(id);
+                            continue;
+                        }
+
+                        if source::screen(config, store, &email, size.map(u64::from))? {
+                            done.insert(id);
+                            continue;
+                        }
+                        emails.push((id, email));
+                    } else {
+                        // Gone between SEARCH and FETCH
+                        done.insert(id);
+                    }
+                }
+
+                deliver_batch(config, store, leader, emails, catching_up, &mut done)?;
+
+                match mark {
+                    Some(ref mut mark) => {
+                        // Only advance past a contiguous run of handled messages, so a failed
+                        // delivery is retried next cycle
+                        for id in messages.iter().take_while(|id| done.contains(id)) {
+                            mark.last_uid = *id;
+                        }
+                        mark.save(config, store)?;
+                    }
+                    None if processing == ProcessingMode::MarkSeen => {
+                        for id in &done {
+                            imap_session.store(id.to_string(), "+FLAGS (\\Seen)")?;
+                            if let Some(trace_id) = traces.get(id) {
+                                events::emit_trace("deleted", trace_id, json!({ "action": "mark_seen" }));
+                            }
+                        }
+                    }
+                    None => {
+                        for id in &done {
+                            // The message was handled either way; a failed copy only loses the archive copy
+                            if let Some(ref folder) = archive_folder
+                                && let Err(e) = imap_session.copy(id.to_string(), folders::quote(folder))
+                            {
+                                ops::alert_once(
+                                    config,
+                                    &format!("folder-copy:{}", folder),
+                                    "IMAP folder copy failed",
+                                    &format!("Could not copy messages to {}: {}", folder, e),
+                                );
+                            }
+                            imap_session.store(id.to_string(), "+FLAGS (\\Deleted)")?;
+                        }
+                        // Permanently remove deleted messages
+                        imap_session.expunge()?;
+                        let action = if archive_folder.is_some() { "move" } else { "delete" };
+                        for trace_id in done.iter().filter_map(|id| traces.get(id)) {
+                            events::emit_trace("deleted", trace_id, json!({ "action": action }));
+                        }
+                    }
+                }
+            }
+            left_over |= done.len() < messages.len();
+        }
+        // A backlog cut short by max_messages_per_cycle is still being caught up on
+        catching_up = catching_up && more_pending;
+
+        housekeeping(config, store, &mut pruner, &mut watcher);
+        watchdog.beat();
+        crate::health::polled(config);
+
+        // Wait before next check. Anything left over (a failed or paced delivery, the rest
+        // of a capped backlog) is retried on the polling schedule rather than at the next
+        // mailbox change.
+        match idle {
+            Some(interval) if !left_over && !more_pending => {
+                // IDLE watches the first folder; the others are polled when it times out
+                let interval = match monitored.len() {
+                    1 => interval,
+                    _ => {
+                        if observe {
+                            imap_session.examine(&monitored[0].1)?;
+                        } else {
+                            imap_session.select(&monitored[0].1)?;
+                        }
+                        interval.min(FOLDER_POLL_INTERVAL)
+                    }
+                };
+                watchdog.beat_after(interval);
+                // Checked after setting the flag, so a shutdown is either seen here or
+                // interrupts the wait
+                watchdog.set_idling(true);
+                let result = match shutdown::requested() {
+                    true => Ok(()),
+                    false => {
+                        let _span = otel::span("imap.idle");
+                        imap_session.idle().and_then(|idle| idle.wait_with_timeout(interval)).map(drop)
+                    }
+                };
+                watchdog.set_idling(false);
+                if !shutdown::requested() {
+                    result?;
+                }
+            }
+            _ => {
+                shutdown::sleep(POLL_INTERVAL);
+            }
+        }
+
+        if shutdown::requested() {
+            info!("Shutting down, logging out of {}", config.imap_server);
+            // After an interrupted IDLE the reply can't be read, but LOGOUT is still sent
+            let _ = imap_session.logout();
+            return Ok(());
+        }
+    }
+}
+
+// Posts the fetched messages in order, or (when catching up) the older ones as one digest.
+// The ids of those handled, and so safe to remove, are added to `done`. A post can take
+// minutes (rate limits, summaries), far longer than a leader lease, so the lease is renewed
+// before each one; once it's lost, the rest are left in place for the new leader.
+pub fn deliver_batch(
+    config: &Config,
+    store: &dyn StateStore,
+    leader: Option<&Leader>,
+    mut emails: Batch,
+    catching_up: bool,
+    done: &mut BTreeSet<u32>,
+) -> Result<(), Error> {
+    let catchup = config.catchup.clone().unwrap_or_default();
+    sort_emails(&mut emails, catchup.order.unwrap_or_default());
+
+    if catching_up {
+        let (digest, individual) = split_catchup(emails, &catchup);
+        if !digest.is_empty() && leading(leader)? {
+            info!("Collapsing {} older messages into a catch-up digest", digest.len());
+            let refs: Vec<&Email> = digest.iter().map(|(_, email)| email).collect();
+            let title = format!("📬 Catch-up: {} earlier messages", refs.len());
+            match webhooks::send(config, None, &cluster::digest_payload(config, store, &title, &refs), None) {
+                Ok(_) => {
+                    for (id, email) in &digest {
+                        let _trace = trace::enter(email).uid(*id);
+                        history::record(store, email, Status::Digested, None);
+                        events::emit("filtered", email, json!({ "reason": "catchup_digest" }));
+                        cadence::observe(config, store, email);
+                        done.insert(*id);
+                    }
+                }
+                Err(e) => error!("Failed to send catch-up digest to Discord: {}", e),
+            }
+        }
+        emails = individual;
+    }
+
+    for (id, email) in emails {
+        if shutdown::requested() || !leading(leader)? {
+            break;
+        }
+        let _trace = trace::enter(&email).uid(id);
+        // Do not delete if failed to send
+        if pipeline::deliver(config, store, &email)? {
+            done.insert(id);
+        }
+    }
+    Ok(())
+}
+
+// Renews the lease, if there is one. False when another instance took it over.
+fn leading(leader: Option<&Leader>) -> Result<bool, Error> {
+    match leader.map(Leader::renew) {
+        Some(Err(Error::LostLeadership(e))) => {
+            warn!("{}, leaving the rest of the batch", e);
+            Ok(false)
+        }
+        Some(Err(e)) => Err(e),
+        _ => Ok(true),
+    }
+}
+
+// Snoozes, category digests, retention and cadence checks, done by the first account only
+pub fn housekeeping(config: &Config, store: &dyn StateStore, pruner: &mut Pruner, watcher: &mut cadence::Watcher) {
+    if config.runs_housekeeping() {
+        if let Err(e) = snooze::flush_expired(config, store) {
+            error!("Failed to process expired snoozes: {}", e);
+        }
+        if let Err(e) = categories::flush_due(config, store) {
+            error!("Failed to post category digests: {}", e);
+        }
+        pruner.maybe_run(config, store);
+        watcher.maybe_run(config, store);
+    }
+}
+
+// How long to wait in IDLE between cycles, or None to poll: IDLE is turned off or the server
+// doesn't advertise it. The wait is kept short enough to renew a leader lease in time.
+fn idle_interval(config: &Config, session: &mut Session, leader: Option<&Leader>) -> Result<Option<Duration>, Error> {
+    let settings = config.idle.clone().unwrap_or_default();
+    if !settings.enabled() {
+        return Ok(None);
+    }
+    if !session.capabilities()?.has_str("IDLE") {
+        info!("{} does not support IDLE, polling every {} seconds", config.imap_server, POLL_INTERVAL.as_secs());
+        return Ok(None);
+    }
+    let mut interval = Duration::from_secs(settings.renew_minutes() * 60);
+    if let Some(leader) = leader {
+        interval = interval.min(leader.renew_interval());
+    }
+    Ok(Some(interval))
+}
+
+// Sizes of the messages over `max` bytes, by sequence number (UID in observer mode)
+fn oversized(session: &mut Session, ids: &[u32], uid: bool, max: u64) -> Result<HashMap<u32, u32>, Error> {
+    let set: Vec<String> = ids.iter().map(u32::to_string).collect();
+    let fetches = if uid {
+        session.uid_fetch(set.join(","), "RFC822.SIZE")?
+    } else {
+        session.fetch(set.join(","), "RFC822.SIZE")?
+    };
+    Ok(fetches
+        .iter()
+        .filter_map(|f| Some((if uid { f.uid? } else { f.message }, f.size?)))
+        .filter(|(_, size)| u64::from(*size) > max)
+        .collect())
+}
+
+// Stands in for a message too large to download
+pub fn headers_only(header: &[u8], size: u32, max: Option<u64>) -> Result<Email, Error> {
+    let mut email = Email::parse(header)?;
+    email.body = format!(
+        "(This email is {} KB, over the {} KB limit, so only its headers were fetched.)",
+        size.div_ceil(1024),
+        max.unwrap_or_default().div_ceil(1024)
+    );
+    email.body_source = "headers only";
+    Ok(email)
+}
+
+// Forwards older mail from any folder in one pass, e.g. after adding a route. The folder is
+// opened read-only and messages already delivered according to the history are skipped.
+pub fn backfill(
+    config: &Config,
+    store: &dyn StateStore,
+    folder: &str,
+    since: Option<&str>,
+    before: Option<&str>,
+) -> Result<(), Error> {
+    let criteria = search::criteria(since, before).map_err(Error::Config)?;
+    let mut imap_session = auth::login(config, connect(config, None)?)?;
+    let folders = Folders::discover(&mut imap_session)?;
+    let mailbox = folders.resolve(folder);
+    imap_session.examine(&mailbox)?;
+
+    let mut uids: Vec<u32> = imap_session.uid_search(&criteria)?.into_iter().collect();
+    uids.sort();
+    info!("Backfilling {} messages from {} ({})", uids.len(), mailbox, criteria);
+
+    let (mut delivered, mut skipped, mut failed) = (0, 0, 0);
+    for uid in uids {
+        let fetches = imap_session.uid_fetch(uid.to_string(), "BODY.PEEK[]")?;
+        let Some(msg) = fetches.iter().next() else {
+            continue;
+        };
+        let email = Email::parse(msg.body().unwrap_or(&[]))?;
+        let _trace = trace::enter(&email).uid(uid);
+        events::emit("fetched", &email, json!({ "source": "backfill", "folder": mailbox, "uid": uid }));
+        let already = history::get(store, &email.trace_id)?
+            .is_some_and(|h| matches!(h.status, Status::Delivered | Status::Updated | Status::Duplicate));
+        if already || pipeline::screen(config, store, &email)? {
+            skipped += 1;
+            continue;
+        }
+        if pipeline::deliver(config, store, &email)? {
+            delivered += 1;
+        } else {
+            failed += 1;
+        }
+    }
+    info!("Backfill done: {} delivered, {} skipped, {} failed", delivered, skipped, failed);
+    imap_session.logout()?;
+    Ok(())
+}
+
+// Lists what's waiting in a folder and where it would go, without touching anything: only
+// headers are fetched, with BODY.PEEK so \Seen isn't set.
+pub fn peek(config: &Config, folder: &str, limit: usize) -> Result<(), Error> {
+    let mut imap_session = auth::login(config, connect(config, None)?)?;
+    let folders = Folders::discover(&mut imap_session)?;
+    let mailbox = folders.resolve(folder);
+    imap_session.examine(&mailbox)?;
+
+    let search = config.search.clone().unwrap_or_default();
+    let criteria = search::criteria(search.since.as_deref(), search.before.as_deref()).map_err(Error::Config)?;
+    let mut uids: Vec<u32> = imap_session.uid_search(&criteria)?.into_iter().collect();
+    uids.sort();
+    let total = uids.len();
+    // The oldest messages are next in line
+    uids.truncate(limit);
+    println!("{} messages in {} ({}), showing {}", total, mailbox, criteria, uids.len());
+    if uids.is_empty() {
+        return Ok(imap_session.logout()?);
+    }
+
+    let set: Vec<String> = uids.iter().map(u32::to_string).collect();
+    let fetches = imap_session.uid_fetch(set.join(","), "(UID RFC822.SIZE BODY.PEEK[HEADER])")?;
+    let mut rows: Vec<_> = fetches.iter().collect();
+    rows.sort_by_key(|f| f.uid);
+    println!("{:>8}  {:>8}  {:<16}  {:<30}  SUBJECT", "UID", "SIZE", "ROUTE", "FROM");
+    for fetch in rows {
+        let email = Email::parse(fetch.header().unwrap_or(&[]))?;
+        let route = if is_ignored(config, &email) {
+            "(ignored)".to_string()
+        } else {
+            crate::routes::find(config, &email).map_or("-".to_string(), |r| r.name.clone())
+        };
+        println!(
+            "{:>8}  {:>8}  {:<16}  {:<30}  {}",
+            fetch.uid.unwrap_or_default(),
+            fetch.size.map_or("?".to_string(), |s| format!("{}K", s.div_ceil(1024))),
+            truncate(&route, 16),
+            truncate(&email.from, 30),
+            email.subject
+        );
+    }
+    imap_session.logout()?;
+    Ok(())
+}
+
+// `--dry-run`: fetches what a cycle would pick up (with BODY.PEEK, from a read-only INBOX),
+// renders it through the real pipeline and prints the payloads. Nothing is posted, flagged,
+// expunged or recorded, so filters and routes can be checked against a real mailbox.
+pub fn dry_run(config: &Config) -> Result<(), Error> {
+    let mut imap_session = auth::login(config, connect(config, None)?)?;
+    let folders = Folders::discover(&mut imap_session)?;
+
+    let search = config.search.clone().unwrap_or_default();
+    let mut criteria = search::criteria(search.since.as_deref(), search.before.as_deref()).map_err(Error::Config)?;
+    if config.processing_mode() == ProcessingMode::MarkSeen {
+        criteria = if criteria == "ALL" { "UNSEEN".to_string() } else { format!("UNSEEN {}", criteria) };
+    }
+    let mut budget = config.limits.as_ref().and_then(|l| l.max_messages_per_cycle);
+    for name in config.folders() {
+        let mailbox = folders.resolve(&name);
+        imap_session.examine(&mailbox)?;
+        let mut uids: Vec<u32> = imap_session.uid_search(&criteria)?.into_iter().collect();
+        uids.sort();
+        if let Some(max) = budget {
+            uids.truncate(max);
+            budget = Some(max - uids.len());
+        }
+        println!("Dry run: {} messages in {} for {} ({})", uids.len(), mailbox, config.imap_username, criteria);
+        for uid in uids {
+            preview(config, &mut imap_session, uid, config.folders.as_ref().map(|_| &name))?;
+        }
+    }
+    imap_session.logout()?;
+    Ok(())
+}
+
+// One message of a dry run: whether it would be posted, where, and the payload
+fn preview(config: &Config, imap_session: &mut Session, uid: u32, folder: Option<&String>) -> Result<(), Error> {
+    let fetches = imap_session.uid_fetch(uid.to_string(), "BODY.PEEK[]")?;
+    let Some(msg) = fetches.iter().next() else {
+        return Ok(());
+    };
+    let mut email = Email::parse(msg.body().unwrap_or(&[]))?;
+    email.folder = folder.cloned();
+    println!();
+    println!("UID {} [{}] from {}: {}", uid, email.trace_id, email.from, email.subject);
+    if is_ignored(config, &email) {
+        println!("  ignored by the filters, would be removed without posting");
+        return Ok(());
+    }
+    if let Some(reason) = email.auto_reply.filter(|_| config.auto_replies.unwrap_or_default() != AutoReplyAction::Forward) {
+        println!("  bounce/autoreply ({}), would not be posted", reason);
+        return Ok(());
+    }
+    if let Some(notice) = crate::notices::detect(config, &email) {
+        println!("  mailbox notice ({}), would go to the ops webhook", notice.kind);
+        return Ok(());
+    }
+    let route = crate::routes::find(config, &email);
+    println!("  route: {}", route.map_or("(default)", |r| r.name.as_str()));
+    let payload = pipeline::render(config, &email);
+    let files = payload.get(crate::discord::FILES_KEY).and_then(Value::as_array).map_or(0, Vec::len);
+    if files > 0 {
+        println!("  with {} file(s)", files);
+    }
+    println!("{}", serde_json::to_string_pretty(&crate::discord::without_files(&payload)).unwrap_or_default());
+    Ok(())
+}
+
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        return s.to_string();
+    }
+    format!("{}…", s.chars().take(width - 1).collect::<String>())
+}
+
+// Sequence numbers follow arrival order, which is the best fallback when a Date header is
+// missing or unparseable.
+fn sort_emails(emails: &mut [(u32, Email)], order: CatchupOrder) {
+    emails.sort_by_key(|(seq_num, email)| (email.date, *seq_num));
+    if order == CatchupOrder::NewestFirst {
+        emails.reverse();
+    }
+}
+
+// Splits a sorted backlog into (collapsed into a digest, posted individually). The newest
+// `max_messages` are kept as individual posts; everything older goes into the digest.
+fn split_catchup(emails: Batch, catchup: &CatchupConfig) -> (Batch, Batch) {
+    let cutoff = catchup
+        .collapse_older_than_days
+        .map(|days| chrono::Utc::now() - chrono::Duration::days(days));
+    let (mut digest, mut individual): (Vec<_>, Vec<_>) = emails
+        .into_iter()
+        .partition(|(_, email)| cutoff.is_some_and(|cutoff| email.date.is_some_and(|date| date < cutoff)));
+
+    if let Some(max) = catchup.max_messages
+        && individual.len() > max
+    {
+        let overflow = individual.len() - max;
+        let newest_first = catchup.order == Some(CatchupOrder::NewestFirst);
+        let dropped: Vec<_> = if newest_first {
+            individual.split_off(max)
+        } else {
+            individual.drain(..overflow).collect()
+        };
+        digest.extend(dropped);
+    }
+    (digest, individual)
+}
+
+pub fn is_ignored(config: &Config, email: &Email) -> bool {
+    if let Some((_, filter)) = crate::filters::first_match(config, email) {
+        return filter.action == FilterAction::Ignore;
+    }
+    let sender_ignored = config
+        .ignored_senders
+        .as_ref()
+        .is_some_and(|senders| senders.iter().any(|s| email.from.contains(s)));
+    let subject_ignored = config
+        .ignored_subjects
+        .as_ref()
+        .is_some_and(|subjects| subjects.iter().any(|s| email.subject.contains(s)));
+    sender_ignored || subject_ignored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Keeps state in memory, and lets another instance take the lease after `granted` renewals
+    struct Store {
+        entries: Mutex<BTreeMap<String, String>>,
+        leases: AtomicUsize,
+        granted: usize,
+    }
+
+    impl StateStore for Store {
+        fn get(&self, key: &str) -> Result<Option<String>, Error> {
+            Ok(self.entries.lock().unwrap().get(key).cloned())
+        }
+
+        fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+            self.entries.lock().unwrap().insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+
+        fn delete(&self, key: &str) -> Result<(), Error> {
+            self.entries.lock().unwrap().remove(key);
+            Ok(())
+        }
+
+        fn keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+            Ok(self.entries.lock().unwrap().keys().filter(|k| k.starts_with(prefix)).cloned().collect())
+        }
+
+        fn try_lease(&self, _name: &str, _holder: &str, _ttl: Duration) -> Result<bool, Error> {
+            Ok(self.leases.fetch_add(1, Ordering::SeqCst) < self.granted)
+        }
+    }
+
+    #[test]
+    fn batch_stops_when_the_lease_is_lost() {
+        // A webhook that accepts everything and counts the posts
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let posts = std::sync::Arc::new(AtomicUsize::new(0));
+        let counted = posts.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                counted.fetch_add(1, Ordering::SeqCst);
+                let _ = request.respond(tiny_http::Response::from_string(r#"{"id": "1", "channel_id": "2"}"#));
+            }
+        });
+        let config: Config = serde_json::from_value(json!({
+            "imap_server": "imap.example.com",
+            "imap_username": "me",
+            "discord_webhook_url": format!("http://127.0.0.1:{}/api/webhooks/1/token", port),
+            "leader": { "enabled": true },
+        }))
+        .unwrap();
+        // Renewed for the first post only
+        let store = Store { entries: Mutex::default(), leases: AtomicUsize::new(0), granted: 1 };
+        let leader = Leader::from_config(&config, &store);
+        let emails = ["plain", "cjk", "long-body"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (i as u32 + 1, Email::parse(crate::samples::find(name).unwrap()).unwrap()))
+            .collect();
+
+        let mut done = BTreeSet::new();
+        deliver_batch(&config, &store, leader.as_ref(), emails, false, &mut done).unwrap();
+        assert_eq!(done.into_iter().collect::<Vec<_>>(), [1]);
+        assert_eq!(posts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::config::{Config, MqttConfig, Route};
+use crate::domains;
+use crate::error::Error;
+use crate::mail::Email;
+use crate::schema;
+use crate::series::sender_address;
+use crate::webhooks;
+use native_tls::TlsConnector;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use tracing::error;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Publishes a compact JSON message per delivered email to an MQTT broker, for home
+// automation (Home Assistant and the like) to react to particular newsletters. A short-lived
+// MQTT 3.1.1 connection is made per message; newsletters don't come often enough to keep one
+// open. Failures are logged and never hold up the delivery itself.
+pub fn publish(config: &Config, route: Option<&Route>, email: &Email) {
+    let Some(ref mqtt) = config.mqtt else {
+        return;
+    };
+    let route = route.map_or(webhooks::DEFAULT_TARGET, |r| r.name.as_str());
+    let topic = topic(mqtt, route, &email.from);
+    let message = schema::email(email, route);
+    if let Err(e) = send(mqtt, &topic, message.to_string().as_bytes()) {
+        error!("Failed to publish to MQTT topic {}: {}", topic, e);
+    }
+}
+
+// `{route}`, `{sender}` and `{domain}` filled in, with the characters MQTT gives a meaning replaced
+fn topic(mqtt: &MqttConfig, route: &str, from: &str) -> String {
+    let level = |s: &str| s.replace(['/', '+', '#'], "_");
+    mqtt.topic
+        .as_deref()
+        .unwrap_or("newsletter/{route}")
+        .replace("{route}", &level(route))
+        .replace("{sender}", &level(&sender_address(from)))
+        .replace("{domain}", &level(&domains::of_sender(from)))
+}
+
+fn send(mqtt: &MqttConfig, topic: &str, payload: &[u8]) -> Result<(), Error> {
+    let url = reqwest::Url::parse(&mqtt.broker_url).map_err(Error::config)?;
+    let tls = match url.scheme() {
+        "mqtt" => false,
+        "mqtts" => true,
+        scheme => return Err(Error::Config(format!("Unsupported MQTT scheme {} (use mqtt:// or mqtts://)", scheme))),
+    };
+    let host = url.host_str().ok_or_else(|| Error::config("The MQTT broker URL has no host"))?;
+    let port = url.port().unwrap_or(if tls { 8883 } else { 1883 });
+    let address = (host, port)
+        .to_socket_addrs()
+        .map_err(Error::network)?
+        .next()
+        .ok_or_else(|| Error::Network(format!("{} did not resolve", host)))?;
+    let tcp = TcpStream::connect_timeout(&address, TIMEOUT).map_err(Error::network)?;
+    tcp.set_read_timeout(Some(TIMEOUT)).map_err(Error::network)?;
+    tcp.set_write_timeout(Some(TIMEOUT)).map_err(Error::network)?;
+    let user = (!url.username().is_empty()).then(|| url.username());
+    let session = Session { mqtt, user, password: url.password() };
+    if tls {
+        let connector = TlsConnector::new().map_err(Error::network)?;
+        let mut stream = connector.connect(host, tcp).map_err(Error::network)?;
+        session.publish(&mut stream, topic, payload)
+    } else {
+        session.publish(&mut &tcp, topic, payload)
+    }
+}
+
+struct Session<'a> {
+    mqtt: &'a MqttConfig,
+    user: Option<&'a str>,
+    password: Option<&'a str>,
+}
+
+impl Session<'_> {
+    // CONNECT, PUBLISH (waiting for PUBACK at QoS 1), DISCONNECT
+    fn publish(&self, stream: &mut impl ReadWrite, topic: &str, payload: &[u8]) -> Result<(), Error> {
+        let client_id = self.mqtt.client_id.as_deref().unwrap_or("newsletter");
+        let mut flags = 0x02; // clean session
+        let mut body = Vec::new();
+        string(&mut body, "MQTT");
+        body.push(4); // protocol level 3.1.1
+        let flags_at = body.len();
+        body.push(0);
+        body.extend_from_slice(&60u16.to_be_bytes()); // keep alive
+        string(&mut body, client_id);
+        if let Some(user) = self.user {
+            flags |= 0x80;
+            string(&mut body, user);
+        }
+        if let Some(password) = self.password {
+            flags |= 0x40;
+            string(&mut body, password);
+        }
+        body[flags_at] = flags;
+        write_packet(stream, 0x10, &body)?;
+
+        let connack = read_packet(stream)?;
+        if connack.0 != 0x20 || connack.1.len() < 2 {
+            return Err(Error::network("Broker did not acknowledge the connection"));
+        }
+        if connack.1[1] != 0 {
+            return Err(Error::Network(format!("Broker refused the connection (code {})", connack.1[1])));
+        }
+
+        let qos = self.mqtt.qos.unwrap_or(0);
+        let mut body = Vec::new();
+        string(&mut body, topic);
+        if qos > 0 {
+            body.extend_from_slice(&1u16.to_be_bytes()); // packet identifier
+        }
+        body.extend_from_slice(payload);
+        write_packet(stream, 0x30 | (qos << 1) | self.mqtt.retain as u8, &body)?;
+        if qos > 0 {
+            let puback = read_packet(stream)?;
+            if puback.0 != 0x40 {
+                return Err(Error::network("Broker did not acknowledge the message"));
+            }
+        }
+        write_packet(stream, 0xE0, &[])
+    }
+}
+
+trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+// Length-prefixed UTF-8, as MQTT encodes strings
+fn string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn write_packet(stream: &mut impl Write, header: u8, body: &[u8]) -> Result<(), Error> {
+    let mut packet = vec![header];
+    // Remaining length: 7 bits per byte, least significant first
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    stream.write_all(&packet).and_then(|_| stream.flush()).map_err(Error::network)
+}
+
+fn read_packet(stream: &mut impl Read) -> Result<(u8, Vec<u8>), Error> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte).map_err(Error::network)?;
+    let header = byte[0] & 0xF0;
+    let (mut len, mut shift) = (0usize, 0);
+    loop {
+        stream.read_exact(&mut byte).map_err(Error::network)?;
+        len |= ((byte[0] & 0x7F) as usize) << shift;
+        if byte[0] & 0x80 == 0 || shift > 21 {
+            break;
+        }
+        shift += 7;
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).map_err(Error::network)?;
+    Ok((header, body))
+}
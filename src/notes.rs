@@ -0,0 +1,155 @@
+use crate::archive;
+use crate::config::{Config, NotionConfig, Route};
+use crate::error::Error;
+use crate::mail::Email;
+use serde_json::{Value, json};
+use std::fs;
+use std::path::Path;
+use tracing::{error, info};
+
+// Notion's limits: characters per text object, blocks per request
+const MAX_TEXT: usize = 2000;
+const MAX_BLOCKS: usize = 100;
+const NOTION_VERSION: &str = "2022-06-28";
+
+// Keeps every delivered newsletter as a note for reading later: a Markdown file with YAML
+// front matter in a vault directory (Obsidian, or anything that reads Markdown), a page in
+// a Notion database, or both. The note has the full body, not the shortened post. Failures
+// are logged and don't hold up the delivery.
+pub fn export(config: &Config, route: Option<&Route>, email: &Email) {
+    let Some(ref notes) = config.notes else {
+        return;
+    };
+    let mut tags = notes.tags.clone().unwrap_or_default();
+    tags.extend(route.map(|r| r.name.clone()));
+    tags.extend(route.and_then(|r| r.category.clone()));
+    let url = archive::url(config, &email.trace_id);
+    if let Some(ref dir) = notes.vault_dir {
+        match write_file(config, route, Path::new(dir), email, &tags, url.as_deref()) {
+            Ok(name) => info!("Saved note {}", name),
+            Err(e) => error!("Failed to save the note in {}: {}", dir, e),
+        }
+    }
+    if let Some(ref notion) = notes.notion
+        && let Err(e) = add_page(notion, email, &tags, url.as_deref())
+    {
+        error!("Failed to add the note to Notion: {}", e);
+    }
+}
+
+// `<date> <subject>.md`, with the trace ID added when another email already has the name
+fn write_file(
+    config: &Config,
+    route: Option<&Route>,
+    dir: &Path,
+    email: &Email,
+    tags: &[String],
+    url: Option<&str>,
+) -> Result<String, Error> {
+    let date = email.date.unwrap_or_else(chrono::Utc::now).with_timezone(&config.timezone(route));
+    let title: String = email
+        .subject
+        .chars()
+        .map(|c| if "/\\:*?\"<>|#^[]".contains(c) || c.is_control() { '-' } else { c })
+        .take(100)
+        .collect();
+    let stem = format!("{} {}", date.format("%Y-%m-%d"), title.trim());
+    let mut path = dir.join(format!("{}.md", stem));
+    if path.exists() {
+        path = dir.join(format!("{} ({}).md", stem, email.trace_id));
+    }
+
+    // JSON strings are valid YAML scalars, which saves escaping by hand
+    let quote = |s: &str| Value::String(s.to_string()).to_string();
+    let mut note = String::from("---\n");
+    note.push_str(&format!("title: {}\n", quote(&email.subject)));
+    note.push_str(&format!("sender: {}\n", quote(&email.from)));
+    note.push_str(&format!("date: {}\n", date.to_rfc3339()));
+    note.push_str(&format!("tags: [{}]\n", tags.iter().map(|t| quote(t)).collect::<Vec<_>>().join(", ")));
+    if let Some(url) = url {
+        note.push_str(&format!("url: {}\n", quote(url)));
+    }
+    note.push_str(&format!("trace_id: {}\n---\n\n", email.trace_id));
+    note.push_str(email.body.trim());
+    note.push('\n');
+
+    fs::create_dir_all(dir)?;
+    fs::write(&path, note)?;
+    Ok(path.file_name().unwrap_or_default().to_string_lossy().into_owned())
+}
+
+// A page in the database, titled with the subject, with the body as paragraphs. The
+// sender, date, tags and link go into the properties configured for them, if any.
+fn add_page(notion: &NotionConfig, email: &Email, tags: &[String], url: Option<&str>) -> Result<(), Error> {
+    let mut properties = json!({});
+    properties[notion.title_property.as_deref().unwrap_or("Name")] = json!({ "title": text(&email.subject) });
+    if let Some(ref name) = notion.sender_property {
+        properties[name] = json!({ "rich_text": text(&email.from) });
+    }
+    if let Some(ref name) = notion.date_property {
+        properties[name] = json!({ "date": { "start": email.date.unwrap_or_else(chrono::Utc::now).to_rfc3339() } });
+    }
+    if let Some(ref name) = notion.tags_property {
+        // Multi-select options can't contain commas
+        let options: Vec<Value> = tags.iter().map(|t| json!({ "name": t.replace(',', " ") })).collect();
+        properties[name] = json!({ "multi_select": options });
+    }
+    if let (Some(name), Some(url)) = (notion.url_property.as_ref(), url) {
+        properties[name] = json!({ "url": url });
+    }
+
+    let blocks = paragraphs(&email.body);
+    let mut chunks = blocks.chunks(MAX_BLOCKS);
+    let first = chunks.next().unwrap_or_default();
+    let page = request(
+        notion,
+        crate::http::client().post("https://api.notion.com/v1/pages"),
+        &json!({ "parent": { "database_id": notion.database_id }, "properties": properties, "children": first }),
+    )?;
+    // The rest is appended to the page, a request's worth at a time
+    let id = page["id"].as_str().unwrap_or_default();
+    for chunk in chunks {
+        request(
+            notion,
+            crate::http::client().patch(format!("https://api.notion.com/v1/blocks/{}/children", id)),
+            &json!({ "children": chunk }),
+        )?;
+    }
+    Ok(())
+}
+
+fn request(notion: &NotionConfig, request: reqwest::blocking::RequestBuilder, body: &Value) -> Result<Value, Error> {
+    let response = request
+        .bearer_auth(&notion.token)
+        .header("Notion-Version", NOTION_VERSION)
+        .json(body)
+        .send()?;
+    let status = response.status();
+    let body: Value = response.json().unwrap_or_default();
+    if !status.is_success() {
+        return Err(Error::Network(format!(
+            "Status {}: {}",
+            status,
+            body["message"].as_str().unwrap_or_default()
+        )));
+    }
+    Ok(body)
+}
+
+// Rich text, in pieces within Notion's limit
+fn text(s: &str) -> Vec<Value> {
+    let chars: Vec<char> = s.chars().collect();
+    chars
+        .chunks(MAX_TEXT)
+        .map(|piece| json!({ "type": "text", "text": { "content": piece.iter().collect::<String>() } }))
+        .collect()
+}
+
+// One paragraph block per paragraph of the body
+fn paragraphs(body: &str) -> Vec<Value> {
+    body.split("\n\n")
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| json!({ "object": "block", "type": "paragraph", "paragraph": { "rich_text": text(p) } }))
+        .collect()
+}
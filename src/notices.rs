@@ -0,0 +1,66 @@
+use crate::config::Config;
+use crate::mail::Email;
+use regex::Regex;
+use std::sync::LazyLock;
+use tracing::warn;
+
+// Subjects of the warnings mail providers send about the mailbox itself: it is (nearly) full,
+// or the account is about to be locked or deleted. Matched case-insensitively.
+const BUILTIN: &[(&str, &str)] = &[
+    ("quota", r"mailbox (is )?(almost |nearly )?(\d{1,3} ?% )?full"),
+    ("quota", r"(mailbox|storage|disk|e-?mail) (quota|space|storage) (warning|exceeded|reached|limit|is (almost |nearly )?full)"),
+    ("quota", r"(over|exceeded|reached|approaching) (your |the )?(mailbox |storage )?(quota|storage limit)"),
+    ("quota", r"(running|run) out of (storage|space)|storage (is )?(almost |nearly )?full|\d{1,3} ?% of (your )?storage"),
+    ("quota", r"your (icloud|google|outlook|onedrive) storage"),
+    ("inactivity", r"(inactive|dormant) (account|mailbox)|account inactivity|due to inactivity"),
+    ("suspension", r"account (will be |has been |is )?(suspended|deactivated|disabled|closed|locked|deleted|terminated)"),
+    ("suspension", r"(mailbox|account) (will be |is scheduled for )(deletion|removal)"),
+    ("expiry", r"password (will )?expir|(subscription|account|mailbox) (will )?expir(e|es|ing)"),
+];
+
+static BUILTIN_REGEXES: LazyLock<Vec<(&str, Regex)>> = LazyLock::new(|| {
+    BUILTIN
+        .iter()
+        .map(|(kind, pattern)| (*kind, Regex::new(&format!("(?i){}", pattern)).unwrap()))
+        .collect()
+});
+static PERCENT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b(\d{1,3}(?:[.,]\d+)?) ?%").unwrap());
+
+// A mailbox notice: what kind ("quota", "suspension", ..., or "custom" for configured
+// patterns) and, for quota warnings, how full the mailbox is as far as the email says
+pub struct Notice {
+    pub kind: &'static str,
+    pub usage: Option<String>,
+}
+
+// Whether the email is a provider's notice about the mailbox rather than a newsletter.
+// The built-in patterns only look at the subject, since newsletters write about storage
+// too; the configured senders catch a provider's notices whatever their subject.
+pub fn detect(config: &Config, email: &Email) -> Option<Notice> {
+    let notices = config.notices.as_ref().filter(|n| n.enabled)?;
+    let builtin = || {
+        BUILTIN_REGEXES
+            .iter()
+            .find(|(_, re)| re.is_match(&email.subject))
+            .map(|(kind, _)| *kind)
+    };
+    let custom = || {
+        let subject = notices.subjects.iter().flatten().any(|pattern| match Regex::new(pattern) {
+            Ok(re) => re.is_match(&email.subject),
+            Err(e) => {
+                warn!("notices: invalid subject pattern {:?}: {}", pattern, e);
+                false
+            }
+        });
+        let from = email.from.to_lowercase();
+        let sender = notices.senders.iter().flatten().any(|s| from.contains(&s.to_lowercase()));
+        (subject || sender).then_some("custom")
+    };
+    let kind = if notices.builtin.unwrap_or(true) { builtin().or_else(custom) } else { custom() }?;
+    let usage = PERCENT
+        .captures(&email.subject)
+        .or_else(|| PERCENT.captures(&email.body))
+        .filter(|_| kind == "quota" || kind == "custom")
+        .map(|c| format!("{}%", &c[1]));
+    Some(Notice { kind, usage })
+}
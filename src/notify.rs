@@ -0,0 +1,142 @@
+use crate::config::{DiscourseConfig, MatrixConfig, NotifierKind, ZulipConfig};
+use crate::discord::{self, Posted};
+use crate::error::Error;
+use crate::{discourse, matrix, slack, telegram, zulip};
+use serde_json::Value;
+
+// Where a rendered message goes. Payloads are built in Discord's shape throughout the
+// pipeline; each backend sends them the way its service expects.
+pub trait Notifier {
+    fn send(&self, payload: &Value, thread_id: Option<&str>) -> Result<Posted, Error>;
+}
+
+pub struct DiscordWebhook<'a>(pub &'a str);
+
+pub struct DiscordBot<'a> {
+    pub token: &'a str,
+    pub channel_id: &'a str,
+}
+
+pub struct SlackWebhook<'a>(pub &'a str);
+
+pub struct TelegramBot<'a> {
+    pub token: &'a str,
+    pub chat_id: &'a str,
+}
+
+pub struct ZulipStream<'a> {
+    pub zulip: &'a ZulipConfig,
+    pub stream: &'a str,
+    // Topic template, with `{route}` for this name
+    pub topic: &'a str,
+    pub route: &'a str,
+}
+
+pub struct DiscourseCategory<'a> {
+    pub discourse: &'a DiscourseConfig,
+    pub category: u64,
+}
+
+pub struct MatrixRoom<'a> {
+    pub matrix: &'a MatrixConfig,
+    pub room_id: &'a str,
+}
+
+impl Notifier for DiscordWebhook<'_> {
+    fn send(&self, payload: &Value, thread_id: Option<&str>) -> Result<Posted, Error> {
+        discord::send_to(self.0, payload, thread_id)
+    }
+}
+
+impl Notifier for DiscordBot<'_> {
+    fn send(&self, payload: &Value, thread_id: Option<&str>) -> Result<Posted, Error> {
+        discord::send_to_channel(self.token, self.channel_id, payload, thread_id)
+    }
+}
+
+// Incoming webhooks can't post into threads, so `thread_id` is ignored
+impl Notifier for SlackWebhook<'_> {
+    fn send(&self, payload: &Value, _thread_id: Option<&str>) -> Result<Posted, Error> {
+        slack::send(self.0, payload)
+    }
+}
+
+// Telegram has no threads, so `thread_id` is ignored
+impl Notifier for TelegramBot<'_> {
+    fn send(&self, payload: &Value, _thread_id: Option<&str>) -> Result<Posted, Error> {
+        telegram::send(self.token, self.chat_id, payload)
+    }
+}
+
+// Zulip topics already thread the conversation, so `thread_id` is ignored
+impl Notifier for ZulipStream<'_> {
+    fn send(&self, payload: &Value, _thread_id: Option<&str>) -> Result<Posted, Error> {
+        zulip::send(self.zulip, self.stream, &self.topic.replace("{route}", self.route), payload)
+    }
+}
+
+// Every email is a topic of its own, so `thread_id` is ignored
+impl Notifier for DiscourseCategory<'_> {
+    fn send(&self, payload: &Value, _thread_id: Option<&str>) -> Result<Posted, Error> {
+        discourse::send(self.discourse, self.category, payload)
+    }
+}
+
+// Each email is a message of its own in the room, so `thread_id` is ignored
+impl Notifier for MatrixRoom<'_> {
+    fn send(&self, payload: &Value, _thread_id: Option<&str>) -> Result<Posted, Error> {
+        matrix::send(self.matrix, self.room_id, payload)
+    }
+}
+
+// A webhook URL of the given kind. Telegram, Zulip, Discourse and Matrix have no webhooks to
+// post to, so URLs configured next to them (ops_webhook_url, route webhooks) are Discord's.
+pub fn webhook(kind: NotifierKind, url: &str) -> Box<dyn Notifier + '_> {
+    match kind {
+        NotifierKind::Discord
+        | NotifierKind::Telegram
+        | NotifierKind::Zulip
+        | NotifierKind::Discourse
+        | NotifierKind::Matrix => Box::new(DiscordWebhook(url)),
+        NotifierKind::Slack => Box::new(SlackWebhook(url)),
+    }
+}
+
+// The payload as Markdown, for services that render it themselves (Zulip, Discourse). The
+// renderers' **bold** and [text](url) are Markdown already. Without `title` the first
+// embed's title is left out, for services that take it separately.
+pub fn markdown(payload: &Value, title: bool) -> String {
+    let mut blocks = Vec::new();
+    if let Some(content) = payload["content"].as_str().filter(|c| !c.is_empty()) {
+        blocks.push(content.to_string());
+    }
+    for (i, embed) in payload["embeds"].as_array().into_iter().flatten().enumerate() {
+        if let Some(name) = embed["title"].as_str().filter(|_| title || i > 0) {
+            blocks.push(match embed["url"].as_str() {
+                Some(url) => format!("### [{}]({})", name, url),
+                None => format!("### {}", name),
+            });
+        }
+        if let Some(author) = embed["author"]["name"].as_str() {
+            blocks.push(format!("*{}*", author));
+        }
+        // Uploaded images (attachment://) don't come along
+        if let Some(url) = embed["image"]["url"].as_str().filter(|u| u.starts_with("http")) {
+            blocks.push(format!("![]({})", url));
+        }
+        if let Some(description) = embed["description"].as_str().filter(|d| !d.is_empty()) {
+            blocks.push(description.to_string());
+        }
+        for field in embed["fields"].as_array().into_iter().flatten() {
+            blocks.push(format!(
+                "**{}**\n{}",
+                field["name"].as_str().unwrap_or_default(),
+                field["value"].as_str().unwrap_or_default()
+            ));
+        }
+        if let Some(footer) = embed["footer"]["text"].as_str() {
+            blocks.push(format!("*{}*", footer));
+        }
+    }
+    blocks.join("\n\n")
+}
@@ -0,0 +1,61 @@
+use crate::config::Config;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tracing::error;
+
+static SENT: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+// Operational alerts are logged as errors and, when configured, to a separate ops webhook so they
+// don't get lost between newsletters.
+pub fn alert(config: &Config, title: &str, message: &str) {
+    error!("ALERT: {}: {}", title, message);
+    send(config, title, message, None);
+}
+
+// An alert someone should act on soon: it also pings `mention` (a user or role mention,
+// e.g. "<@&123>") so it isn't just another line in the ops channel
+pub fn urgent(config: &Config, title: &str, message: &str, mention: Option<&str>) {
+    error!("URGENT: {}: {}", title, message);
+    send(config, title, message, mention);
+}
+
+fn send(config: &Config, title: &str, message: &str, mention: Option<&str>) {
+    let trace_id = crate::trace::current();
+
+    let Some(ref url) = config.ops_webhook_url else {
+        return;
+    };
+    let footer = match trace_id {
+        Some(id) => format!("📰 Newsletter ops · trace {}", id),
+        None => "📰 Newsletter ops".to_string(),
+    };
+    let mut payload = serde_json::json!({
+        "embeds": [{
+            "title": format!("⚠️ {}", title),
+            "description": message,
+            "color": 0xED4245, // Red
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "footer": {
+                "text": footer
+            }
+        }]
+    });
+    if let Some(mention) = mention {
+        payload["content"] = serde_json::Value::String(mention.to_string());
+        payload["allowed_mentions"] = serde_json::json!({ "parse": ["users", "roles", "everyone"] });
+    }
+    if let Err(e) = crate::notify::webhook(config.notifier(None), url).send(&payload, None) {
+        error!("Failed to send ops alert: {}", e);
+    }
+}
+
+// Like `alert`, but only the first alert for a given key is sent, so a condition that
+// persists across reconnect attempts doesn't flood the ops channel.
+pub fn alert_once(config: &Config, key: &str, title: &str, message: &str) {
+    let first = SENT.lock().unwrap().get_or_insert_with(HashSet::new).insert(key.to_string());
+    if first {
+        alert(config, title, message);
+    } else {
+        error!("{}: {}", title, message);
+    }
+}
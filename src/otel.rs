@@ -0,0 +1,194 @@
+use crate::config::OtlpConfig;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+// Minimal OTLP/HTTP exporter using the JSON encoding, so traces and counters can go to any
+// collector (Tempo, Jaeger, the OpenTelemetry Collector) without an async runtime. Spans
+// are buffered and shipped with the counters every `interval_seconds`.
+static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+// Counter name plus an optional single attribute (e.g. status=delivered)
+type CounterKey = (String, Option<(String, String)>);
+
+struct Exporter {
+    config: OtlpConfig,
+    started: u128,
+    spans: Mutex<Vec<Value>>,
+    counters: Mutex<BTreeMap<CounterKey, u64>>,
+}
+
+pub fn init(config: Option<&OtlpConfig>) {
+    let Some(config) = config.filter(|c| !c.endpoint.is_empty()) else {
+        return;
+    };
+    let exporter = Exporter {
+        config: config.clone(),
+        started: now_nanos(),
+        spans: Mutex::new(Vec::new()),
+        counters: Mutex::new(BTreeMap::new()),
+    };
+    if EXPORTER.set(exporter).is_err() {
+        return;
+    }
+    let interval = Duration::from_secs(config.interval_seconds());
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            flush();
+        }
+    });
+}
+
+pub struct Span {
+    name: &'static str,
+    trace_id: String,
+    start: u128,
+    attributes: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+// Starts a span in the current message's trace (see `trace::enter`), or in a fresh trace for
+// work that isn't tied to a message. The span is recorded when dropped.
+pub fn span(name: &'static str) -> Option<Span> {
+    EXPORTER.get()?;
+    let trace_id = match crate::trace::current() {
+        Some(id) => format!("{:0>32}", id),
+        None => random_hex(16),
+    };
+    Some(Span {
+        name,
+        trace_id,
+        start: now_nanos(),
+        attributes: Vec::new(),
+        error: None,
+    })
+}
+
+impl Span {
+    pub fn attr(&mut self, key: &str, value: impl ToString) {
+        self.attributes.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn fail(&mut self, error: impl ToString) {
+        self.error = Some(error.to_string());
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(exporter) = EXPORTER.get() else {
+            return;
+        };
+        let status = match self.error {
+            Some(ref message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 1 }),
+        };
+        let span = json!({
+            "traceId": self.trace_id,
+            "spanId": random_hex(8),
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": now_nanos().to_string(),
+            "attributes": attributes(self.attributes.iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+            "status": status,
+        });
+        exporter.spans.lock().unwrap().push(span);
+    }
+}
+
+pub fn count(name: &str, attribute: Option<(&str, &str)>) {
+    add(name, attribute, 1);
+}
+
+pub fn add(name: &str, attribute: Option<(&str, &str)>, value: u64) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let key = (name.to_string(), attribute.map(|(k, v)| (k.to_string(), v.to_string())));
+    *exporter.counters.lock().unwrap().entry(key).or_insert(0) += value;
+}
+
+pub fn flush() {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let resource = json!({
+        "attributes": attributes([("service.name", exporter.config.service_name())].into_iter()),
+    });
+    let scope = json!({ "name": "newsletter", "version": env!("CARGO_PKG_VERSION") });
+
+    let spans: Vec<Value> = std::mem::take(&mut *exporter.spans.lock().unwrap());
+    if !spans.is_empty() {
+        let body = json!({
+            "resourceSpans": [{ "resource": resource, "scopeSpans": [{ "scope": scope, "spans": spans }] }]
+        });
+        exporter.post("v1/traces", &body);
+    }
+
+    let now = now_nanos().to_string();
+    let mut metrics: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    for ((name, attribute), value) in exporter.counters.lock().unwrap().iter() {
+        metrics.entry(name.clone()).or_default().push(json!({
+            "asInt": value.to_string(),
+            "startTimeUnixNano": exporter.started.to_string(),
+            "timeUnixNano": now,
+            "attributes": attributes(attribute.iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+        }));
+    }
+    if !metrics.is_empty() {
+        let metrics: Vec<Value> = metrics
+            .into_iter()
+            .map(|(name, points)| {
+                json!({
+                    "name": name,
+                    // Cumulative, monotonic
+                    "sum": { "dataPoints": points, "aggregationTemporality": 2, "isMonotonic": true },
+                })
+            })
+            .collect();
+        let body = json!({
+            "resourceMetrics": [{ "resource": resource, "scopeMetrics": [{ "scope": scope, "metrics": metrics }] }]
+        });
+        exporter.post("v1/metrics", &body);
+    }
+}
+
+impl Exporter {
+    fn post(&self, path: &str, body: &Value) {
+        let url = format!("{}/{}", self.config.endpoint.trim_end_matches('/'), path);
+        let mut request = crate::http::client().post(&url).json(body);
+        for (name, value) in self.config.headers.iter().flatten() {
+            request = request.header(name, value);
+        }
+        match request.send() {
+            Ok(response) if !response.status().is_success() => {
+                error!("OTLP export to {} failed: Status {}", url, response.status())
+            }
+            Ok(_) => {}
+            Err(e) => error!("OTLP export to {} failed: {}", url, e),
+        }
+    }
+}
+
+fn attributes<'a>(pairs: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<Value> {
+    pairs
+        .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0)
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    if openssl::rand::rand_bytes(&mut buf).is_err() {
+        buf[0] = 1;
+    }
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,248 @@
+use crate::config::{Config, RetryConfig};
+use crate::error::Error;
+use crate::history::Status;
+use crate::mail::Email;
+use crate::pipeline;
+use crate::leader::Leader;
+use crate::state::StateStore;
+use crate::webhooks;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const PREFIX: &str = "outbox:";
+
+// How often the retry queue looks for entries that are due
+const RETRY_TICK: Duration = Duration::from_secs(5);
+
+// Outbox entries being sent right now, so the retry queue and the startup recovery never
+// post the same one twice at once
+static SENDING: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+// A rendered email waiting to be posted. It is written before the first attempt and removed
+// once the post succeeds (or the email is dead-lettered), so a retry sends exactly what was
+// rendered the first time instead of rendering (and summarizing) again, and a crash leaves
+// behind the deliveries that never completed.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub payload: Value,
+    // The plain render kept for the archive, when this is a new email
+    pub rendered: Option<Value>,
+    pub status: Status,
+    // The `[[accounts]]` entry the email came in through, if any
+    pub account: Option<String>,
+    // The route it goes to; the retry queue resends each route's entries on their own
+    #[serde(default)]
+    pub target: Option<String>,
+    // Base64 of the original message, so the delivery can be resumed without its source
+    pub raw: Option<String>,
+    pub created_at: DateTime<Utc>,
+    // Failed attempts since the retry queue took the entry over, and when it tries next
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub next_attempt: Option<DateTime<Utc>>,
+}
+
+pub fn get(store: &dyn StateStore, trace_id: &str) -> Result<Option<Entry>, Error> {
+    store.get_json(&format!("{}{}", PREFIX, trace_id))
+}
+
+pub fn put(
+    config: &Config,
+    store: &dyn StateStore,
+    email: &Email,
+    target: &str,
+    payload: &Value,
+    rendered: Option<&Value>,
+    status: Status,
+) -> Result<Entry, Error> {
+    let entry = Entry {
+        payload: payload.clone(),
+        rendered: rendered.cloned(),
+        status,
+        account: config.account.clone(),
+        target: Some(target.to_string()),
+        raw: email.raw.as_deref().map(openssl::base64::encode_block),
+        created_at: Utc::now(),
+        attempts: 0,
+        next_attempt: None,
+    };
+    store.put_json(&format!("{}{}", PREFIX, email.trace_id), &entry)?;
+    Ok(entry)
+}
+
+pub fn remove(store: &dyn StateStore, email: &Email) {
+    if let Err(e) = store.delete(&format!("{}{}", PREFIX, email.trace_id)) {
+        error!("Failed to clear outbox entry: {}", e);
+    }
+}
+
+// Hands a failed delivery to the retry queue ([retry]), which tries it again with
+// exponential backoff until it goes through. Returns true if the queue took it, in which
+// case the source copy of the message is no longer needed.
+pub fn queue(config: &Config, store: &dyn StateStore, email: &Email) -> Result<bool, Error> {
+    let Some(retry) = config.retry.as_ref().filter(|r| r.enabled) else {
+        return Ok(false);
+    };
+    let key = format!("{}{}", PREFIX, email.trace_id);
+    let Some(mut entry) = store.get_json::<Entry>(&key)? else {
+        return Ok(false);
+    };
+    // Without the message there is nothing to resend from
+    if entry.raw.is_none() {
+        return Ok(false);
+    }
+    entry.attempts += 1;
+    let delay = backoff(retry, entry.attempts);
+    entry.next_attempt = Some(Utc::now() + delay);
+    store.put_json(&key, &entry)?;
+    info!("Queued for retry #{} in {}s", entry.attempts, delay.num_seconds());
+    Ok(true)
+}
+
+// Whether `queue` can take the email should its delivery fail
+pub fn can_queue(config: &Config, email: &Email) -> bool {
+    config.retry.as_ref().is_some_and(|r| r.enabled) && email.raw.is_some()
+}
+
+// initial_delay_seconds, doubled after every failure up to max_delay_seconds
+fn backoff(retry: &RetryConfig, attempts: u32) -> chrono::Duration {
+    let initial = retry.initial_delay_seconds.unwrap_or(30).max(1);
+    let max = retry.max_delay_seconds.unwrap_or(3600).max(initial);
+    let seconds = initial.saturating_mul(1u64 << attempts.saturating_sub(1).min(32)).min(max);
+    chrono::Duration::seconds(seconds as i64)
+}
+
+// Run by each account's worker next to the IMAP loop: resends the account's queued entries
+// as they fall due, whatever the mailbox connection is doing. Each route's entries go
+// through a worker of their own, so a route whose target is rate limited or slow to answer
+// doesn't hold up the retries bound elsewhere. Only the leader sends.
+pub fn run_retries(config: &Config, store: &dyn StateStore, leader: Option<&Leader>) {
+    if !config.retry.as_ref().is_some_and(|r| r.enabled) {
+        return;
+    }
+    // Entries handed to a worker and not done yet, so a slow one isn't handed over again
+    let queued = Mutex::new(HashSet::new());
+    thread::scope(|s| {
+        let mut lanes: HashMap<String, Sender<String>> = HashMap::new();
+        while crate::shutdown::sleep(RETRY_TICK) {
+            if leader.is_some_and(|l| l.renew().is_err()) {
+                continue;
+            }
+            let now = Utc::now();
+            for (key, entry) in pending(config, store) {
+                if entry.next_attempt.is_none_or(|t| t > now) || !queued.lock().unwrap().insert(key.clone()) {
+                    continue;
+                }
+                let target = entry.target.unwrap_or_else(|| webhooks::DEFAULT_TARGET.to_string());
+                let lane = lanes.entry(target).or_insert_with(|| {
+                    let (sender, keys) = mpsc::channel::<String>();
+                    let queued = &queued;
+                    s.spawn(move || {
+                        for key in keys {
+                            retry(config, store, &key);
+                            queued.lock().unwrap().remove(&key);
+                        }
+                    });
+                    sender
+                });
+                let _ = lane.send(key);
+            }
+        }
+        // Closing the lanes lets their workers finish what they have
+        lanes.clear();
+    });
+}
+
+// Resends a due entry, and puts it back in the queue if it was held back before it was
+// even tried (paused, paced, quiet hours)
+fn retry(config: &Config, store: &dyn StateStore, key: &str) {
+    let entry = match store.get_json::<Entry>(key) {
+        Ok(Some(entry)) => entry,
+        // Delivered some other way in the meantime
+        Ok(None) => return,
+        Err(e) => {
+            error!("Failed to read the outbox: {}", e);
+            return;
+        }
+    };
+    let before = entry.next_attempt;
+    let Some(email) = resend(config, store, key, entry) else {
+        return;
+    };
+    match get(store, &email.trace_id) {
+        Ok(Some(left)) if left.next_attempt == before => {
+            if let Err(e) = queue(config, store, &email) {
+                error!("Failed to requeue: {}", e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to read the outbox: {}", e),
+    }
+}
+
+// The account's entries, oldest first. Entries from no account (the ingest endpoint) go
+// with the first account.
+fn pending(config: &Config, store: &dyn StateStore) -> Vec<(String, Entry)> {
+    let mut entries: Vec<(String, Entry)> = match store.entries(PREFIX) {
+        Ok(entries) => entries
+            .into_iter()
+            .filter_map(|(key, raw)| Some((key, serde_json::from_str::<Entry>(&raw).ok()?)))
+            .filter(|(_, entry)| match entry.account {
+                Some(_) => entry.account == config.account,
+                None => config.runs_housekeeping(),
+            })
+            .collect(),
+        Err(e) => {
+            error!("Failed to read the outbox: {}", e);
+            return Vec::new();
+        }
+    };
+    entries.sort_by_key(|(_, entry)| entry.created_at);
+    entries
+}
+
+// Delivers an entry again from its stored message. Returns the email, unless the entry was
+// unreadable (and dropped) or is already being sent.
+fn resend(config: &Config, store: &dyn StateStore, key: &str, entry: Entry) -> Option<Email> {
+    let email = entry
+        .raw
+        .as_deref()
+        .and_then(|raw| openssl::base64::decode_block(raw).ok())
+        .and_then(|raw| Email::parse(&raw).ok());
+    let Some(email) = email else {
+        warn!("Outbox entry {} has no readable message, dropping it", key);
+        let _ = store.delete(key);
+        return None;
+    };
+    if !SENDING.lock().unwrap().get_or_insert_with(HashSet::new).insert(key.to_string()) {
+        return None;
+    }
+    let _trace = crate::trace::enter(&email);
+    if let Err(e) = pipeline::deliver(config, store, &email) {
+        error!("Failed to resend from the outbox: {}", e);
+    }
+    SENDING.lock().unwrap().get_or_insert_with(HashSet::new).remove(key);
+    Some(email)
+}
+
+// Run once at startup by each account's worker: resends whatever that account left unsent in
+// the last run, oldest first. What still fails stays in the outbox, and is picked up again
+// when its source offers it again (or by the retry queue).
+pub fn recover(config: &Config, store: &dyn StateStore) {
+    let entries = pending(config, store);
+    if entries.is_empty() {
+        return;
+    }
+    info!("Resending {} unsent message(s) from the outbox", entries.len());
+    for (key, entry) in entries {
+        resend(config, store, &key, entry);
+    }
+}
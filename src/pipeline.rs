@@ -0,0 +1,372 @@
+use crate::config::{AutoReplyAction, Config, Format, LinkStyle, ReadLater, Route, Stage};
+use crate::discord::Failure;
+use crate::error::Error;
+use crate::history::{self, Status};
+use crate::mail::Email;
+use crate::resend::{self, Resend};
+use crate::state::StateStore;
+use serde_json::{Value, json};
+use crate::{archive, cadence, categories, confirm, deadletter, discord, emoji, events, experiments, footer, footnotes, homeassistant, irc, loops, monitor, mqtt, notes, notices, ops, outbox, reactions, readlater, redact, routes, series, shortener, site, snooze, styles, subscriptions, summarize, trace, webhooks, xmpp};
+use tracing::{debug, error, info, warn};
+
+// Deliveries Discord rejects as malformed this many times are moved to the dead-letter store
+const DEAD_LETTER_AFTER: u32 = 3;
+
+// The stages every email goes through, whatever its source (IMAP, the ingest endpoint, ...).
+// Both return true when the source copy of the message can be discarded.
+
+// Filters: ignore rules, bounces/autoreplies, snoozed routes. Returns true if the email was
+// handled here and must not be delivered.
+pub fn screen(config: &Config, store: &dyn StateStore, email: &Email) -> Result<bool, Error> {
+    if let Some(reason) = loops::detect(config, store, email)? {
+        warn!("Mail loop ({}) from {}, not processing: {}", reason, email.from, email.subject);
+        crate::metrics::count("newsletter_mail_loops_suppressed_total", Some(("reason", reason)));
+        events::emit("filtered", email, json!({ "reason": "loop", "detail": reason }));
+        history::record(store, email, Status::Looped, Some(reason.to_string()));
+        return Ok(true);
+    }
+
+    if monitor::is_ignored(config, email) {
+        info!("Ignored email from: {}, Subject: {}", email.from, email.subject);
+        events::emit("filtered", email, json!({ "reason": "ignored" }));
+        history::record(store, email, Status::Ignored, None);
+        return Ok(true);
+    }
+
+    if let Some(reason) = email.auto_reply {
+        let action = config.auto_replies.unwrap_or_default();
+        if action != AutoReplyAction::Forward {
+            info!("Bounce/autoreply ({}) from {}", reason, email.from);
+            events::emit("filtered", email, json!({ "reason": "auto_reply", "detail": reason }));
+            if action == AutoReplyAction::Ops {
+                ops::alert(
+                    config,
+                    "Bounce or autoreply received",
+                    &format!("{} ({})\n**{}**", email.from, reason, email.subject),
+                );
+            }
+            history::record(store, email, Status::AutoReply, Some(reason.to_string()));
+            return Ok(true);
+        }
+    }
+
+    if let Some(notice) = notices::detect(config, email) {
+        info!("Mailbox notice ({}) from {}: {}", notice.kind, email.from, email.subject);
+        events::emit("filtered", email, json!({ "reason": "notice", "detail": notice.kind }));
+        let mut message = format!("{}\n**{}**", email.from, email.subject);
+        if let Some(ref usage) = notice.usage {
+            message.push_str(&format!("\nUsage: {}", usage));
+        }
+        let mention = config.notices.as_ref().and_then(|n| n.mention.as_deref());
+        ops::urgent(config, &format!("Mailbox notice ({})", notice.kind), &message, mention);
+        history::record(store, email, Status::Notice, Some(notice.kind.to_string()));
+        return Ok(true);
+    }
+
+    if let Some(route) = routes::find(config, email)
+        && snooze::hold(store, &route.name, email)?
+    {
+        info!("Route {} is snoozed, holding: {}", route.name, email.subject);
+        cadence::observe(config, store, email);
+        events::emit("filtered", email, json!({ "reason": "snoozed", "route": route.name }));
+        history::record(store, email, Status::Snoozed, Some(route.name.clone()));
+        return Ok(true);
+    }
+
+    let route = routes::find(config, email);
+    if categories::hold(config, store, route, email)? {
+        let category = route.and_then(|r| r.category.clone());
+        info!("Collected for the {} digest: {}", category.as_deref().unwrap_or_default(), email.subject);
+        cadence::observe(config, store, email);
+        events::emit("filtered", email, json!({ "reason": "category_digest", "category": category }));
+        history::record(store, email, Status::Digested, category);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+// Used when neither the route nor the config sets `pipeline`
+const DEFAULT_STAGES: &[Stage] =
+    &[Stage::Redact, Stage::Summarize, Stage::SubjectEmoji, Stage::ShortenLinks, Stage::Render];
+
+// A rendered email, and the text to summarize into it when the pipeline has that stage
+pub struct Prepared {
+    pub payload: Value,
+    pub summarize: Option<Email>,
+}
+
+pub fn stages<'a>(config: &'a Config, route: Option<&'a Route>, email: &Email) -> &'a [Stage] {
+    experiments::assign(route, email)
+        .and_then(|(_, variant)| variant.pipeline.as_deref())
+        .or(route.and_then(|r| r.pipeline.as_deref()))
+        .or(config.pipeline.as_deref())
+        .unwrap_or(DEFAULT_STAGES)
+}
+
+// Runs the email through its route's stages in order. Each stage sees the email as the
+// stages before it left it; `summarize` only notes that text, as the summary replaces the
+// body of the rendered payload afterwards. Without a `render` stage the email is rendered
+// at the end.
+pub fn prepare(config: &Config, email: &Email) -> Prepared {
+    let route = routes::find(config, email);
+    let mut current = email.clone();
+    let mut payload = None;
+    let mut summarize = None;
+    for stage in stages(config, route, email) {
+        match stage {
+            Stage::StripFooter => current = footer::strip(&current),
+            Stage::Redact => current = redact::apply(config, &current),
+            Stage::SubjectEmoji => {
+                if let Some(emoji) = emoji::for_subject(config, &email.subject) {
+                    current.subject = format!("{} {}", emoji, current.subject);
+                }
+            }
+            Stage::ShortenLinks => current = shortener::apply(config, &current),
+            Stage::Summarize => summarize = Some(current.clone()),
+            Stage::Render => payload = Some(render_stage(config, route, &current)),
+        }
+    }
+    Prepared {
+        payload: payload.unwrap_or_else(|| render_stage(config, route, &current)),
+        summarize,
+    }
+}
+
+// The Discord message for a new email
+pub fn render(config: &Config, email: &Email) -> Value {
+    let payload = prepare(config, email).payload;
+    // Only previewed, so its redirects aren't kept
+    shortener::discard(email);
+    payload
+}
+
+fn render_stage(config: &Config, route: Option<&Route>, email: &Email) -> Value {
+    let format = experiments::assign(route, email)
+        .and_then(|(_, variant)| variant.format)
+        .or(route.and_then(|r| r.format))
+        .unwrap_or_default();
+    let marker = truncation_marker(config, route, email);
+    let marker = marker.as_deref();
+    let style = styles::find(config, email);
+    let color = route.and_then(|r| r.color()).or_else(|| style.and_then(|s| s.color()));
+    let embed = |email: &Email| {
+        let mut payload = discord::build_payload(email, color, marker);
+        if let Some(style) = style {
+            styles::apply(style, email, &mut payload);
+        }
+        payload
+    };
+    if route.and_then(|r| r.links).unwrap_or_default() == LinkStyle::Footnotes {
+        let (body, urls) = footnotes::extract(&email.body);
+        let mut email = email.clone();
+        email.body = body;
+        return match format {
+            Format::Embed => {
+                let mut payload = embed(&email);
+                footnotes::append(&mut payload, &urls);
+                payload
+            }
+            // Message content has no room to spare; the list goes with the body and is cut with it
+            Format::Plain => {
+                email.body.push_str(&format!("\n{}", footnotes::list(&urls, &email.body, usize::MAX)));
+                discord::build_plain_payload(&email, archive::url(config, &email.trace_id).as_deref(), marker)
+            }
+        };
+    }
+    match format {
+        Format::Embed => embed(email),
+        Format::Plain => discord::build_plain_payload(email, archive::url(config, &email.trace_id).as_deref(), marker),
+    }
+}
+
+// The route's truncation_marker, else the global one, with the archive link filled in.
+// A marker that needs the link falls back to the plain one when the email has none.
+fn truncation_marker(config: &Config, route: Option<&Route>, email: &Email) -> Option<String> {
+    let template = route.and_then(|r| r.truncation_marker.as_deref()).or(config.truncation_marker.as_deref())?;
+    if !template.contains("{archive_url}") {
+        return Some(template.to_string());
+    }
+    let url = archive::url(config, &email.trace_id)?;
+    Some(template.replace("{archive_url}", &url))
+}
+
+// Renders and posts the email. Returns false if delivery failed and should be retried.
+pub fn deliver(config: &Config, store: &dyn StateStore, email: &Email) -> Result<bool, Error> {
+    let delivered = try_deliver(config, store, email);
+    // Redirects are stored with the render; any still pending belong to one that wasn't
+    shortener::discard(email);
+    delivered
+}
+
+fn try_deliver(config: &Config, store: &dyn StateStore, email: &Email) -> Result<bool, Error> {
+    info!("Processing email: {}", email.subject);
+    if email.body_source != "text/plain" {
+        debug!("Body extracted via {}", email.body_source);
+    }
+
+    let route = routes::find(config, email);
+    let target = route.map_or(webhooks::DEFAULT_TARGET, |r| r.name.as_str());
+    trace::record_route(target);
+    events::emit("routed", email, json!({ "route": route.map(|r| &r.name) }));
+    if let Some(route) = route.filter(|r| r.save_only == Some(true))
+        && let Some(service) = route.save_to
+    {
+        return save_only(config, store, route, service, email);
+    }
+    if let Some(until) = webhooks::paused_until(store, target)? {
+        info!("Deliveries for {} are paused until {}, keeping for later", target, until.to_rfc3339());
+        return Ok(false);
+    }
+    let urgent = categories::is_immediate(config, route);
+    if let Some(route) = route.filter(|_| !urgent)
+        && let Some(next) = webhooks::paced_until(store, route)?
+    {
+        info!("Route {} is paced, queued until {}", route.name, next.to_rfc3339());
+        return Ok(false);
+    }
+    if let Some(route) = route.filter(|_| !urgent)
+        && let Some(until) = webhooks::quiet_until(config, route)?
+    {
+        info!("Route {} is in quiet hours, queued until {}", route.name, until.to_rfc3339());
+        return Ok(false);
+    }
+
+    // Rendered once, then sent from the outbox until it goes through
+    let entry = match outbox::get(store, &email.trace_id)? {
+        Some(entry) => {
+            debug!("Sending the render from {}", entry.created_at.to_rfc3339());
+            entry
+        }
+        None => {
+            // Posted by an earlier run whose source copy outlived the delivery
+            if history::get(store, &email.trace_id)?.is_some_and(|h| matches!(h.status, Status::Delivered | Status::Updated)) {
+                info!("Already delivered, not posting again");
+                return Ok(true);
+            }
+            let (prepared, status) = match resend::check(config, store, email)? {
+                Resend::New => (prepare(config, email), Status::Delivered),
+                Resend::Duplicate(previous) => {
+                    info!("Identical to archived {}, not posting", previous.trace_id);
+                    events::emit("filtered", email, json!({ "reason": "duplicate", "of": previous.trace_id }));
+                    history::record(store, email, Status::Duplicate, Some(previous.trace_id));
+                    return Ok(true);
+                }
+                Resend::Updated(previous) => {
+                    info!("Updated re-send of {}, posting the changes", previous.trace_id);
+                    let payload = resend::build_payload(email, &previous, config.timezone(route));
+                    (Prepared { payload, summarize: None }, Status::Updated)
+                }
+            };
+
+            // Only plain renders are worth comparing against in a replay, so this is kept from
+            // before the (non-deterministic) summary is added
+            let rendered = (status == Status::Delivered).then(|| discord::without_files(&prepared.payload));
+            let mut payload = prepared.payload;
+            if let Some(ref input) = prepared.summarize {
+                summarize::apply(config, store, input, &mut payload);
+            }
+            shortener::save(store, email)?;
+            outbox::put(config, store, email, target, &payload, rendered.as_ref(), status)?
+        }
+    };
+    let (status, rendered) = (entry.status, entry.rendered);
+    let mut payload = entry.payload;
+    let embeds = payload.clone();
+    let (since, retried) = confirm::begin(store, email)?;
+    discord::mark_delivery(&mut payload, since, retried);
+    let deferring = discord::deferring(outbox::can_queue(config, email));
+    let sent = series::send(config, store, email, payload);
+    drop(deferring);
+    match sent {
+        Ok(posted) => {
+            info!("Sent to Discord");
+            events::emit("delivered", email, json!({ "status": status.as_str(), "posted_id": posted.id, "channel_id": posted.channel_id }));
+            confirm::finish(store, email);
+            outbox::remove(store, email);
+            cadence::observe(config, store, email);
+            subscriptions::notify(config, store, email, &embeds);
+            mqtt::publish(config, route, email);
+            homeassistant::notify(config, route, email);
+            irc::announce(config, route, email);
+            xmpp::announce(config, email, &embeds);
+            notes::export(config, route, email);
+            readlater::save(config, route, email);
+            if let Some(route) = route {
+                webhooks::record_post(store, route);
+                reactions::seed(config, store, route, &posted, &email.trace_id);
+            }
+            if archive::enabled(config) {
+                match archive::save(store, email, rendered.as_ref()) {
+                    Ok(()) => {
+                        if let Err(e) = site::update(config, store, &email.trace_id) {
+                            error!("Failed to update the archive site: {}", e);
+                        }
+                    }
+                    Err(e) => error!("Failed to archive email: {}", e),
+                }
+            }
+            // Note how the body was obtained when it took more than the text/plain part
+            let detail = (email.body_source != "text/plain").then(|| format!("body: {}", email.body_source));
+            history::record(store, email, status, detail);
+            experiments::record(config, store, route, email, &posted, &embeds);
+            Ok(true)
+        }
+        Err(e) => {
+            error!("Failed to send to Discord: {}", e);
+            events::emit("failed", email, json!({ "error": e.to_string() }));
+            history::record(store, email, Status::Failed, Some(e.to_string()));
+            match e.delivery_failure() {
+                Some(Failure::Dead) => webhooks::pause(config, store, target, &e),
+                Some(Failure::BadPayload | Failure::TooLarge) => {
+                    let attempts = history::get(store, &email.trace_id)?.map_or(0, |h| h.attempts);
+                    if attempts >= DEAD_LETTER_AFTER {
+                        deadletter::save(store, email, &embeds, &e.to_string())?;
+                        confirm::finish(store, email);
+                        outbox::remove(store, email);
+                        history::record(store, email, Status::DeadLettered, Some(e.to_string()));
+                        events::emit("dead_lettered", email, json!({ "error": e.to_string() }));
+                        ops::alert(
+                            config,
+                            "Email dead-lettered",
+                            &format!("Discord rejected **{}** {} times ({}). It was moved to the dead-letter store.", email.subject, attempts, e),
+                        );
+                        return Ok(true);
+                    }
+                }
+                // Rate limits and outages were already retried; try again next cycle
+                _ => {}
+            }
+            // With [retry], the queue takes it from here and the source copy can go
+            if outbox::queue(config, store, email)? {
+                return Ok(true);
+            }
+            Ok(false)
+        }
+    }
+}
+
+// Routes with `save_only` go to their read-later service instead of the chat. An email the
+// service can't take (a link-only service and no link) is dead-lettered rather than retried.
+fn save_only(config: &Config, store: &dyn StateStore, route: &Route, service: ReadLater, email: &Email) -> Result<bool, Error> {
+    match readlater::push(config, route, service, email) {
+        Ok(()) => {
+            events::emit("delivered", email, json!({ "status": Status::Delivered.as_str(), "saved_to": service.as_str() }));
+            cadence::observe(config, store, email);
+            history::record(store, email, Status::Delivered, Some(format!("saved to {}", service.as_str())));
+            Ok(true)
+        }
+        Err(e @ Error::Config(_)) => {
+            error!("Cannot save to {}: {}", service.as_str(), e);
+            deadletter::save(store, email, &Value::Null, &e.to_string())?;
+            history::record(store, email, Status::DeadLettered, Some(e.to_string()));
+            events::emit("dead_lettered", email, json!({ "error": e.to_string() }));
+            Ok(true)
+        }
+        Err(e) => {
+            error!("Failed to save to {}: {}", service.as_str(), e);
+            events::emit("failed", email, json!({ "error": e.to_string() }));
+            history::record(store, email, Status::Failed, Some(e.to_string()));
+            Ok(false)
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::auth::{AuthError, AuthHealth};
+use crate::cadence;
+use crate::config::Config;
+use crate::error::Error;
+use crate::leader::Leader;
+use crate::monitor::{self, POLL_INTERVAL};
+use crate::retention::Pruner;
+use crate::shutdown;
+use crate::source::{self, MailSource};
+use crate::state::StateStore;
+use crate::watchdog::Watchdog;
+use native_tls::TlsStream;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// A POP3 session over TLS (RFC 1939): USER/PASS, then LIST, RETR or TOP, and DELE. The
+// deletions only happen at QUIT, so a session that breaks off leaves the mailbox as it was.
+pub struct Pop3 {
+    stream: BufReader<TlsStream<TcpStream>>,
+}
+
+impl Pop3 {
+    pub fn connect(config: &Config, watchdog: Option<&Watchdog>) -> Result<Pop3, Error> {
+        let mut pop3 = Pop3 { stream: BufReader::new(monitor::tls_connect(config, watchdog)?) };
+        pop3.reply()?;
+        Ok(pop3)
+    }
+
+    pub fn login(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        self.command(&format!("USER {}", username))?;
+        self.write(&format!("PASS {}", password))?;
+        match self.status()? {
+            Ok(_) => Ok(()),
+            Err(message) => Err(AuthError(message).into()),
+        }
+    }
+
+    fn write(&mut self, line: &str) -> Result<(), Error> {
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{}\r\n", line).as_bytes())?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    fn line(&mut self) -> Result<Vec<u8>, Error> {
+        let mut line = Vec::new();
+        if self.stream.read_until(b'\n', &mut line)? == 0 {
+            return Err(Error::imap("POP3 server closed the connection"));
+        }
+        Ok(line)
+    }
+
+    // "+OK text" or "-ERR text"
+    fn status(&mut self) -> Result<Result<String, String>, Error> {
+        let line = String::from_utf8_lossy(&self.line()?).trim_end().to_string();
+        if let Some(text) = line.strip_prefix("+OK") {
+            return Ok(Ok(text.trim().to_string()));
+        }
+        Ok(Err(line.strip_prefix("-ERR").unwrap_or(&line).trim().to_string()))
+    }
+
+    fn reply(&mut self) -> Result<String, Error> {
+        self.status()?.map_err(|message| Error::Imap(format!("POP3 server said: {}", message)))
+    }
+
+    fn command(&mut self, line: &str) -> Result<String, Error> {
+        self.write(line)?;
+        self.reply()
+    }
+
+    // The lines of a multi-line reply up to the lone ".", with the byte-stuffing undone
+    fn multiline(&mut self, line: &str) -> Result<Vec<u8>, Error> {
+        self.command(line)?;
+        let mut data = Vec::new();
+        loop {
+            let line = self.line()?;
+            let content = line.strip_suffix(b"\r\n").or_else(|| line.strip_suffix(b"\n")).unwrap_or(&line);
+            if content == b"." {
+                return Ok(data);
+            }
+            data.extend_from_slice(content.strip_prefix(b".").unwrap_or(content));
+            data.extend_from_slice(b"\r\n");
+        }
+    }
+}
+
+impl MailSource for Pop3 {
+    fn protocol(&self) -> &'static str {
+        "pop3"
+    }
+
+    fn list(&mut self) -> Result<Vec<(u32, u64)>, Error> {
+        let listing = self.multiline("LIST")?;
+        let mut messages: Vec<(u32, u64)> = String::from_utf8_lossy(&listing)
+            .lines()
+            .filter_map(|line| {
+                let (id, size) = line.trim().split_once(' ')?;
+                Some((id.parse().ok()?, size.trim().parse().ok()?))
+            })
+            .collect();
+        messages.sort();
+        Ok(messages)
+    }
+
+    fn fetch(&mut self, id: u32, headers_only: bool) -> Result<Vec<u8>, Error> {
+        match headers_only {
+            true => self.multiline(&format!("TOP {} 0", id)),
+            false => self.multiline(&format!("RETR {}", id)),
+        }
+    }
+
+    fn remove(&mut self, id: u32) -> Result<(), Error> {
+        self.command(&format!("DELE {}", id)).map(drop)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.command("QUIT").map(drop)
+    }
+}
+
+// The worker for a POP3 mailbox. POP3 can't wait for new mail, so it logs in every
+// pop3_poll_seconds, takes what's there and logs out again.
+pub fn run_monitor(
+    config: &Config,
+    store: &dyn StateStore,
+    leader: Option<&Leader>,
+    watchdog: &Watchdog,
+    health: &mut AuthHealth,
+) -> Result<(), Error> {
+    let mut catching_up = true;
+    let mut logged_in = false;
+    let mut pruner = Pruner::default();
+    let mut watcher = cadence::Watcher::default();
+
+    loop {
+        if let Some(leader) = leader {
+            leader.renew()?;
+        }
+        let mut session = Pop3::connect(config, Some(watchdog))?;
+        session.login(&config.imap_username, &config.imap_password)?;
+        if !logged_in {
+            info!("Logged in as {} (POP3)", config.imap_username);
+            health.record_success(config);
+            crate::health::logged_in(config);
+            logged_in = true;
+        }
+        let more_pending = source::cycle(config, store, &mut session, leader, catching_up)?;
+        watchdog.detach();
+        // A backlog cut short by max_messages_per_cycle is still being caught up on
+        catching_up = catching_up && more_pending;
+
+        monitor::housekeeping(config, store, &mut pruner, &mut watcher);
+        watchdog.beat();
+        crate::health::polled(config);
+
+        let wait = match more_pending {
+            true => POLL_INTERVAL,
+            false => Duration::from_secs(config.pop3_poll_seconds()),
+        };
+        watchdog.beat_after(wait);
+        // The lease is renewed in between, without logging in, so it doesn't run out while
+        // waiting for the next check
+        let until = Instant::now() + wait;
+        loop {
+            let left = until.saturating_duration_since(Instant::now());
+            if !shutdown::sleep(leader.map_or(left, |l| left.min(l.renew_interval()))) {
+                return Ok(());
+            }
+            if Instant::now() >= until {
+                break;
+            }
+            if let Some(leader) = leader {
+                leader.renew()?;
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::config::{Config, Route};
+use crate::discord::Posted;
+use crate::state::StateStore;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, warn};
+
+const PREFIX: &str = "reactions:";
+
+// Seeded messages are remembered so their reaction counts can be collected later.
+#[derive(Serialize, Deserialize)]
+pub struct Seeded {
+    pub channel_id: String,
+    pub message_id: String,
+    pub route: String,
+    pub trace_id: String,
+    pub reactions: Vec<String>,
+    pub posted_at: chrono::DateTime<chrono::Utc>,
+}
+
+// Adds the route's configured reactions to a freshly posted message. Webhooks can't react,
+// so this goes through the bot API and needs `discord_bot_token`.
+pub fn seed(config: &Config, store: &dyn StateStore, route: &Route, posted: &Posted, trace_id: &str) {
+    let Some(ref reactions) = route.reactions else {
+        return;
+    };
+    let Some(ref token) = config.discord_bot_token else {
+        warn!("Route {} has reactions but discord_bot_token is not set", route.name);
+        return;
+    };
+    if posted.id.is_empty() || posted.channel_id.is_empty() {
+        return;
+    }
+
+    for emoji in reactions {
+        // Path segments are percent-encoded by the URL builder, which the emoji needs
+        let mut url = reqwest::Url::parse("https://discord.com/api/v10/channels/").unwrap();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments
+                .pop_if_empty()
+                .extend([posted.channel_id.as_str(), "messages", posted.id.as_str(), "reactions", emoji, "@me"]);
+        }
+        let result = crate::http::client()
+            .put(url)
+            .header("Authorization", format!("Bot {}", token))
+            .header("Content-Length", "0")
+            .send();
+        match result {
+            Ok(response) if !response.status().is_success() => {
+                error!("Failed to add reaction {}: Status {}", emoji, response.status())
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to add reaction {}: {}", emoji, e),
+        }
+        // Reactions are rate limited to roughly one every 250ms per channel
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    let seeded = Seeded {
+        channel_id: posted.channel_id.clone(),
+        message_id: posted.id.clone(),
+        route: route.name.clone(),
+        trace_id: trace_id.to_string(),
+        reactions: reactions.clone(),
+        posted_at: chrono::Utc::now(),
+    };
+    if let Err(e) = store.put_json(&format!("{}{}", PREFIX, posted.id), &seeded) {
+        error!("Failed to record seeded reactions: {}", e);
+    }
+}
+
+// Reactions on a posted message, not counting the ones the bot seeded
+pub fn count(token: &str, channel_id: &str, message_id: &str) -> Result<u64, crate::error::Error> {
+    let message: serde_json::Value = crate::http::client()
+        .get(format!("https://discord.com/api/v10/channels/{}/messages/{}", channel_id, message_id))
+        .header("Authorization", format!("Bot {}", token))
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(message["reactions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|r| r["count"].as_u64().unwrap_or(0).saturating_sub(u64::from(r["me"].as_bool() == Some(true))))
+        .sum())
+}
@@ -0,0 +1,157 @@
+use crate::archive;
+use crate::config::{Config, ReadLater, ReadLaterConfig, Route};
+use crate::error::Error;
+use crate::mail::{self, Email};
+use regex::Regex;
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use tracing::{error, info};
+
+// `<a href="...">View in browser</a>` and the like: the sender's own copy of the email
+static WEB_VERSION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<a\s[^>]*href\s*=\s*["']([^"']+)["'][^>]*>(.*?)</a>"#).unwrap()
+});
+static WEB_VERSION_TEXT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)view (this email |it )?(in (your |a )?browser|online|as a web ?page)|web version|read (it )?online").unwrap()
+});
+static TAGS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+// Saves a delivered email to the route's read-later service, next to the chat post.
+// Failures are logged and don't hold up the delivery.
+pub fn save(config: &Config, route: Option<&Route>, email: &Email) {
+    let Some(route) = route.filter(|r| r.save_only != Some(true)) else {
+        return;
+    };
+    if let Some(service) = route.save_to
+        && let Err(e) = push(config, route, service, email)
+    {
+        error!("Failed to save to {}: {}", service.as_str(), e);
+    }
+}
+
+// Readwise Reader gets the email itself (its HTML, or the text), so it reads like any other
+// article there; Pocket and Instapaper only store links, so they get the archived copy or
+// the sender's web version, and an email with neither can't be saved to them.
+pub fn push(config: &Config, route: &Route, service: ReadLater, email: &Email) -> Result<(), Error> {
+    let read_later = config.read_later.as_ref().ok_or_else(|| Error::config("save_to needs a [read_later] section"))?;
+    let html = email.raw.as_deref().and_then(mail::html_part).map(|(html, _)| html);
+    let link = archive::url(config, &email.trace_id).or_else(|| html.as_deref().and_then(web_version));
+    let mut tags = vec![route.name.clone()];
+    tags.extend(route.category.clone());
+
+    match service {
+        ReadLater::Readwise => readwise(read_later, email, link, html, &tags)?,
+        ReadLater::Pocket | ReadLater::Instapaper => {
+            let link = link.ok_or_else(|| {
+                Error::Config(format!(
+                    "{} only saves links, and this email has no web version; enable [archive] with server.public_url",
+                    service.as_str()
+                ))
+            })?;
+            match service {
+                ReadLater::Pocket => pocket(read_later, email, &link, &tags)?,
+                _ => instapaper(read_later, email, &link)?,
+            }
+        }
+    }
+    info!("Saved to {}", service.as_str());
+    Ok(())
+}
+
+fn readwise(
+    read_later: &ReadLaterConfig,
+    email: &Email,
+    link: Option<String>,
+    html: Option<String>,
+    tags: &[String],
+) -> Result<(), Error> {
+    let readwise = read_later.readwise.as_ref().ok_or_else(|| Error::config("save_to = \"readwise\" needs [read_later.readwise]"))?;
+    // Reader identifies documents by URL; without a real one, any unique URL will do
+    let url = link.unwrap_or_else(|| format!("https://newsletter.invalid/{}", email.trace_id));
+    let html = html.unwrap_or_else(|| {
+        email
+            .body
+            .split("\n\n")
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| format!("<p>{}</p>", escape(p).replace('\n', "<br>")))
+            .collect()
+    });
+    let mut body = json!({
+        "url": url,
+        "html": html,
+        "should_clean_html": true,
+        "title": email.subject,
+        "author": email.from,
+        "category": "email",
+        "tags": tags,
+        "saved_using": "newsletter",
+    });
+    if let Some(date) = email.date {
+        body["published_date"] = Value::String(date.to_rfc3339());
+    }
+    if let Some(ref location) = readwise.location {
+        body["location"] = Value::String(location.clone());
+    }
+    let response = crate::http::client()
+        .post("https://readwise.io/api/v3/save/")
+        .header("Authorization", format!("Token {}", readwise.token))
+        .json(&body)
+        .send()?;
+    check(response)
+}
+
+fn pocket(read_later: &ReadLaterConfig, email: &Email, link: &str, tags: &[String]) -> Result<(), Error> {
+    let pocket = read_later.pocket.as_ref().ok_or_else(|| Error::config("save_to = \"pocket\" needs [read_later.pocket]"))?;
+    let response = crate::http::client()
+        .post("https://getpocket.com/v3/add")
+        .header("X-Accept", "application/json")
+        .json(&json!({
+            "url": link,
+            "title": email.subject,
+            // Pocket splits tags on commas
+            "tags": tags.iter().map(|t| t.replace(',', " ")).collect::<Vec<_>>().join(","),
+            "consumer_key": pocket.consumer_key,
+            "access_token": pocket.access_token,
+        }))
+        .send()?;
+    check(response)
+}
+
+fn instapaper(read_later: &ReadLaterConfig, email: &Email, link: &str) -> Result<(), Error> {
+    let instapaper =
+        read_later.instapaper.as_ref().ok_or_else(|| Error::config("save_to = \"instapaper\" needs [read_later.instapaper]"))?;
+    let selection = format!("From {}", email.from);
+    let response = crate::http::client()
+        .post("https://www.instapaper.com/api/add")
+        .basic_auth(&instapaper.username, instapaper.password.as_deref())
+        .form(&[("url", link), ("title", email.subject.as_str()), ("selection", selection.as_str())])
+        .send()?;
+    check(response)
+}
+
+fn check(response: reqwest::blocking::Response) -> Result<(), Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    // Pocket explains itself in a header, the others in the body
+    let reason = match response.headers().get("X-Error").and_then(|v| v.to_str().ok()) {
+        Some(reason) => reason.to_string(),
+        None => response.text().unwrap_or_default(),
+    };
+    Err(Error::Network(format!("Status {}: {}", status, reason.trim())))
+}
+
+// The link whose text offers the email in a browser, if the sender has one
+fn web_version(html: &str) -> Option<String> {
+    WEB_VERSION
+        .captures_iter(html)
+        .find(|c| WEB_VERSION_TEXT.is_match(&TAGS.replace_all(&c[2], "")))
+        .map(|c| c[1].replace("&amp;", "&"))
+        .filter(|url| url.starts_with("https://") || url.starts_with("http://"))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
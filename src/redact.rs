@@ -0,0 +1,41 @@
+use crate::config::Config;
+use crate::mail::Email;
+use crate::routes;
+use regex::Regex;
+use tracing::{debug, warn};
+
+// Drops paragraphs (blocks separated by a blank line) matching any of the global or
+// route-specific `redact_paragraphs` patterns. Only the posted copy is redacted; the
+// archive keeps the original.
+pub fn apply(config: &Config, email: &Email) -> Email {
+    let route_patterns = routes::find(config, email).and_then(|r| r.redact_paragraphs.as_ref());
+    let patterns: Vec<Regex> = config
+        .redact_paragraphs
+        .iter()
+        .chain(route_patterns)
+        .flatten()
+        .filter_map(|p| match Regex::new(p) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                warn!("Invalid redact_paragraphs pattern {:?}: {}", p, e);
+                None
+            }
+        })
+        .collect();
+
+    let mut redacted = email.clone();
+    if patterns.is_empty() {
+        return redacted;
+    }
+    let kept: Vec<&str> = email
+        .body
+        .split("\n\n")
+        .filter(|paragraph| !patterns.iter().any(|re| re.is_match(paragraph)))
+        .collect();
+    let removed = email.body.split("\n\n").count() - kept.len();
+    if removed > 0 {
+        debug!("Redacted {} paragraph(s)", removed);
+        redacted.body = kept.join("\n\n");
+    }
+    redacted
+}
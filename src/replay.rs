@@ -0,0 +1,101 @@
+use crate::{archive, discord, monitor, notify, pipeline};
+use crate::config::{Config, parse_duration};
+use crate::diff::{self, Change};
+use crate::error::Error;
+use crate::mail::Email;
+use crate::state::StateStore;
+use clap::ValueEnum;
+use serde_json::Value;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Target {
+    /// Only report differences
+    DryRun,
+    /// Also post the new renders to --webhook-url
+    Webhook,
+}
+
+// Re-renders archived emails from their original messages with the current configuration
+// and code, and reports every payload that differs from what was delivered at the time.
+pub fn run(
+    config: &Config,
+    store: &dyn StateStore,
+    since: &str,
+    target: Target,
+    webhook_url: Option<&str>,
+) -> Result<(), Error> {
+    let since = chrono::Utc::now() - parse_duration(since).map_err(Error::Config)?;
+    let webhook_url = match target {
+        Target::DryRun => None,
+        Target::Webhook => Some(webhook_url.ok_or_else(|| Error::config("--target webhook needs --webhook-url"))?),
+    };
+
+    let (mut unchanged, mut changed, mut ignored, mut skipped) = (0, 0, 0, 0);
+    for archived in archive::all(store)?.into_iter().filter(|a| a.archived_at >= since) {
+        // Archived before raw messages were kept, or not received as a message at all
+        let Some(ref raw) = archived.raw else {
+            skipped += 1;
+            continue;
+        };
+        let email = Email::parse(&openssl::base64::decode_block(raw)?)?;
+        if monitor::is_ignored(config, &email) {
+            println!("{} {}: now ignored", archived.trace_id, archived.subject);
+            ignored += 1;
+            continue;
+        }
+
+        let payload = pipeline::render(config, &email);
+        match archived.payload {
+            Some(ref original) if strip_volatile(original) == strip_volatile(&payload) => unchanged += 1,
+            Some(ref original) => {
+                println!("{} {}: payload changed", archived.trace_id, archived.subject);
+                print_diff(original, &payload)?;
+                changed += 1;
+            }
+            None => {
+                println!("{} {}: no delivered payload to compare with", archived.trace_id, archived.subject);
+                skipped += 1;
+            }
+        }
+
+        if let Some(url) = webhook_url
+            && let Err(e) = notify::webhook(config.notifier(None), url).send(&payload, None)
+        {
+            eprintln!("{} Failed to post replay: {}", archived.trace_id, e);
+        }
+    }
+
+    println!(
+        "Replayed archive since {}: {} unchanged, {} changed, {} now ignored, {} skipped",
+        since.to_rfc3339(),
+        unchanged,
+        changed,
+        ignored,
+        skipped
+    );
+    Ok(())
+}
+
+// Embed timestamps are the time of posting and always differ. Uploads aren't archived.
+fn strip_volatile(payload: &Value) -> Value {
+    let mut payload = discord::without_files(payload);
+    if let Some(embeds) = payload["embeds"].as_array_mut() {
+        for embed in embeds.iter_mut().filter_map(Value::as_object_mut) {
+            embed.remove("timestamp");
+        }
+    }
+    payload
+}
+
+fn print_diff(original: &Value, payload: &Value) -> Result<(), Error> {
+    let old = serde_json::to_string_pretty(&strip_volatile(original))?;
+    let new = serde_json::to_string_pretty(&strip_volatile(payload))?;
+    for change in diff::lines(&old, &new) {
+        match change {
+            Change::Same => {}
+            Change::Removed(line) => println!("  - {}", line),
+            Change::Added(line) => println!("  + {}", line),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use crate::archive::{self, Archived};
+use crate::config::Config;
+use crate::diff::{self, Change};
+use crate::discord;
+use crate::error::Error;
+use crate::mail::Email;
+use crate::state::StateStore;
+use serde_json::Value;
+
+pub enum Resend {
+    New,
+    // Same sender, subject and body as an archived email
+    Duplicate(Archived),
+    // A close variant of an archived email; only the changes are worth posting
+    Updated(Archived),
+}
+
+pub fn check(config: &Config, store: &dyn StateStore, email: &Email) -> Result<Resend, Error> {
+    let Some(resend) = config.resend.as_ref().filter(|r| r.enabled && archive::enabled(config)) else {
+        return Ok(Resend::New);
+    };
+    let Some(previous) = archive::previous_version(store, email)? else {
+        return Ok(Resend::New);
+    };
+    if (chrono::Utc::now() - previous.archived_at).num_days() > resend.window_days() {
+        return Ok(Resend::New);
+    }
+    if previous.body == email.body {
+        return Ok(Resend::Duplicate(previous));
+    }
+    if previous.body.lines().count() > diff::MAX_LINES || email.body.lines().count() > diff::MAX_LINES {
+        return Ok(Resend::New);
+    }
+    let changes = diff::lines(&previous.body, &email.body);
+    if diff::similarity(&changes) < resend.min_similarity() {
+        return Ok(Resend::New);
+    }
+    Ok(Resend::Updated(previous))
+}
+
+pub fn build_payload(email: &Email, previous: &Archived, tz: chrono_tz::Tz) -> Value {
+    let changes = diff::lines(&previous.body, &email.body);
+    let mut description = String::new();
+    let mut omitted = 0;
+    for change in &changes {
+        let line = match change {
+            Change::Same => continue,
+            Change::Removed(l) => format!("- {}\n", l),
+            Change::Added(l) => format!("+ {}\n", l),
+        };
+        // Leave room for the code fence and the summary line within the 4096 char limit
+        if omitted > 0 || description.chars().count() + line.chars().count() > 3800 {
+            omitted += 1;
+            continue;
+        }
+        description.push_str(&line);
+    }
+    let mut description = format!("```diff\n{}```", description);
+    if omitted > 0 {
+        description.push_str(&format!("\n…and {} more changed lines", omitted));
+    }
+
+    serde_json::json!({
+        "embeds": [{
+            "title": discord::embed_title(&format!("✏️ Updated: {}", email.subject)),
+            "author": {
+                "name": email.from
+            },
+            "description": description,
+            "color": 0xFEE75C, // Yellow
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "footer": {
+                "text": format!("📰 Newsletter · changes since the version received {}", previous.archived_at.with_timezone(&tz).format("%Y-%m-%d %H:%M %Z"))
+            }
+        }]
+    })
+}
@@ -0,0 +1,86 @@
+use crate::archive::Archived;
+use crate::config::{Config, parse_duration};
+use crate::error::Error;
+use crate::history::Entry;
+use crate::otel;
+use crate::state::StateStore;
+use chrono::Utc;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+// Runs the pruning job from the monitor loop at most every `retention.interval_minutes`.
+#[derive(Default)]
+pub struct Pruner {
+    last_run: Option<Instant>,
+}
+
+impl Pruner {
+    pub fn maybe_run(&mut self, config: &Config, store: &dyn StateStore) {
+        let Some(ref retention) = config.retention else {
+            return;
+        };
+        let interval = Duration::from_secs(retention.interval_minutes() * 60);
+        if self.last_run.is_some_and(|last| last.elapsed() < interval) {
+            return;
+        }
+        self.last_run = Some(Instant::now());
+
+        match prune(config, store) {
+            Ok((0, 0)) => {}
+            Ok((archived, history)) => {
+                info!("Pruned {} archived emails and {} history entries", archived, history)
+            }
+            Err(e) => error!("Failed to prune archive/history: {}", e),
+        }
+    }
+}
+
+// Returns the number of (archived emails, history entries) removed.
+pub fn prune(config: &Config, store: &dyn StateStore) -> Result<(usize, usize), Error> {
+    let Some(ref retention) = config.retention else {
+        return Ok((0, 0));
+    };
+    let cutoff = match retention.keep {
+        Some(ref keep) => Some(Utc::now() - parse_duration(keep).map_err(Error::Config)?),
+        None => None,
+    };
+
+    let mut archived: Vec<(String, Archived)> = store
+        .entries("archive:")?
+        .into_iter()
+        .filter_map(|(key, raw)| serde_json::from_str(&raw).ok().map(|a| (key, a)))
+        .collect();
+    archived.sort_by_key(|(_, a)| a.archived_at);
+
+    let expired = cutoff.map_or(0, |cutoff| archived.iter().take_while(|(_, a)| a.archived_at < cutoff).count());
+    let over_limit = retention
+        .max_entries
+        .map_or(0, |max| archived.len().saturating_sub(max));
+    let remove = expired.max(over_limit);
+    for (key, _) in archived.drain(..remove) {
+        store.delete(&key)?;
+    }
+
+    // Index entries pointing at pruned emails
+    let remaining: HashSet<&str> = archived.iter().map(|(_, a)| a.trace_id.as_str()).collect();
+    for (key, trace_id) in store.entries("archive-index:")? {
+        if !remaining.contains(trace_id.as_str()) {
+            store.delete(&key)?;
+        }
+    }
+
+    let mut history_removed = 0;
+    if let Some(cutoff) = cutoff {
+        for (key, raw) in store.entries("history:")? {
+            if serde_json::from_str::<Entry>(&raw).is_ok_and(|e| e.updated < cutoff) {
+                store.delete(&key)?;
+                history_removed += 1;
+            }
+        }
+    }
+
+    otel::add("newsletter.pruned", Some(("kind", "archive")), remove as u64);
+    otel::add("newsletter.pruned", Some(("kind", "history")), history_removed as u64);
+    Ok((remove, history_removed))
+}
@@ -0,0 +1,53 @@
+use crate::config::{Config, FilterAction, Route};
+use crate::{domains, filters};
+use crate::mail::Email;
+use regex::Regex;
+use tracing::warn;
+
+pub fn find<'a>(config: &'a Config, email: &Email) -> Option<&'a Route> {
+    if let Some((_, filter)) = filters::first_match(config, email)
+        && filter.action == FilterAction::Route
+    {
+        return config.routes.iter().flatten().find(|route| filter.route.as_ref() == Some(&route.name));
+    }
+    config.routes.iter().flatten().find(|route| matches(route, email))
+}
+
+fn matches(route: &Route, email: &Email) -> bool {
+    let sender = route
+        .senders
+        .as_ref()
+        .is_some_and(|senders| senders.iter().any(|s| email.from.contains(s)));
+    let subject = route
+        .subjects
+        .as_ref()
+        .is_some_and(|subjects| subjects.iter().any(|s| email.subject.contains(s)));
+    let recipient = route
+        .recipients
+        .as_ref()
+        .is_some_and(|recipients| recipients.iter().any(|r| recipient_matches(r, email)));
+    let domain = route
+        .domains
+        .as_ref()
+        .is_some_and(|domains| domains.iter().any(|d| domains::matches(d, &email.from)));
+    let sender_regex = route.sender_regex.as_ref().is_some_and(|p| regex_matches(route, "sender_regex", p, &email.from));
+    let subject_regex =
+        route.subject_regex.as_ref().is_some_and(|p| regex_matches(route, "subject_regex", p, &email.subject));
+    sender || subject || recipient || domain || sender_regex || subject_regex
+}
+
+// Case-insensitive, since addresses are
+pub fn recipient_matches(recipient: &str, email: &Email) -> bool {
+    let recipient = recipient.to_lowercase();
+    email.recipients.iter().any(|value| value.to_lowercase().contains(&recipient))
+}
+
+pub fn regex_matches(route: &Route, key: &str, pattern: &str, text: &str) -> bool {
+    match Regex::new(pattern) {
+        Ok(re) => re.is_match(text),
+        Err(e) => {
+            warn!("Route {}: invalid {} {:?}: {}", route.name, key, pattern, e);
+            false
+        }
+    }
+}
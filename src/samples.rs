@@ -0,0 +1,17 @@
+// Realistic emails bundled into the binary so `send-test --sample` can preview each kind
+// of message in the channel before pointing the monitor at a real mailbox.
+pub const SAMPLES: &[(&str, &[u8])] = &[
+    ("plain", include_bytes!("../samples/plain.eml")),
+    ("html-digest", include_bytes!("../samples/html-digest.eml")),
+    ("cjk", include_bytes!("../samples/cjk.eml")),
+    ("attachments", include_bytes!("../samples/attachments.eml")),
+    ("long-body", include_bytes!("../samples/long-body.eml")),
+];
+
+pub fn names() -> Vec<&'static str> {
+    SAMPLES.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn find(name: &str) -> Option<&'static [u8]> {
+    SAMPLES.iter().find(|(n, _)| *n == name).map(|(_, raw)| *raw)
+}
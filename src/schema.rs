@@ -0,0 +1,74 @@
+use crate::mail::Email;
+use crate::series::sender_address;
+use serde_json::{Value, json};
+
+// Version of the JSON that leaves through the machine-readable outputs: the event lines
+// ([events]) and the MQTT messages. Adding a field keeps the version; renaming, removing or
+// retyping one bumps it, so consumers can check `schema_version` and refuse what they don't
+// know. The schema itself is served at GET /schema/v<version>.json.
+pub const VERSION: u32 = 1;
+
+// The email as MQTT publishes it
+pub fn email(email: &Email, route: &str) -> Value {
+    json!({
+        "schema_version": VERSION,
+        "trace_id": email.trace_id,
+        "message_id": email.message_id,
+        "route": route,
+        "from": email.from,
+        "sender": sender_address(&email.from),
+        "domain": crate::domains::of_sender(&email.from),
+        "subject": email.subject,
+        "date": email.date.map(|d| d.to_rfc3339()),
+        "folder": email.folder,
+    })
+}
+
+// JSON Schema (draft 2020-12) for both outputs
+pub fn document() -> Value {
+    let string = json!({ "type": "string" });
+    let nullable = json!({ "type": ["string", "null"] });
+    let timestamp = json!({ "type": "string", "format": "date-time" });
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!("newsletter/schema/v{}.json", VERSION),
+        "title": "newsletter outputs",
+        "version": VERSION,
+        "$defs": {
+            "email": {
+                "description": "MQTT message per delivered email",
+                "type": "object",
+                "required": ["schema_version", "trace_id", "route", "from", "sender", "subject"],
+                "properties": {
+                    "schema_version": { "const": VERSION },
+                    "trace_id": string,
+                    "message_id": nullable,
+                    "route": { "type": "string", "description": "The route's name, or \"default\"" },
+                    "from": { "type": "string", "description": "The From header as received" },
+                    "sender": { "type": "string", "description": "The bare address from `from`, lowercased" },
+                    "domain": { "type": "string", "description": "The sender's registered domain, e.g. substack.com" },
+                    "subject": string,
+                    "date": { "type": ["string", "null"], "format": "date-time" },
+                    "folder": { "type": ["string", "null"], "description": "Set when several folders are monitored" },
+                },
+            },
+            "event": {
+                "description": "Line in the events file or socket, one per lifecycle step",
+                "type": "object",
+                "required": ["schema_version", "ts", "event", "trace_id"],
+                "properties": {
+                    "schema_version": { "const": VERSION },
+                    "ts": timestamp,
+                    "event": {
+                        "enum": ["fetched", "filtered", "routed", "delivered", "failed", "dead_lettered", "deleted"],
+                    },
+                    "trace_id": string,
+                    "message_id": nullable,
+                    "from": string,
+                    "subject": string,
+                },
+                "additionalProperties": { "description": "Details of the step, e.g. `route` or `error`" },
+            },
+        },
+    })
+}
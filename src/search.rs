@@ -0,0 +1,30 @@
+use chrono::{Datelike, NaiveDate, Utc};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// An absolute date ("2026-10-01") or one relative to now ("-7d", "-36h")
+pub fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    let s = s.trim();
+    if let Some(relative) = s.strip_prefix('-') {
+        return Ok((Utc::now() - crate::config::parse_duration(relative)?).date_naive());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| format!("Invalid date {} (use YYYY-MM-DD or e.g. -7d)", s))
+}
+
+// RFC 3501 date literal, e.g. `8-Oct-2026`. Month names are spelled out here rather than
+// formatted, since IMAP only understands the English abbreviations.
+pub fn imap_date(date: NaiveDate) -> String {
+    format!("{}-{}-{}", date.day(), MONTHS[date.month0() as usize], date.year())
+}
+
+// SEARCH criteria for messages received in [since, before); "ALL" without bounds
+pub fn criteria(since: Option<&str>, before: Option<&str>) -> Result<String, String> {
+    let mut parts = Vec::new();
+    if let Some(since) = since {
+        parts.push(format!("SINCE {}", imap_date(parse_date(since)?)));
+    }
+    if let Some(before) = before {
+        parts.push(format!("BEFORE {}", imap_date(parse_date(before)?)));
+    }
+    Ok(if parts.is_empty() { "ALL".to_string() } else { parts.join(" ") })
+}
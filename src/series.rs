@@ -0,0 +1,182 @@
+use crate::config::{Config, SeriesGroup, SeriesMode};
+use crate::discord::{self, Posted};
+use crate::error::Error;
+use crate::mail::Email;
+use crate::{routes, webhooks};
+use crate::state::StateStore;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use tracing::{info, warn};
+
+const PREFIX: &str = "series:";
+
+// "Issue #142", "No. 12", "Vol 3", "Episode 7", "2026-10-15", "Oct 15, 2026"
+static ISSUE_MARKER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)(#\s*\d+|\b(issue|no\.?|vol\.?|volume|edition|episode|ep\.?|part)\s*#?\d+|\b\d{4}-\d{2}-\d{2}\b|\b(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.? \d{1,2}(, \d{4})?)",
+    )
+    .unwrap()
+});
+
+#[derive(Serialize, Deserialize)]
+struct Series {
+    thread_id: Option<String>,
+    last_message_id: String,
+    last_channel_id: String,
+    last_subject: String,
+}
+
+// Emails belong to the same series when they come from the same sender and their subjects
+// share everything before the issue number or date, e.g. "Tech Weekly #141: ..." and
+// "Tech Weekly #142: ...".
+pub fn series_key(email: &Email) -> Option<String> {
+    let marker = ISSUE_MARKER.find(&email.subject)?;
+    let name = email.subject[..marker.start()].trim().trim_end_matches(['-', '—', '|', ':']).trim();
+    Some(format!("{}|{}", sender_address(&email.from), name.to_lowercase()))
+}
+
+// The newsletter an email is from: the ID part of its List-Id, else the sender address
+pub fn newsletter_key(email: &Email) -> String {
+    match email.list.id.as_deref() {
+        Some(id) => sender_address(id),
+        None => sender_address(&email.from),
+    }
+}
+
+// What a newsletter is called: the List-Id's description or the sender's display name
+fn newsletter_name(email: &Email) -> String {
+    let phrase = |header: &str| header.rfind('<').map(|i| header[..i].trim().trim_matches('"').to_string());
+    email
+        .list
+        .id
+        .as_deref()
+        .and_then(phrase)
+        .or_else(|| phrase(&email.from))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| newsletter_key(email))
+}
+
+pub fn sender_address(from: &str) -> String {
+    match (from.rfind('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => from[start + 1..end].to_lowercase(),
+        _ => from.trim().to_lowercase(),
+    }
+}
+
+// Delivers a rendered email, keeping issues of a series together: either in one forum
+// thread, or with a link back to the previous issue.
+pub fn send(
+    config: &Config,
+    store: &dyn StateStore,
+    email: &Email,
+    mut payload: Value,
+) -> Result<Posted, Error> {
+    let route = routes::find(config, email);
+    let series = config.series.as_ref().filter(|s| s.enabled);
+    let group = series.and_then(|s| s.group_by).unwrap_or_default();
+    let key = match group {
+        SeriesGroup::Series => series_key(email),
+        SeriesGroup::Newsletter => Some(newsletter_key(email)),
+    };
+    let Some((series, key)) = series.zip(key) else {
+        return webhooks::send(config, route, &payload, None);
+    };
+    let state_key = format!("{}{}", PREFIX, key);
+    let previous = store.get_json::<Series>(&state_key)?;
+    let mode = series.mode.unwrap_or_default();
+    // Threads are named after the series' first issue, or after the newsletter
+    let name: String = match group {
+        SeriesGroup::Series => email.subject.chars().take(100).collect(),
+        SeriesGroup::Newsletter => newsletter_name(email).chars().take(100).collect(),
+    };
+
+    let mut thread_id = match mode {
+        SeriesMode::Thread | SeriesMode::TextThread => previous.as_ref().and_then(|p| p.thread_id.clone()),
+        SeriesMode::Link => {
+            if let Some(ref p) = previous
+                && let Some(ref guild_id) = series.guild_id
+            {
+                let url = format!(
+                    "https://discord.com/channels/{}/{}/{}",
+                    guild_id, p.last_channel_id, p.last_message_id
+                );
+                if let Some(embed) = payload["embeds"].get_mut(0) {
+                    // After the full subject and the files left out, when there are those
+                    let field = serde_json::json!({
+                        "name": "Previous issue",
+                        "value": format!("[{}]({})", discord::embed_title(&p.last_subject), url),
+                    });
+                    match embed["fields"].as_array_mut() {
+                        Some(fields) => fields.push(field),
+                        None => embed["fields"] = serde_json::json!([field]),
+                    }
+                }
+            }
+            None
+        }
+    };
+    // Starts a forum post (forum channels only)
+    if mode == SeriesMode::Thread && thread_id.is_none() {
+        payload["thread_name"] = Value::String(name.clone());
+    }
+
+    let posted = match webhooks::send(config, route, &payload, thread_id.as_deref()) {
+        // The thread was deleted: start over with a new one
+        Err(Error::Delivery(ref e)) if thread_id.is_some() && e.status == Some(404) => {
+            warn!("Thread {} for {} is gone, starting a new one", thread_id.unwrap_or_default(), key);
+            thread_id = None;
+            if mode == SeriesMode::Thread {
+                payload["thread_name"] = Value::String(name.clone());
+            }
+            webhooks::send(config, route, &payload, None)?
+        }
+        result => result?,
+    };
+    let thread_id = match mode {
+        SeriesMode::Thread => thread_id.or(Some(posted.channel_id.clone())),
+        // Without a thread yet, the post just made starts one; if that fails, the next
+        // issue tries again
+        SeriesMode::TextThread => thread_id.or_else(|| match start_thread(config, &posted, &name) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                warn!("Failed to start a thread for {}: {}", key, e);
+                None
+            }
+        }),
+        SeriesMode::Link => None,
+    };
+    store.put_json(
+        &state_key,
+        &Series {
+            thread_id,
+            last_message_id: posted.id.clone(),
+            last_channel_id: posted.channel_id.clone(),
+            last_subject: email.subject.clone(),
+        },
+    )?;
+    Ok(posted)
+}
+
+// A public thread on a message, kept open for a week of inactivity (posting reopens it)
+fn start_thread(config: &Config, posted: &Posted, name: &str) -> Result<String, Error> {
+    let token = config.discord_bot_token.as_deref().ok_or_else(|| Error::config("text_thread needs discord_bot_token"))?;
+    if posted.id.is_empty() || posted.channel_id.is_empty() {
+        return Err(Error::network("The post's ID is unknown"));
+    }
+    let response = crate::http::client()
+        .post(format!("https://discord.com/api/v10/channels/{}/messages/{}/threads", posted.channel_id, posted.id))
+        .header("Authorization", format!("Bot {}", token))
+        .json(&json!({ "name": name, "auto_archive_duration": 10080 }))
+        .send()?;
+    let status = response.status();
+    let body: Value = response.json().unwrap_or_default();
+    match body["id"].as_str() {
+        Some(id) if status.is_success() => {
+            info!("Started thread {} ({})", name, id);
+            Ok(id.to_string())
+        }
+        _ => Err(Error::Network(format!("Status {}: {}", status, body["message"].as_str().unwrap_or_default()))),
+    }
+}
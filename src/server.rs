@@ -0,0 +1,148 @@
+use crate::{archive, health, ingest, schema, ses, shortener, subscriptions, webarchive};
+use crate::config::{Config, ShortenerProvider};
+use crate::error::Error;
+use crate::inbound::{self, Provider};
+use crate::state::StateStore;
+use serde_json::{Value, json};
+use std::io::Read;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info};
+
+// Largest request body accepted, generous enough for a raw email with attachments
+const MAX_BODY: u64 = 32 * 1024 * 1024;
+
+pub type HttpResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+// Small blocking HTTP server for push-style integrations. Requests are handled one at a
+// time on this thread, which is plenty for mail volumes.
+pub fn run(config: &Config, store: &dyn StateStore) {
+    let Some(ref server_config) = config.server else {
+        return;
+    };
+    let server = match Server::http(&server_config.listen) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to start HTTP server on {}: {}", server_config.listen, e);
+            return;
+        }
+    };
+    info!("HTTP server listening on {}", server_config.listen);
+    subscriptions::register(config);
+
+    for mut request in server.incoming_requests() {
+        let response = handle(config, store, &mut request);
+        if let Err(e) = request.respond(response) {
+            error!("Failed to send HTTP response: {}", e);
+        }
+    }
+}
+
+fn handle(config: &Config, store: &dyn StateStore, request: &mut Request) -> HttpResponse {
+    let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+    let (path, query) = (path.to_string(), query.to_string());
+    match (request.method(), path.as_str()) {
+        (Method::Post, "/ingest") => {
+            if !authorized(config, request) {
+                return json_response(401, json!({ "error": "unauthorized" }));
+            }
+            match read_body(request) {
+                Ok(body) => ingest::handle(config, store, header(request, "Content-Type").as_deref(), &body),
+                Err(e) => json_response(400, json!({ "error": e.to_string() })),
+            }
+        }
+        (Method::Post, "/ses") => match read_body(request) {
+            Ok(body) => ses::handle(config, store, &body),
+            Err(e) => json_response(400, json!({ "error": e.to_string() })),
+        },
+        (Method::Post, "/inbound/mailgun" | "/inbound/sendgrid") => {
+            let provider = if path.ends_with("mailgun") { Provider::Mailgun } else { Provider::Sendgrid };
+            match read_body(request) {
+                Ok(body) => inbound::handle(
+                    config,
+                    store,
+                    provider,
+                    &query,
+                    header(request, "Content-Type").as_deref(),
+                    &body,
+                ),
+                Err(e) => json_response(400, json!({ "error": e.to_string() })),
+            }
+        }
+        (Method::Post, "/discord/interactions") => match read_body(request) {
+            Ok(body) => subscriptions::handle(
+                config,
+                store,
+                header(request, "X-Signature-Ed25519").as_deref(),
+                header(request, "X-Signature-Timestamp").as_deref(),
+                &body,
+            ),
+            Err(e) => json_response(400, json!({ "error": e.to_string() })),
+        },
+        (Method::Get, "/healthz") => {
+            let (ok, body) = health::live(config);
+            json_response(if ok { 200 } else { 503 }, body)
+        }
+        (Method::Get, "/readyz") => {
+            let (ok, body) = health::ready();
+            json_response(if ok { 200 } else { 503 }, body)
+        }
+        // The current version's schema; older ones aren't served
+        (Method::Get, path) if path == format!("/schema/v{}.json", schema::VERSION) || path == "/schema" => {
+            json_response(200, schema::document())
+        }
+        (Method::Get, path) if path.starts_with("/r/") && redirects(config) => {
+            shortener::follow(store, &path["/r/".len()..])
+        }
+        (Method::Get, path) if path.starts_with("/archive/") && archive::enabled(config) => {
+            webarchive::handle(store, &path["/archive/".len()..], &query)
+        }
+        _ => json_response(404, json!({ "error": "not found" })),
+    }
+}
+
+fn redirects(config: &Config) -> bool {
+    config.shortener.as_ref().is_some_and(|s| s.provider == ShortenerProvider::Builtin)
+}
+
+// Bearer token from `server.ingest_token`. Without a configured token nothing is accepted.
+fn authorized(config: &Config, request: &Request) -> bool {
+    let Some(expected) = config.server.as_ref().and_then(|s| s.ingest_token.as_deref()) else {
+        return false;
+    };
+    let Some(given) = header(request, "Authorization") else {
+        return false;
+    };
+    let given = given.strip_prefix("Bearer ").unwrap_or_default().trim();
+    crate::crypto::secure_eq(given.as_bytes(), expected.as_bytes())
+}
+
+pub fn header(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str().to_string())
+}
+
+pub fn read_body(request: &mut Request) -> Result<Vec<u8>, Error> {
+    let mut body = Vec::new();
+    request.as_reader().take(MAX_BODY + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(Error::parse("Request body too large"));
+    }
+    Ok(body)
+}
+
+pub fn text_response(status: u16, body: String) -> HttpResponse {
+    let content_type = Header::from_bytes("Content-Type", "text/plain; charset=utf-8").unwrap();
+    Response::from_data(body.into_bytes())
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+pub fn json_response(status: u16, body: Value) -> HttpResponse {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_data(body.to_string().into_bytes())
+        .with_status_code(status)
+        .with_header(content_type)
+}
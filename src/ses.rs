@@ -0,0 +1,183 @@
+use crate::config::{Config, SesConfig};
+use crate::crypto::{hex, hmac_sha256};
+use crate::error::Error;
+use crate::ingest;
+use crate::mail::Email;
+use crate::server::{HttpResponse, json_response};
+use crate::state::StateStore;
+use openssl::base64;
+use openssl::hash::{MessageDigest, hash};
+use openssl::sign::Verifier;
+use openssl::x509::X509;
+use regex::Regex;
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use tracing::{info, warn};
+
+// SNS's regional endpoints (sns.us-east-1.amazonaws.com, sns.cn-north-1.amazonaws.com.cn).
+// The label has to look like a region, so S3's sns.s3.amazonaws.com doesn't pass.
+static SNS_HOST: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^sns\.[a-z]{2}(-[a-z]+)+-[0-9]+\.amazonaws\.com(\.cn)?$").unwrap());
+
+// `POST /ses`: SNS notifications from an SES receipt rule. The raw message is either inline
+// (SNS action) or in S3 (S3 action with an SNS topic). Every notification is checked against
+// the SNS signing certificate and the configured topic ARNs before anything else happens.
+pub fn handle(config: &Config, store: &dyn StateStore, body: &[u8]) -> HttpResponse {
+    let Some(ref ses) = config.ses else {
+        return json_response(404, json!({ "error": "not found" }));
+    };
+    let notification: Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(e) => return json_response(400, json!({ "error": format!("Invalid SNS message: {}", e) })),
+    };
+
+    let topic = notification["TopicArn"].as_str().unwrap_or_default();
+    if !ses.topic_arns.iter().any(|arn| arn == topic) {
+        return json_response(403, json!({ "error": "topic not allowed" }));
+    }
+    if let Err(e) = verify_signature(&notification) {
+        warn!("Rejected SNS message from {}: {}", topic, e);
+        return json_response(403, json!({ "error": "invalid signature" }));
+    }
+
+    match notification["Type"].as_str() {
+        Some("SubscriptionConfirmation") => {
+            let url = notification["SubscribeURL"].as_str().unwrap_or_default();
+            match crate::http::client().get(url).send() {
+                Ok(r) if r.status().is_success() => {
+                    info!("Confirmed SNS subscription to {}", topic);
+                    json_response(200, json!({ "status": "subscribed" }))
+                }
+                Ok(r) => json_response(502, json!({ "error": format!("Status {}", r.status()) })),
+                Err(e) => json_response(502, json!({ "error": e.to_string() })),
+            }
+        }
+        Some("Notification") => {
+            let raw = match raw_message(ses, &notification) {
+                Ok(raw) => raw,
+                Err(e) => return json_response(502, json!({ "error": e.to_string() })),
+            };
+            match Email::parse(&raw) {
+                Ok(email) => ingest::respond(ingest::process(config, store, &email), &email),
+                Err(e) => json_response(400, json!({ "error": format!("Invalid email: {}", e) })),
+            }
+        }
+        _ => json_response(200, json!({ "status": "ignored" })),
+    }
+}
+
+fn raw_message(ses: &SesConfig, notification: &Value) -> Result<Vec<u8>, Error> {
+    let message: Value = serde_json::from_str(notification["Message"].as_str().unwrap_or_default())?;
+    let action = &message["receipt"]["action"];
+
+    if let Some(content) = message["content"].as_str() {
+        return Ok(if action["encoding"].as_str() == Some("BASE64") {
+            base64::decode_block(content)?
+        } else {
+            content.as_bytes().to_vec()
+        });
+    }
+    if action["type"].as_str() == Some("S3") {
+        let bucket = action["bucketName"].as_str().ok_or_else(|| Error::parse("S3 action without bucketName"))?;
+        let key = action["objectKey"].as_str().ok_or_else(|| Error::parse("S3 action without objectKey"))?;
+        return s3_get(ses, bucket, key);
+    }
+    Err(Error::parse("Notification carries no message content"))
+}
+
+fn verify_signature(notification: &Value) -> Result<(), Error> {
+    let cert_url = reqwest::Url::parse(notification["SigningCertURL"].as_str().unwrap_or_default()).map_err(Error::parse)?;
+    let host = cert_url.host_str().unwrap_or_default();
+    // Only certificates served by SNS itself can vouch for a message. A looser match would
+    // take in other services' hosts (sns.s3.amazonaws.com serves anyone's bucket).
+    if cert_url.scheme() != "https" || !SNS_HOST.is_match(host) {
+        return Err(Error::Parse(format!("Untrusted signing certificate URL {}", cert_url)));
+    }
+    let cert = X509::from_pem(&crate::http::get(cert_url.as_str())?)?;
+    let digest = match notification["SignatureVersion"].as_str() {
+        Some("1") => MessageDigest::sha1(),
+        Some("2") => MessageDigest::sha256(),
+        other => return Err(Error::Parse(format!("Unsupported SignatureVersion {:?}", other))),
+    };
+
+    let fields: &[&str] = match notification["Type"].as_str() {
+        Some("Notification") => &["Message", "MessageId", "Subject", "Timestamp", "TopicArn", "Type"],
+        _ => &["Message", "MessageId", "SubscribeURL", "Timestamp", "Token", "TopicArn", "Type"],
+    };
+    let mut canonical = String::new();
+    for field in fields {
+        if let Some(value) = notification[*field].as_str() {
+            canonical.push_str(&format!("{}\n{}\n", field, value));
+        }
+    }
+
+    let signature = base64::decode_block(notification["Signature"].as_str().unwrap_or_default())?;
+    let key = cert.public_key()?;
+    let mut verifier = Verifier::new(digest, &key)?;
+    verifier.update(canonical.as_bytes())?;
+    if !verifier.verify(&signature)? {
+        return Err(Error::parse("Signature mismatch"));
+    }
+    Ok(())
+}
+
+// GET with AWS Signature Version 4, enough to read the object SES stored.
+fn s3_get(ses: &SesConfig, bucket: &str, key: &str) -> Result<Vec<u8>, Error> {
+    let access_key = ses.aws_access_key_id.as_deref().ok_or_else(|| Error::config("ses.aws_access_key_id is required for S3"))?;
+    let secret_key = ses
+        .aws_secret_access_key
+        .as_deref()
+        .ok_or_else(|| Error::config("ses.aws_secret_access_key is required for S3"))?;
+    let region = ses.region.as_deref().unwrap_or("us-east-1");
+
+    let host = format!("{}.s3.{}.amazonaws.com", bucket, region);
+    let path: String = key.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+    let now = chrono::Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let day = now.format("%Y%m%d").to_string();
+    let payload_hash = hex(&hash(MessageDigest::sha256(), b"")?);
+
+    let canonical_request = format!(
+        "GET\n/{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+        path, host, payload_hash, amz_date, payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", day, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&hash(MessageDigest::sha256(), canonical_request.as_bytes())?)
+    );
+    let mut signing_key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), day.as_bytes())?;
+    for part in [region, "s3", "aws4_request"] {
+        signing_key = hmac_sha256(&signing_key, part.as_bytes())?;
+    }
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes())?);
+
+    let response = crate::http::client()
+        .get(format!("https://{}/{}", host, path))
+        .header("x-amz-date", amz_date)
+        .header("x-amz-content-sha256", payload_hash)
+        .header(
+            "Authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                access_key, scope, signature
+            ),
+        )
+        .send()?;
+    if !response.status().is_success() {
+        return Err(Error::Network(format!("Status {} fetching s3://{}/{}", response.status(), bucket, key)));
+    }
+    Ok(response.bytes()?.to_vec())
+}
+
+fn uri_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
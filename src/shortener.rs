@@ -0,0 +1,258 @@
+use crate::config::{Config, ShortenerConfig, ShortenerProvider};
+use crate::error::Error;
+use crate::mail::Email;
+use crate::series::sender_address;
+use crate::state::StateStore;
+use crate::{history, server};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use tiny_http::{Header, Response};
+use tracing::{debug, error, info};
+
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"https?://[^\s<>()\[\]"']+"#).unwrap());
+
+// Long URL -> (short URL, when last used), so a link repeated in an email (or across issues)
+// is looked up once. The least recently used link makes room past MAX_SHORTENED.
+static SHORTENED: Mutex<Option<HashMap<String, (String, u64)>>> = Mutex::new(None);
+static USES: AtomicU64 = AtomicU64::new(0);
+const MAX_SHORTENED: usize = 4096;
+
+// Built-in redirects made while rendering, by the email's trace ID and then by ID, until the
+// delivery stores them or drops them (see `discard`)
+static PENDING: Mutex<Option<HashMap<String, HashMap<String, Link>>>> = Mutex::new(None);
+
+const LINK_PREFIX: &str = "link:";
+const SENDER_PREFIX: &str = "clicks:";
+
+// A built-in redirect: where it goes, and whose email it is in
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Link {
+    pub url: String,
+    pub trace_id: String,
+    pub sender: String,
+    #[serde(default)]
+    pub clicks: u64,
+}
+
+// Replaces body links longer than `min_length` (tracking redirects, mostly) with links from
+// a self-hosted shortener, so they don't eat the embed's character budget. Only the posted
+// copy changes; the archive keeps the original URLs. A link that can't be shortened is left
+// as it is.
+pub fn apply(config: &Config, email: &Email) -> Email {
+    let mut shortened = email.clone();
+    let Some(ref shortener) = config.shortener else {
+        return shortened;
+    };
+    let min_length = shortener.min_length();
+    let mut count = 0;
+    shortened.body = LINK
+        .replace_all(&email.body, |caps: &regex::Captures| {
+            let url = &caps[0];
+            if url.len() <= min_length {
+                return url.to_string();
+            }
+            let short = match shortener.provider {
+                ShortenerProvider::Builtin => Ok(redirect(config, email, url)),
+                _ => shorten(shortener, url),
+            };
+            match short {
+                Ok(short) => {
+                    count += 1;
+                    short
+                }
+                Err(e) => {
+                    error!("Failed to shorten {}: {}", url, e);
+                    url.to_string()
+                }
+            }
+        })
+        .into_owned();
+    if count > 0 {
+        debug!("Shortened {} link(s)", count);
+    }
+    shortened
+}
+
+// `<public_url>/r/<id>`, the ID derived from the email and the URL so a re-render comes
+// out the same. Every email gets its own IDs, so clicks count towards the right issue.
+fn redirect(config: &Config, email: &Email, url: &str) -> String {
+    let digest = openssl::sha::sha256(format!("{}|{}", email.trace_id, url).as_bytes());
+    let id = crate::crypto::hex(&digest[..6]);
+    let link = Link {
+        url: url.to_string(),
+        trace_id: email.trace_id.clone(),
+        sender: sender_address(&email.from),
+        clicks: 0,
+    };
+    let mut pending = PENDING.lock().unwrap();
+    pending.get_or_insert_with(HashMap::new).entry(email.trace_id.clone()).or_default().insert(id.clone(), link);
+    format!("{}/r/{}", public_url(config), id)
+}
+
+fn public_url(config: &Config) -> &str {
+    config.server.as_ref().and_then(|s| s.public_url.as_deref()).unwrap_or_default().trim_end_matches('/')
+}
+
+// Stores the redirects made for an email about to be posted
+pub fn save(store: &dyn StateStore, email: &Email) -> Result<(), Error> {
+    let links = PENDING.lock().unwrap().as_mut().and_then(|p| p.remove(&email.trace_id)).unwrap_or_default();
+    for (id, link) in links {
+        let key = format!("{}{}", LINK_PREFIX, id);
+        if store.get(&key)?.is_none() {
+            store.put_json(&key, &link)?;
+        }
+    }
+    Ok(())
+}
+
+// Forgets the redirects made for an email that wasn't stored for posting (screened out,
+// failed before its render was kept, or only previewed)
+pub fn discard(email: &Email) {
+    if let Some(pending) = PENDING.lock().unwrap().as_mut() {
+        pending.remove(&email.trace_id);
+    }
+}
+
+// GET /r/<id>: counts the click (on the link, the email's history entry and the sender)
+// and sends the reader on to the original URL
+pub fn follow(store: &dyn StateStore, id: &str) -> server::HttpResponse {
+    let key = format!("{}{}", LINK_PREFIX, id);
+    let mut link = match store.get_json::<Link>(&key) {
+        Ok(Some(link)) => link,
+        Ok(None) => return server::json_response(404, json!({ "error": "not found" })),
+        Err(e) => return server::json_response(500, json!({ "error": e.to_string() })),
+    };
+    link.clicks += 1;
+    let counted = store
+        .put_json(&key, &link)
+        .and_then(|_| history::add_click(store, &link.trace_id))
+        .and_then(|_| {
+            let key = format!("{}{}", SENDER_PREFIX, link.sender);
+            let clicks = store.get_json::<u64>(&key)?.unwrap_or(0);
+            store.put_json(&key, &(clicks + 1))
+        });
+    if let Err(e) = counted {
+        error!("Failed to count a click on {}: {}", id, e);
+    }
+    info!("Click on {} from {}", id, link.sender);
+    match Header::from_bytes("Location", link.url.as_bytes()) {
+        Ok(location) => Response::from_data(Vec::new()).with_status_code(302).with_header(location),
+        Err(_) => server::json_response(500, json!({ "error": "invalid link" })),
+    }
+}
+
+// Clicks on built-in redirects per sender, most clicked first
+pub fn clicks_by_sender(store: &dyn StateStore) -> Result<Vec<(String, u64)>, Error> {
+    let mut senders: Vec<(String, u64)> = store
+        .entries(SENDER_PREFIX)?
+        .into_iter()
+        .filter_map(|(key, raw)| Some((key.strip_prefix(SENDER_PREFIX)?.to_string(), raw.parse().ok()?)))
+        .collect();
+    senders.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(senders)
+}
+
+fn shorten(shortener: &ShortenerConfig, url: &str) -> Result<String, Error> {
+    if let Some((short, used)) = SHORTENED.lock().unwrap().as_mut().and_then(|m| m.get_mut(url)) {
+        *used = USES.fetch_add(1, Ordering::Relaxed);
+        return Ok(short.clone());
+    }
+    let base = shortener.endpoint.trim_end_matches('/');
+    let client = crate::http::client();
+    // Both APIs can hand back an existing short link for a URL they've seen before
+    let short = match shortener.provider {
+        ShortenerProvider::Shlink => {
+            let response: Value = client
+                .post(format!("{}/rest/v3/short-urls", base))
+                .header("X-Api-Key", &shortener.api_key)
+                .json(&json!({ "longUrl": url, "findIfExists": true }))
+                .send()?
+                .error_for_status()?
+                .json()?;
+            response["shortUrl"].as_str().map(str::to_string)
+        }
+        ShortenerProvider::Kutt => {
+            let response: Value = client
+                .post(format!("{}/api/v2/links", base))
+                .header("X-API-KEY", &shortener.api_key)
+                .json(&json!({ "target": url, "reuse": true }))
+                .send()?
+                .error_for_status()?
+                .json()?;
+            response["link"].as_str().map(str::to_string)
+        }
+        ShortenerProvider::Builtin => None,
+    };
+    let short = short.ok_or_else(|| Error::parse("No short URL in the response"))?;
+    let mut shortened = SHORTENED.lock().unwrap();
+    let shortened = shortened.get_or_insert_with(HashMap::new);
+    if shortened.len() >= MAX_SHORTENED
+        && let Some(oldest) = shortened.iter().min_by_key(|(_, (_, used))| *used).map(|(url, _)| url.clone())
+    {
+        shortened.remove(&oldest);
+    }
+    shortened.insert(url.to_string(), (short.clone(), USES.fetch_add(1, Ordering::Relaxed)));
+    Ok(short)
+}
+
+// The short links in `text`: built-in redirects, or those handed out by the shortener
+// this run
+pub fn links_in(config: &Config, text: &str) -> Vec<String> {
+    if config.shortener.as_ref().is_some_and(|s| s.provider == ShortenerProvider::Builtin) {
+        let prefix = regex::escape(&format!("{}/r/", public_url(config)));
+        let Ok(re) = Regex::new(&format!("{}[0-9a-f]+", prefix)) else {
+            return Vec::new();
+        };
+        let mut links: Vec<String> = re.find_iter(text).map(|m| m.as_str().to_string()).collect();
+        links.sort();
+        links.dedup();
+        return links;
+    }
+    let shortened = SHORTENED.lock().unwrap();
+    let mut links: Vec<String> = shortened
+        .iter()
+        .flat_map(|m| m.values())
+        .map(|(short, _)| short)
+        .filter(|short| text.contains(short.as_str()))
+        .cloned()
+        .collect();
+    links.sort();
+    links.dedup();
+    links
+}
+
+// Visits to a short link so far, as the shortener counts them
+pub fn clicks(shortener: &ShortenerConfig, store: &dyn StateStore, short: &str) -> Result<u64, Error> {
+    let code = short.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    let base = shortener.endpoint.trim_end_matches('/');
+    let client = crate::http::client();
+    let clicks = match shortener.provider {
+        ShortenerProvider::Shlink => {
+            let response: Value = client
+                .get(format!("{}/rest/v3/short-urls/{}/visits?itemsPerPage=1", base, code))
+                .header("X-Api-Key", &shortener.api_key)
+                .send()?
+                .error_for_status()?
+                .json()?;
+            response["visits"]["pagination"]["totalItems"].as_u64()
+        }
+        ShortenerProvider::Kutt => {
+            let response: Value = client
+                .get(format!("{}/api/v2/links?search={}&limit=10", base, code))
+                .header("X-API-KEY", &shortener.api_key)
+                .send()?
+                .error_for_status()?
+                .json()?;
+            response["data"]
+                .as_array()
+                .and_then(|links| links.iter().find(|l| l["address"].as_str() == Some(code)))
+                .and_then(|link| link["visit_count"].as_u64())
+        }
+        ShortenerProvider::Builtin => store.get_json::<Link>(&format!("{}{}", LINK_PREFIX, code))?.map(|l| l.clicks),
+    };
+    clicks.ok_or_else(|| Error::parse("No visit count in the response"))
+}
@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Set on SIGTERM or SIGINT. Workers finish the message in hand, expunge what was handled,
+// log out and return; waits between cycles are cut short. A second signal exits at once.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+const STEP: Duration = Duration::from_millis(200);
+
+extern "C" fn handle(_signal: libc::c_int) {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        // Only async-signal-safe calls are allowed here
+        unsafe { libc::_exit(130) };
+    }
+}
+
+pub fn install() {
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+// Sleeps for `duration` unless shutdown is requested first. Returns false if it was.
+pub fn sleep(duration: Duration) -> bool {
+    let end = Instant::now() + duration;
+    while !requested() {
+        let left = end.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        thread::sleep(left.min(STEP));
+    }
+    false
+}
+
+// Blocks until shutdown is requested
+pub fn wait() {
+    while !requested() {
+        thread::sleep(STEP);
+    }
+}
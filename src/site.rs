@@ -0,0 +1,231 @@
+use crate::archive::{self, Archived};
+use crate::config::{ArchiveConfig, Config};
+use crate::error::Error;
+use crate::mail;
+use crate::series::sender_address;
+use crate::state::StateStore;
+use crate::webarchive::{self, ImageSrc};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Issues listed on the front page and in the feed
+const RECENT: usize = 50;
+
+// A static copy of the archive for GitHub Pages, Netlify or any file server:
+//
+//   index.html               recent issues, senders and months
+//   feed.xml                 RSS of the recent issues
+//   senders/<sender>.html    every issue from one sender
+//   months/<YYYY-MM>.html    every issue from one month
+//   issues/<trace id>/       the issue, sanitized like the served archive, with its images
+//
+// After a delivery only the new issue and the listings it appears in are written; `site`
+// rebuilds everything, e.g. after retention pruned the archive.
+pub fn update(config: &Config, store: &dyn StateStore, trace_id: &str) -> Result<(), Error> {
+    let Some((site, dir)) = site_dir(config) else {
+        return Ok(());
+    };
+    let all = archive::all(store)?;
+    let Some(issue) = all.iter().find(|a| a.trace_id == trace_id) else {
+        return Ok(());
+    };
+    write_issue(site, &dir, issue)?;
+    let sender = sender_address(&issue.from);
+    let from_sender: Vec<&Archived> = all.iter().filter(|a| sender_address(&a.from) == sender).collect();
+    write_listing(site, &dir.join("senders"), &slug(&sender), &sender, &from_sender)?;
+    let issue_month = month(issue);
+    let in_month: Vec<&Archived> = all.iter().filter(|a| month(a) == issue_month).collect();
+    write_listing(site, &dir.join("months"), &issue_month, &issue_month, &in_month)?;
+    write_index(site, &dir, &all)
+}
+
+// Writes the whole site from the archive
+pub fn build(config: &Config, store: &dyn StateStore) -> Result<(), Error> {
+    let (site, dir) = site_dir(config).ok_or_else(|| Error::config("archive.site_dir is not set"))?;
+    let all = archive::all(store)?;
+    let mut senders: BTreeMap<String, Vec<&Archived>> = BTreeMap::new();
+    let mut months: BTreeMap<String, Vec<&Archived>> = BTreeMap::new();
+    for issue in &all {
+        write_issue(site, &dir, issue)?;
+        senders.entry(sender_address(&issue.from)).or_default().push(issue);
+        months.entry(month(issue)).or_default().push(issue);
+    }
+    for (sender, issues) in &senders {
+        write_listing(site, &dir.join("senders"), &slug(sender), sender, issues)?;
+    }
+    for (month, issues) in &months {
+        write_listing(site, &dir.join("months"), month, month, issues)?;
+    }
+    write_index(site, &dir, &all)?;
+    println!("Wrote {} issues to {}", all.len(), dir.display());
+    Ok(())
+}
+
+fn site_dir(config: &Config) -> Option<(&ArchiveConfig, PathBuf)> {
+    let site = config.archive.as_ref().filter(|a| a.enabled)?;
+    let dir = PathBuf::from(site.site_dir.as_deref()?);
+    Some((site, dir))
+}
+
+fn write_issue(site: &ArchiveConfig, dir: &Path, issue: &Archived) -> Result<(), Error> {
+    let dir = dir.join("issues").join(&issue.trace_id);
+    fs::create_dir_all(&dir)?;
+    let html = issue
+        .raw
+        .as_deref()
+        .and_then(|raw| openssl::base64::decode_block(raw).ok())
+        .and_then(|raw| mail::html_part(&raw));
+    let body = match html {
+        Some((html, images)) => {
+            for image in &images {
+                fs::write(dir.join(&image.filename), &image.data)?;
+            }
+            // Without a server to proxy through, remote images are linked directly; tracking
+            // pixels are gone either way
+            let filenames: Vec<String> = images.iter().map(|image| image.filename.clone()).collect();
+            webarchive::sanitize(&html, &images, move |src| match src {
+                ImageSrc::Inline(index) => filenames.get(index).cloned(),
+                ImageSrc::Remote(url) => Some(url.to_string()),
+            })
+            .0
+        }
+        None => format!("<pre>{}</pre>", escape(&issue.body)),
+    };
+    let sender = sender_address(&issue.from);
+    let header = format!(
+        "<p>From: <a href=\"../../senders/{}.html\">{}</a><br>Date: <a href=\"../../months/{}.html\">{}</a></p>\n<hr>\n",
+        slug(&sender),
+        escape(&issue.from),
+        month(issue),
+        date(issue).format("%Y-%m-%d"),
+    );
+    let page = layout(site, &issue.subject, "../../", &format!("{}{}", header, body));
+    fs::write(dir.join("index.html"), page)?;
+    Ok(())
+}
+
+fn write_listing(
+    site: &ArchiveConfig,
+    dir: &Path,
+    name: &str,
+    title: &str,
+    issues: &[&Archived],
+) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    let page = layout(site, title, "../", &issue_list(issues, "../"));
+    fs::write(dir.join(format!("{}.html", name)), page)?;
+    Ok(())
+}
+
+fn write_index(site: &ArchiveConfig, dir: &Path, all: &[Archived]) -> Result<(), Error> {
+    let mut recent: Vec<&Archived> = all.iter().collect();
+    recent.sort_by_key(|a| std::cmp::Reverse(date(a)));
+    recent.truncate(RECENT);
+
+    let mut senders: BTreeMap<String, usize> = BTreeMap::new();
+    let mut months: BTreeMap<String, usize> = BTreeMap::new();
+    for issue in all {
+        *senders.entry(sender_address(&issue.from)).or_default() += 1;
+        *months.entry(month(issue)).or_default() += 1;
+    }
+    let mut body = String::from("<h2>Recent issues</h2>\n");
+    body.push_str(&issue_list(&recent, ""));
+    body.push_str("<h2>By sender</h2>\n<ul>\n");
+    for (sender, count) in &senders {
+        body.push_str(&format!("<li><a href=\"senders/{}.html\">{}</a> ({})</li>\n", slug(sender), escape(sender), count));
+    }
+    body.push_str("</ul>\n<h2>By month</h2>\n<ul>\n");
+    for (month, count) in months.iter().rev() {
+        body.push_str(&format!("<li><a href=\"months/{}.html\">{}</a> ({})</li>\n", month, month, count));
+    }
+    body.push_str("</ul>\n");
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("index.html"), layout(site, site.site_title(), "", &body))?;
+    fs::write(dir.join("feed.xml"), feed(site, &recent))?;
+    Ok(())
+}
+
+// Newest first
+fn issue_list(issues: &[&Archived], root: &str) -> String {
+    let mut issues = issues.to_vec();
+    issues.sort_by_key(|a| std::cmp::Reverse(date(a)));
+    let mut list = String::from("<ul>\n");
+    for issue in issues {
+        list.push_str(&format!(
+            "<li>{} <a href=\"{}issues/{}/\">{}</a> — {}</li>\n",
+            date(issue).format("%Y-%m-%d"),
+            root,
+            issue.trace_id,
+            escape(&issue.subject),
+            escape(&issue.from)
+        ));
+    }
+    list.push_str("</ul>\n");
+    list
+}
+
+fn feed(site: &ArchiveConfig, recent: &[&Archived]) -> String {
+    let base = site.site_url.as_deref().unwrap_or_default().trim_end_matches('/');
+    let mut items = String::new();
+    for issue in recent {
+        let link = format!("{}/issues/{}/", base, issue.trace_id);
+        let summary: String = issue.body.chars().take(500).collect();
+        items.push_str(&format!(
+            "<item><title>{}</title><link>{}</link><guid isPermaLink=\"false\">{}</guid><pubDate>{}</pubDate><author>{}</author><description>{}</description></item>\n",
+            escape(&issue.subject),
+            escape(&link),
+            issue.trace_id,
+            date(issue).to_rfc2822(),
+            escape(&issue.from),
+            escape(&summary)
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\"><channel><title>{}</title><link>{}/</link><description>{}</description>\n{}</channel></rss>\n",
+        escape(site.site_title()),
+        escape(base),
+        escape(site.site_title()),
+        items
+    )
+}
+
+fn layout(site: &ArchiveConfig, title: &str, root: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title>\
+         <link rel=\"alternate\" type=\"application/rss+xml\" href=\"{}feed.xml\"></head><body>\n\
+         <nav><a href=\"{}index.html\">{}</a></nav>\n<h1>{}</h1>\n{}</body></html>\n",
+        escape(title),
+        root,
+        root,
+        escape(site.site_title()),
+        escape(title),
+        body
+    )
+}
+
+fn date(issue: &Archived) -> DateTime<Utc> {
+    issue.date.unwrap_or(issue.archived_at)
+}
+
+fn month(issue: &Archived) -> String {
+    date(issue).format("%Y-%m").to_string()
+}
+
+// File name for a sender address
+fn slug(sender: &str) -> String {
+    sender
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '@' { c } else { '-' })
+        .collect()
+}
+
+// For both HTML text and XML, including attribute values
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
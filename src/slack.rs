@@ -0,0 +1,145 @@
+use crate::discord::{self, Posted, WebhookError};
+use crate::error::Error;
+use regex::Regex;
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+// Block Kit limits: https://api.slack.com/reference/block-kit/blocks
+const MAX_BLOCKS: usize = 50;
+const MAX_HEADER: usize = 150;
+const MAX_TEXT: usize = 3000;
+const MAX_FIELDS: usize = 10;
+
+static BOLD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\*\*(.+?)\*\*").unwrap());
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[([^\]]+)\]\((https?://[^)\s]+)\)").unwrap());
+
+// Posts to a Slack incoming webhook, with the same retries as a Discord webhook. Slack
+// answers with a bare "ok", so there is no message to point back to.
+pub fn send(webhook_url: &str, payload: &Value) -> Result<Posted, Error> {
+    let target = format!("slack:{}", webhook_url);
+    discord::send_with("slack.send", &target, payload, |payload| post(webhook_url, payload), |_, _| None)
+}
+
+fn post(webhook_url: &str, payload: &Value) -> Result<Posted, WebhookError> {
+    let response = crate::http::client()
+        .post(webhook_url)
+        .json(&message(payload))
+        .send()
+        .map_err(|e| WebhookError {
+            status: None,
+            retry_after: None,
+            message: e.to_string(),
+        })?;
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_secs);
+        // Slack's reason is the whole body, e.g. `invalid_blocks` or `channel_is_archived`
+        let reason = response.text().unwrap_or_default();
+        return Err(WebhookError {
+            status: Some(status.as_u16()),
+            retry_after,
+            message: if reason.is_empty() { status.to_string() } else { reason },
+        });
+    }
+    Ok(Posted {
+        id: String::new(),
+        channel_id: String::new(),
+    })
+}
+
+// The Discord payload as Block Kit: per embed, the title as a header (a link when the embed
+// has a URL), the author, the description, fields, image and footer. Uploaded files and
+// forum thread names have no equivalent and are left out.
+pub fn message(payload: &Value) -> Value {
+    let mut blocks = Vec::new();
+    let mut fallback = Vec::new();
+    if let Some(content) = payload["content"].as_str().filter(|c| !c.is_empty()) {
+        blocks.push(section(&mrkdwn(content)));
+        fallback.push(content.to_string());
+    }
+    for (i, embed) in payload["embeds"].as_array().into_iter().flatten().enumerate() {
+        if i > 0 || !blocks.is_empty() {
+            blocks.push(json!({ "type": "divider" }));
+        }
+        if let Some(title) = embed["title"].as_str() {
+            match embed["url"].as_str() {
+                Some(url) => blocks.push(section(&format!("*<{}|{}>*", url, escape(title)))),
+                None => blocks.push(json!({
+                    "type": "header",
+                    "text": { "type": "plain_text", "text": truncate(title, MAX_HEADER), "emoji": true }
+                })),
+            }
+            fallback.push(title.to_string());
+        }
+        if let Some(author) = embed["author"]["name"].as_str() {
+            blocks.push(context(&escape(author)));
+        }
+        if let Some(description) = embed["description"].as_str().filter(|d| !d.is_empty()) {
+            blocks.push(section(&mrkdwn(description)));
+        }
+        let fields: Vec<Value> = embed["fields"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .take(MAX_FIELDS)
+            .map(|field| {
+                let text = format!(
+                    "*{}*\n{}",
+                    escape(field["name"].as_str().unwrap_or_default()),
+                    mrkdwn(field["value"].as_str().unwrap_or_default())
+                );
+                json!({ "type": "mrkdwn", "text": truncate(&text, 2000) })
+            })
+            .collect();
+        if !fields.is_empty() {
+            blocks.push(json!({ "type": "section", "fields": fields }));
+        }
+        // Uploaded images (`attachment://`) only exist on Discord
+        if let Some(url) = embed["image"]["url"].as_str().filter(|u| u.starts_with("http")) {
+            blocks.push(json!({
+                "type": "image",
+                "image_url": url,
+                "alt_text": embed["title"].as_str().unwrap_or("image")
+            }));
+        }
+        if let Some(footer) = embed["footer"]["text"].as_str() {
+            blocks.push(context(&escape(footer)));
+        }
+    }
+    blocks.truncate(MAX_BLOCKS);
+    json!({ "text": fallback.join(" · "), "blocks": blocks })
+}
+
+fn section(text: &str) -> Value {
+    json!({ "type": "section", "text": { "type": "mrkdwn", "text": truncate(text, MAX_TEXT) } })
+}
+
+fn context(text: &str) -> Value {
+    json!({ "type": "context", "elements": [{ "type": "mrkdwn", "text": truncate(text, MAX_TEXT) }] })
+}
+
+// Slack wants &, < and > escaped in message text
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Discord markdown to Slack mrkdwn: `**bold**` and `[text](url)` are written differently
+fn mrkdwn(text: &str) -> String {
+    let text = escape(text);
+    let text = BOLD.replace_all(&text, "*$1*");
+    LINK.replace_all(&text, "<$2|$1>").into_owned()
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max - 1).collect();
+    format!("{}…", kept)
+}
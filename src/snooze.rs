@@ -0,0 +1,105 @@
+use crate::{cluster, webhooks};
+use crate::config::{Config, parse_duration};
+use crate::error::Error;
+use crate::mail::Email;
+use crate::state::StateStore;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+#[derive(Serialize, Deserialize)]
+pub struct Snooze {
+    pub until: DateTime<Utc>,
+    pub summary: bool,
+    #[serde(default)]
+    pub held: Vec<HeldMessage>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HeldMessage {
+    pub subject: String,
+    pub from: String,
+}
+
+const PREFIX: &str = "snooze:";
+
+pub fn snooze(
+    config: &Config,
+    store: &dyn StateStore,
+    route: &str,
+    duration: &str,
+    summary: bool,
+) -> Result<(), Error> {
+    if !config.routes.iter().flatten().any(|r| r.name == route) {
+        return Err(Error::Config(format!("Unknown route: {}", route)));
+    }
+    let until = Utc::now() + parse_duration(duration).map_err(Error::Config)?;
+
+    let key = format!("{}{}", PREFIX, route);
+    let held = store.get_json::<Snooze>(&key)?.map(|s| s.held).unwrap_or_default();
+    store.put_json(&key, &Snooze { until, summary, held })?;
+
+    println!("Snoozed {} until {}", route, until.to_rfc3339());
+    Ok(())
+}
+
+pub fn unsnooze(store: &dyn StateStore, route: &str) -> Result<(), Error> {
+    let key = format!("{}{}", PREFIX, route);
+    let Some(mut snooze) = store.get_json::<Snooze>(&key)? else {
+        return Err(Error::Config(format!("{} is not snoozed", route)));
+    };
+    // Let the monitor post the summary on its next cycle
+    snooze.until = Utc::now();
+    store.put_json(&key, &snooze)?;
+    println!("Unsnoozed {}", route);
+    Ok(())
+}
+
+// Returns true (and remembers the message for the summary) if the route is currently snoozed.
+pub fn hold(store: &dyn StateStore, route: &str, email: &Email) -> Result<bool, Error> {
+    let key = format!("{}{}", PREFIX, route);
+    let Some(mut snooze) = store.get_json::<Snooze>(&key)? else {
+        return Ok(false);
+    };
+    if snooze.until <= Utc::now() {
+        return Ok(false);
+    }
+    snooze.held.push(HeldMessage {
+        subject: email.subject.clone(),
+        from: email.from.clone(),
+    });
+    store.put_json(&key, &snooze)?;
+    Ok(true)
+}
+
+// Drops expired snoozes, posting a summary of what was held back where requested.
+pub fn flush_expired(config: &Config, store: &dyn StateStore) -> Result<(), Error> {
+    let now = Utc::now();
+    for key in store.keys(PREFIX)? {
+        let Some(snooze) = store.get_json::<Snooze>(&key)? else {
+            continue;
+        };
+        if snooze.until > now {
+            continue;
+        }
+        let route = &key[PREFIX.len()..];
+        info!("Snooze for {} expired ({} messages held)", route, snooze.held.len());
+        if snooze.summary && !snooze.held.is_empty() {
+            let emails: Vec<Email> = snooze
+                .held
+                .iter()
+                .map(|m| Email::new(m.subject.clone(), m.from.clone(), String::new()))
+                .collect();
+            let refs: Vec<&Email> = emails.iter().collect();
+            let title = format!("🔕 While {} was snoozed: {} messages", route, refs.len());
+            let target = config.routes.iter().flatten().find(|r| r.name == route);
+            if let Err(e) = webhooks::send(config, target, &cluster::digest_payload(config, store, &title, &refs), None) {
+                // Keep it around so the summary is retried next cycle
+                error!("Failed to send snooze summary to Discord: {}", e);
+                continue;
+            }
+        }
+        store.delete(&key)?;
+    }
+    Ok(())
+}
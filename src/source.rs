@@ -0,0 +1,116 @@
+use crate::config::{Config, Oversized};
+use crate::error::Error;
+use crate::history::{self, Status};
+use crate::leader::Leader;
+use crate::mail::Email;
+use crate::state::StateStore;
+use crate::{deadletter, events, monitor, otel, pipeline, shutdown, trace};
+use serde_json::{Value, json};
+use std::collections::{BTreeSet, HashMap};
+use tracing::info;
+
+// A mailbox that is read whole on every check and emptied of what was handled: POP3 and
+// JMAP. IMAP isn't one: it keeps its own loop in monitor.rs, as it works on several folders
+// with one budget, waits in IDLE, and in observer mode reads by UID above a watermark
+// without removing anything, or else flags or moves what it handled instead of deleting it.
+// Both loops share the per-message `screen`, `monitor::deliver_batch` for delivery and
+// catch-up digests, and `monitor::housekeeping`.
+pub trait MailSource {
+    // The protocol, for the events
+    fn protocol(&self) -> &'static str;
+    // The messages waiting, oldest first, with their sizes in bytes
+    fn list(&mut self) -> Result<Vec<(u32, u64)>, Error>;
+    // The whole message, or only its headers
+    fn fetch(&mut self, id: u32, headers_only: bool) -> Result<Vec<u8>, Error>;
+    // Marks a message for removal, which takes effect when the session is closed
+    fn remove(&mut self, id: u32) -> Result<(), Error>;
+    fn close(&mut self) -> Result<(), Error>;
+}
+
+// One check: what's waiting (up to limits.max_messages_per_cycle) is fetched, screened and
+// delivered, and the handled messages are removed. Returns whether more is waiting.
+pub fn cycle(
+    config: &Config,
+    store: &dyn StateStore,
+    source: &mut dyn MailSource,
+    leader: Option<&Leader>,
+    catching_up: bool,
+) -> Result<bool, Error> {
+    let protocol = source.protocol();
+    let limits = config.limits.clone().unwrap_or_default();
+    let mut messages = {
+        let _span = otel::span("mail.list");
+        source.list()?
+    };
+    let more_pending = limits.max_messages_per_cycle.is_some_and(|max| messages.len() > max);
+    if let Some(max) = limits.max_messages_per_cycle {
+        messages.truncate(max);
+    }
+    if messages.is_empty() {
+        source.close()?;
+        return Ok(false);
+    }
+    info!("Found {} messages for {}", messages.len(), config.imap_username);
+
+    let mut done = BTreeSet::new();
+    // Trace IDs of the messages fetched, for the events once they are removed
+    let mut traces = HashMap::new();
+    let mut emails = Vec::new();
+    for &(id, size) in &messages {
+        // Stop after the message in hand; the rest stay in the mailbox for next time
+        if shutdown::requested() {
+            break;
+        }
+        // Only the headers of oversized messages are downloaded
+        let oversized = limits.max_message_size.is_some_and(|max| size > max);
+        let raw = {
+            let _span = otel::span("mail.fetch");
+            source.fetch(id, oversized)?
+        };
+        let email = match oversized {
+            true => monitor::headers_only(&raw, u32::try_from(size).unwrap_or(u32::MAX), limits.max_message_size)?,
+            false => Email::parse(&raw)?,
+        };
+        let _trace = trace::enter(&email).uid(id);
+        info!("Fetched message {} from {}", id, email.from);
+        events::emit("fetched", &email, json!({ "source": protocol, "account": config.account, "id": id }));
+        traces.insert(id, email.trace_id.clone());
+
+        if screen(config, store, &email, oversized.then_some(size))? {
+            done.insert(id);
+            continue;
+        }
+        emails.push((id, email));
+    }
+
+    monitor::deliver_batch(config, store, leader, emails, catching_up, &mut done)?;
+
+    for id in &done {
+        source.remove(*id)?;
+    }
+    source.close()?;
+    for trace_id in done.iter().filter_map(|id| traces.get(id)) {
+        events::emit_trace("deleted", trace_id, json!({ "action": "delete" }));
+    }
+    Ok(more_pending)
+}
+
+// Whether a fetched message was dealt with before delivery: screened out, or dead-lettered
+// for being over limits.max_message_size (`oversized` is its size then). Either way it
+// counts as handled, as anything left in the mailbox would be fetched again every cycle.
+pub fn screen(config: &Config, store: &dyn StateStore, email: &Email, oversized: Option<u64>) -> Result<bool, Error> {
+    if pipeline::screen(config, store, email)? {
+        return Ok(true);
+    }
+    let limits = config.limits.clone().unwrap_or_default();
+    if let Some(size) = oversized
+        && limits.oversized == Oversized::DeadLetter
+    {
+        let reason = format!("Message is {} bytes, over limits.max_message_size", size);
+        info!("{}; dead-lettered", reason);
+        deadletter::save(store, email, &Value::Null, &reason)?;
+        history::record(store, email, Status::DeadLettered, Some(reason));
+        return Ok(true);
+    }
+    Ok(false)
+}
@@ -0,0 +1,269 @@
+use crate::config::{StateBackend, StateConfig};
+use crate::error::Error;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Persistence shared by every stateful feature. Keys are namespaced by feature
+// (`snooze:<route>`, ...) and values are JSON documents, so a backend only has to be a
+// string key-value store.
+pub trait StateStore: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<String>, Error>;
+    fn put(&self, key: &str, value: &str) -> Result<(), Error>;
+    fn delete(&self, key: &str) -> Result<(), Error>;
+    fn keys(&self, prefix: &str) -> Result<Vec<String>, Error>;
+
+    // All entries under a prefix. Backends that can read them in one go should override this.
+    fn entries(&self, prefix: &str) -> Result<Vec<(String, String)>, Error> {
+        let mut entries = Vec::new();
+        for key in self.keys(prefix)? {
+            if let Some(value) = self.get(&key)? {
+                entries.push((key, value));
+            }
+        }
+        Ok(entries)
+    }
+
+    // Takes or renews a named lease for `holder`. Returns false while another holder's
+    // lease is still live. Only backends that can be shared between hosts support this.
+    fn try_lease(&self, _name: &str, _holder: &str, _ttl: Duration) -> Result<bool, Error> {
+        Err(Error::config("leader election requires the sqlite or redis state backend"))
+    }
+}
+
+impl dyn StateStore + '_ {
+    pub fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, Error> {
+        match self.get(key)? {
+            Some(raw) => Ok(Some(serde_json::from_str(&raw).map_err(|e| Error::State(format!("Corrupt state for {}: {}", key, e)))?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put_json<T: Serialize>(&self, key: &str, value: &T) -> Result<(), Error> {
+        self.put(key, &serde_json::to_string(value)?)
+    }
+}
+
+pub fn open(config: Option<&StateConfig>) -> Result<Box<dyn StateStore>, Error> {
+    let config = config.cloned().unwrap_or_default();
+    Ok(match config.backend.unwrap_or_default() {
+        StateBackend::Json => Box::new(JsonFileStore {
+            path: config.path.unwrap_or_else(|| "state.json".to_string()),
+            lock: Mutex::new(()),
+        }),
+        StateBackend::Sqlite => Box::new(SqliteStore::open(config.path.as_deref().unwrap_or("state.db"))?),
+        StateBackend::Redis => {
+            let url = config.url.ok_or_else(|| Error::config("state.url is required for the redis backend"))?;
+            Box::new(RedisStore::open(&url, config.key_prefix.as_deref().unwrap_or("newsletter:"))?)
+        }
+    })
+}
+
+// The whole map is re-read before every change so one-shot CLI commands and the running
+// monitor can share the file.
+pub struct JsonFileStore {
+    path: String,
+    lock: Mutex<()>,
+}
+
+impl JsonFileStore {
+    fn read(&self) -> Result<BTreeMap<String, String>, Error> {
+        if !Path::new(&self.path).exists() {
+            return Ok(BTreeMap::new());
+        }
+        let content = fs::read_to_string(&self.path).map_err(Error::state)?;
+        serde_json::from_str(&content).map_err(|e| Error::State(format!("Failed to parse {}: {}", self.path, e)))
+    }
+
+    fn write(&self, map: &BTreeMap<String, String>) -> Result<(), Error> {
+        // Write-then-rename so a crash never leaves a half-written file behind
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_string_pretty(map)?).map_err(Error::state)?;
+        fs::rename(&tmp, &self.path).map_err(Error::state)?;
+        Ok(())
+    }
+}
+
+impl StateStore for JsonFileStore {
+    fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let _guard = self.lock.lock().unwrap();
+        Ok(self.read()?.remove(key))
+    }
+
+    fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+        let _guard = self.lock.lock().unwrap();
+        let mut map = self.read()?;
+        map.insert(key.to_string(), value.to_string());
+        self.write(&map)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        let _guard = self.lock.lock().unwrap();
+        let mut map = self.read()?;
+        if map.remove(key).is_some() {
+            self.write(&map)?;
+        }
+        Ok(())
+    }
+
+    fn keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let _guard = self.lock.lock().unwrap();
+        Ok(self.read()?.into_keys().filter(|k| k.starts_with(prefix)).collect())
+    }
+
+    fn entries(&self, prefix: &str) -> Result<Vec<(String, String)>, Error> {
+        let _guard = self.lock.lock().unwrap();
+        Ok(self.read()?.into_iter().filter(|(k, _)| k.starts_with(prefix)).collect())
+    }
+}
+
+pub struct SqliteStore {
+    conn: Mutex<rusqlite::Connection>,
+}
+
+impl SqliteStore {
+    fn open(path: &str) -> Result<SqliteStore, Error> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS state (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS leases (name TEXT PRIMARY KEY, holder TEXT NOT NULL, expires_at INTEGER NOT NULL);",
+        )?;
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached("SELECT value FROM state WHERE key = ?1")?;
+        let mut rows = stmt.query([key])?;
+        Ok(match rows.next()? {
+            Some(row) => Some(row.get(0)?),
+            None => None,
+        })
+    }
+
+    fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO state (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            [key, value],
+        )?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM state WHERE key = ?1", [key])?;
+        Ok(())
+    }
+
+    fn keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached("SELECT key FROM state WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key")?;
+        let keys = stmt.query_map([prefix], |row| row.get(0))?.collect::<Result<Vec<String>, _>>()?;
+        Ok(keys)
+    }
+
+    fn entries(&self, prefix: &str) -> Result<Vec<(String, String)>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare_cached("SELECT key, value FROM state WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key")?;
+        let entries = stmt
+            .query_map([prefix], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+        Ok(entries)
+    }
+
+    // The upsert only overwrites a lease that is ours or has expired, so exactly one
+    // contender sees a changed row.
+    fn try_lease(&self, name: &str, holder: &str, ttl: Duration) -> Result<bool, Error> {
+        let conn = self.conn.lock().unwrap();
+        let now = chrono::Utc::now().timestamp_millis();
+        let changed = conn.execute(
+            "INSERT INTO leases (name, holder, expires_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET holder = excluded.holder, expires_at = excluded.expires_at
+             WHERE leases.holder = excluded.holder OR leases.expires_at < ?4",
+            rusqlite::params![name, holder, now + ttl.as_millis() as i64, now],
+        )?;
+        Ok(changed == 1)
+    }
+}
+
+pub struct RedisStore {
+    conn: Mutex<redis::Connection>,
+    prefix: String,
+}
+
+impl RedisStore {
+    fn open(url: &str, prefix: &str) -> Result<RedisStore, Error> {
+        let conn = redis::Client::open(url)?.get_connection()?;
+        Ok(RedisStore {
+            conn: Mutex::new(conn),
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+impl StateStore for RedisStore {
+    fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let mut conn = self.conn.lock().unwrap();
+        Ok(redis::cmd("GET").arg(format!("{}{}", self.prefix, key)).query(&mut *conn)?)
+    }
+
+    fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        redis::cmd("SET")
+            .arg(format!("{}{}", self.prefix, key))
+            .arg(value)
+            .query::<()>(&mut *conn)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        redis::cmd("DEL").arg(format!("{}{}", self.prefix, key)).query::<()>(&mut *conn)?;
+        Ok(())
+    }
+
+    fn keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let pattern = format!("{}{}*", self.prefix, prefix);
+        let mut keys: Vec<String> = redis::cmd("SCAN")
+            .cursor_arg(0)
+            .arg("MATCH")
+            .arg(pattern)
+            .clone()
+            .iter::<String>(&mut *conn)?
+            .filter_map(|k| k.strip_prefix(&self.prefix).map(String::from))
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn try_lease(&self, name: &str, holder: &str, ttl: Duration) -> Result<bool, Error> {
+        // Set if absent, or extend if we already hold it, atomically
+        const SCRIPT: &str = r#"
+            if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then return 1 end
+            if redis.call('GET', KEYS[1]) == ARGV[1] then
+                redis.call('PEXPIRE', KEYS[1], ARGV[2])
+                return 1
+            end
+            return 0
+        "#;
+        let mut conn = self.conn.lock().unwrap();
+        let acquired: i64 = redis::cmd("EVAL")
+            .arg(SCRIPT)
+            .arg(1)
+            .arg(format!("{}lease:{}", self.prefix, name))
+            .arg(holder)
+            .arg(ttl.as_millis() as u64)
+            .query(&mut *conn)?;
+        Ok(acquired == 1)
+    }
+}
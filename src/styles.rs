@@ -0,0 +1,30 @@
+use crate::config::{Config, SenderStyle};
+use crate::domains;
+use crate::mail::Email;
+use serde_json::{Value, json};
+
+// The first of the [[senders]] the email is from
+pub fn find<'a>(config: &'a Config, email: &Email) -> Option<&'a SenderStyle> {
+    config.senders.iter().flatten().find(|style| {
+        let sender = style.sender.as_ref().is_some_and(|s| email.from.to_lowercase().contains(&s.to_lowercase()));
+        let domain = style.domain.as_ref().is_some_and(|d| domains::matches(d, &email.from));
+        sender || domain
+    })
+}
+
+// The icon and footer on a rendered embed; the color is picked before rendering, as it
+// gives way to the route's
+pub fn apply(style: &SenderStyle, email: &Email, payload: &mut Value) {
+    let Some(embed) = payload["embeds"].get_mut(0) else {
+        return;
+    };
+    if let Some(ref icon_url) = style.icon_url {
+        embed["author"]["icon_url"] = json!(icon_url);
+    }
+    if let Some(ref footer) = style.footer {
+        embed["footer"]["text"] = json!(match email.folder {
+            Some(ref folder) => format!("{} · {}", footer, folder),
+            None => footer.clone(),
+        });
+    }
+}
@@ -0,0 +1,210 @@
+use crate::config::Config;
+use crate::crypto::hex_decode;
+use crate::error::Error;
+use crate::mail::Email;
+use crate::server::{HttpResponse, json_response};
+use crate::state::StateStore;
+use openssl::pkey::{Id, PKey};
+use openssl::sign::Verifier;
+use serde_json::{Value, json};
+use tracing::error;
+
+const PREFIX: &str = "subscriptions:";
+const API: &str = "https://discord.com/api/v10";
+
+// Personal keyword alerts. Users run `/subscribe kubernetes` (in a DM with the bot or in the
+// server) and get a DM copy of every forwarded email mentioning one of their keywords.
+// Commands arrive as Discord interactions on POST /discord/interactions, so no gateway
+// connection is needed; they are registered when the HTTP server starts.
+pub fn register(config: &Config) {
+    let (Some(_), Some(token)) = (config.subscriptions.as_ref(), config.discord_bot_token.as_ref()) else {
+        return;
+    };
+    let keyword = json!([{ "type": 3, "name": "keyword", "description": "Word or phrase to look for", "required": true }]);
+    let commands = json!([
+        { "name": "subscribe", "description": "Get a DM when a newsletter mentions a keyword", "options": keyword, "contexts": [0, 1] },
+        { "name": "unsubscribe", "description": "Stop getting DMs for a keyword", "options": keyword, "contexts": [0, 1] },
+        { "name": "subscriptions", "description": "List your keywords", "contexts": [0, 1] },
+    ]);
+    let result = bot_get(token, "/applications/@me").and_then(|app| {
+        let app_id = app["id"].as_str().ok_or_else(|| Error::parse("No application id"))?.to_string();
+        let response = crate::http::client()
+            .put(format!("{}/applications/{}/commands", API, app_id))
+            .header("Authorization", format!("Bot {}", token))
+            .json(&commands)
+            .send()?;
+        if !response.status().is_success() {
+            return Err(Error::Network(format!("Status {}", response.status())));
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        error!("Failed to register subscription commands: {}", e);
+    }
+}
+
+pub fn handle(
+    config: &Config,
+    store: &dyn StateStore,
+    signature: Option<&str>,
+    timestamp: Option<&str>,
+    body: &[u8],
+) -> HttpResponse {
+    let Some(ref subscriptions) = config.subscriptions else {
+        return json_response(404, json!({ "error": "not found" }));
+    };
+    // Discord checks this endpoint with bad signatures and expects them to be refused
+    if !verify(&subscriptions.public_key, signature.unwrap_or_default(), timestamp.unwrap_or_default(), body) {
+        return json_response(401, json!({ "error": "invalid request signature" }));
+    }
+    let Ok(interaction) = serde_json::from_slice::<Value>(body) else {
+        return json_response(400, json!({ "error": "Invalid JSON" }));
+    };
+
+    match interaction["type"].as_u64() {
+        // PING
+        Some(1) => json_response(200, json!({ "type": 1 })),
+        // APPLICATION_COMMAND
+        Some(2) => {
+            // `member.user` in a server, `user` in DMs
+            let user = interaction["member"]["user"]["id"]
+                .as_str()
+                .or(interaction["user"]["id"].as_str())
+                .unwrap_or_default();
+            let name = interaction["data"]["name"].as_str().unwrap_or_default();
+            let keyword = interaction["data"]["options"][0]["value"].as_str().unwrap_or_default();
+            let content = match command(store, subscriptions.max_keywords(), user, name, keyword) {
+                Ok(content) => content,
+                Err(e) => {
+                    error!("Subscription command {} failed: {}", name, e);
+                    "Something went wrong, try again later.".to_string()
+                }
+            };
+            // CHANNEL_MESSAGE_WITH_SOURCE, only visible to the user
+            json_response(200, json!({ "type": 4, "data": { "content": content, "flags": 64 } }))
+        }
+        _ => json_response(400, json!({ "error": "unsupported interaction" })),
+    }
+}
+
+fn command(
+    store: &dyn StateStore,
+    max_keywords: usize,
+    user: &str,
+    name: &str,
+    keyword: &str,
+) -> Result<String, Error> {
+    if user.is_empty() {
+        return Err(Error::parse("Interaction without a user"));
+    }
+    let key = format!("{}{}", PREFIX, user);
+    let mut keywords: Vec<String> = store.get_json(&key)?.unwrap_or_default();
+    let keyword = keyword.trim().to_lowercase();
+
+    let reply = match name {
+        "subscribe" if keyword.is_empty() => return Ok("Give a keyword to subscribe to.".to_string()),
+        "subscribe" if keywords.contains(&keyword) => format!("You're already subscribed to `{}`.", keyword),
+        "subscribe" if keywords.len() >= max_keywords => {
+            format!("You can have up to {} keywords; unsubscribe from one first.", max_keywords)
+        }
+        "subscribe" => {
+            keywords.push(keyword.clone());
+            format!("You'll get a DM when a newsletter mentions `{}`.", keyword)
+        }
+        "unsubscribe" if keywords.contains(&keyword) => {
+            keywords.retain(|k| *k != keyword);
+            format!("Unsubscribed from `{}`.", keyword)
+        }
+        "unsubscribe" => format!("You're not subscribed to `{}`.", keyword),
+        "subscriptions" if keywords.is_empty() => "You have no keywords. Add one with `/subscribe`.".to_string(),
+        "subscriptions" => {
+            let list: Vec<String> = keywords.iter().map(|k| format!("`{}`", k)).collect();
+            return Ok(format!("Your keywords: {}", list.join(", ")));
+        }
+        _ => return Ok(format!("Unknown command {}", name)),
+    };
+    if keywords.is_empty() {
+        store.delete(&key)?;
+    } else {
+        store.put_json(&key, &keywords)?;
+    }
+    Ok(reply)
+}
+
+// https://discord.com/developers/docs/interactions/overview#setting-up-an-endpoint-validating-security-request-headers
+fn verify(public_key: &str, signature: &str, timestamp: &str, body: &[u8]) -> bool {
+    let (Some(key), Some(signature)) = (hex_decode(public_key), hex_decode(signature)) else {
+        return false;
+    };
+    let Ok(key) = PKey::public_key_from_raw_bytes(&key, Id::ED25519) else {
+        return false;
+    };
+    let mut message = timestamp.as_bytes().to_vec();
+    message.extend_from_slice(body);
+    Verifier::new_without_digest(&key)
+        .and_then(|mut verifier| verifier.verify_oneshot(&signature, &message))
+        .unwrap_or(false)
+}
+
+// DMs a copy of a delivered email to every user with a matching keyword
+pub fn notify(config: &Config, store: &dyn StateStore, email: &Email, payload: &Value) {
+    let (Some(_), Some(token)) = (config.subscriptions.as_ref(), config.discord_bot_token.as_ref()) else {
+        return;
+    };
+    let entries = match store.entries(PREFIX) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to read subscriptions: {}", e);
+            return;
+        }
+    };
+    let text = format!("{}\n{}", email.subject, email.body).to_lowercase();
+    // DMs go out without the uploaded images, so drop embed references to them
+    let mut embeds = payload["embeds"].clone();
+    for embed in embeds.as_array_mut().into_iter().flatten() {
+        if let Some(embed) = embed.as_object_mut() {
+            embed.remove("image");
+        }
+    }
+    let message = json!({ "content": "📬 Matches your keywords:", "embeds": embeds });
+    for (key, raw) in entries {
+        let keywords: Vec<String> = serde_json::from_str(&raw).unwrap_or_default();
+        if !keywords.iter().any(|k| text.contains(k.as_str())) {
+            continue;
+        }
+        let user = &key[PREFIX.len()..];
+        if let Err(e) = send_dm(token, user, &message) {
+            error!("Failed to DM subscriber {}: {}", user, e);
+        }
+    }
+}
+
+fn send_dm(token: &str, user: &str, message: &Value) -> Result<(), Error> {
+    let channel = bot_post(token, "/users/@me/channels", &json!({ "recipient_id": user }))?;
+    let channel_id = channel["id"].as_str().ok_or_else(|| Error::parse("No DM channel id"))?;
+    bot_post(token, &format!("/channels/{}/messages", channel_id), message)?;
+    Ok(())
+}
+
+fn bot_get(token: &str, path: &str) -> Result<Value, Error> {
+    let response = crate::http::client()
+        .get(format!("{}{}", API, path))
+        .header("Authorization", format!("Bot {}", token))
+        .send()?;
+    if !response.status().is_success() {
+        return Err(Error::Network(format!("Status {}", response.status())));
+    }
+    Ok(response.json()?)
+}
+
+fn bot_post(token: &str, path: &str, body: &Value) -> Result<Value, Error> {
+    let response = crate::http::client()
+        .post(format!("{}{}", API, path))
+        .header("Authorization", format!("Bot {}", token))
+        .json(body)
+        .send()?;
+    if !response.status().is_success() {
+        return Err(Error::Network(format!("Status {}", response.status())));
+    }
+    Ok(response.json()?)
+}
@@ -0,0 +1,133 @@
+use crate::config::{Config, SummarizeApi, SummarizeConfig};
+use crate::error::Error;
+use crate::mail::Email;
+use crate::{discord, routes, usage};
+use crate::state::StateStore;
+use serde_json::{Value, json};
+use tracing::warn;
+
+// Bodies are cut here before being sent, to keep requests (and their cost) bounded
+const MAX_INPUT_CHARS: usize = 24_000;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+// Replaces the truncated body in a rendered payload with an AI summary. On any failure the
+// payload is left as it is.
+pub fn apply(config: &Config, store: &dyn StateStore, email: &Email, payload: &mut Value) {
+    let Some(summarize) = config.summarize.as_ref().filter(|s| s.enabled) else {
+        return;
+    };
+    if email.body.len() < summarize.min_length() || !usage::within_budget(config, store, summarize) {
+        return;
+    }
+    let _span = crate::otel::span("summarize");
+    match summarize_email(store, summarize, &prompt_for(config, summarize, email), email) {
+        Ok(summary) => {
+            if let Some(embed) = payload["embeds"].get_mut(0) {
+                // Embed descriptions are capped at 4096 chars
+                embed["description"] = Value::String(summary.chars().take(4000).collect());
+            }
+            // The summary replaces the cut-off body, so the whole of it goes along as a file
+            if summarize.attach_full_text() && !discord::attach_text(payload, &filename(email), &email.body) {
+                warn!("The full text of {} doesn't fit in the upload, posting the summary alone", email.trace_id);
+            }
+        }
+        Err(e) => warn!("Summarization failed, posting the truncated body: {}", e),
+    }
+}
+
+// The subject, made safe for a file name
+fn filename(email: &Email) -> String {
+    let name: String = email
+        .subject
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .take(60)
+        .collect::<String>()
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    format!("{}.txt", if name.is_empty() { "email" } else { &name })
+}
+
+fn prompt_for(config: &Config, summarize: &SummarizeConfig, email: &Email) -> String {
+    let template = routes::find(config, email)
+        .and_then(|r| r.summary_prompt.as_deref())
+        .or_else(|| {
+            summarize
+                .senders
+                .iter()
+                .flatten()
+                .find(|s| email.from.contains(&s.sender))
+                .map(|s| s.prompt.as_str())
+        })
+        .unwrap_or(summarize.prompt());
+    template.replace("{subject}", &email.subject).replace("{from}", &email.from)
+}
+
+fn summarize_email(
+    store: &dyn StateStore,
+    summarize: &SummarizeConfig,
+    prompt: &str,
+    email: &Email,
+) -> Result<String, Error> {
+    let body: String = email.body.chars().take(MAX_INPUT_CHARS).collect();
+    let content = format!("Subject: {}\nFrom: {}\n\n{}", email.subject, email.from, body);
+    let endpoint = summarize.endpoint().trim_end_matches('/');
+    let client = crate::http::client();
+    let request = match summarize.api() {
+        SummarizeApi::Openai => client
+            .post(format!("{}/chat/completions", endpoint))
+            .bearer_auth(&summarize.api_key)
+            .json(&json!({
+                "model": summarize.model(),
+                "max_tokens": summarize.max_tokens(),
+                "messages": [
+                    { "role": "system", "content": prompt },
+                    { "role": "user", "content": content },
+                ],
+            })),
+        // The system prompt is a top-level field here rather than a message
+        SummarizeApi::Anthropic => client
+            .post(format!("{}/messages", endpoint))
+            .header("x-api-key", &summarize.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&json!({
+                "model": summarize.model(),
+                "max_tokens": summarize.max_tokens(),
+                "system": prompt,
+                "messages": [{ "role": "user", "content": content }],
+            })),
+    };
+    let response = request.send()?;
+    if !response.status().is_success() {
+        return Err(Error::Network(format!("Status {}", response.status())));
+    }
+    let response: Value = response.json()?;
+    let (prompt_tokens, completion_tokens, summary) = match summarize.api() {
+        SummarizeApi::Openai => (
+            response["usage"]["prompt_tokens"].as_u64().unwrap_or(0),
+            response["usage"]["completion_tokens"].as_u64().unwrap_or(0),
+            response["choices"][0]["message"]["content"].as_str(),
+        ),
+        SummarizeApi::Anthropic => (
+            response["usage"]["input_tokens"].as_u64().unwrap_or(0),
+            response["usage"]["output_tokens"].as_u64().unwrap_or(0),
+            response["content"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|c| c["type"] == "text")
+                .and_then(|c| c["text"].as_str()),
+        ),
+    };
+    let cost = (prompt_tokens as f64 * summarize.prompt_price() + completion_tokens as f64 * summarize.completion_price())
+        / 1_000_000.0;
+    usage::record(store, prompt_tokens, completion_tokens, cost);
+    let summary = summary.unwrap_or_default().trim();
+    if summary.is_empty() {
+        return Err(Error::network("Empty summary"));
+    }
+    Ok(summary.to_string())
+}
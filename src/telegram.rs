@@ -0,0 +1,176 @@
+use crate::discord::{self, Posted, WebhookError};
+use crate::error::Error;
+use regex::{Captures, Regex};
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+// Telegram counts the limit in UTF-16 code units of the text after entity parsing; counting
+// the escaped text is on the safe side
+const MAX_TEXT: usize = 4096;
+
+// Discord's **bold** and [text](url), the markup the renderers produce
+static MARKUP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\*\*(.+?)\*\*|\[([^\]]+)\]\((https?://[^)\s]+)\)").unwrap());
+
+// Posts through the Bot API to a chat, as one or more messages. Each message is retried on
+// its own; when a later part fails for good the email is retried as a whole, so the parts
+// that went through are posted again. Telegram has no threads; `thread_id` is ignored.
+pub fn send(token: &str, chat_id: &str, payload: &Value) -> Result<Posted, Error> {
+    let mut first = None;
+    for text in messages(payload) {
+        let message = json!({
+            "chat_id": chat_id,
+            "text": text,
+            "parse_mode": "MarkdownV2",
+            "disable_web_page_preview": true,
+        });
+        let target = format!("telegram:{}", chat_id);
+        let posted = discord::send_with("telegram.send", &target, &message, |message| post(token, message), |_, _| None)?;
+        first.get_or_insert(posted);
+    }
+    first.ok_or_else(|| Error::parse("Nothing to post to Telegram"))
+}
+
+fn post(token: &str, message: &Value) -> Result<Posted, WebhookError> {
+    let response = crate::http::client()
+        .post(format!("https://api.telegram.org/bot{}/sendMessage", token))
+        .json(message)
+        .send()
+        .map_err(|e| WebhookError {
+            status: None,
+            retry_after: None,
+            message: e.to_string(),
+        })?;
+    let status = response.status();
+    let body: Value = response.json().unwrap_or_default();
+    if !status.is_success() || body["ok"].as_bool() != Some(true) {
+        return Err(WebhookError {
+            status: Some(body["error_code"].as_u64().map_or(status.as_u16(), |c| c as u16)),
+            retry_after: body["parameters"]["retry_after"].as_u64().map(Duration::from_secs),
+            message: body["description"].as_str().map_or_else(|| status.to_string(), str::to_string),
+        });
+    }
+    Ok(Posted {
+        id: body["result"]["message_id"].as_i64().map(|id| id.to_string()).unwrap_or_default(),
+        channel_id: body["result"]["chat"]["id"].as_i64().map(|id| id.to_string()).unwrap_or_default(),
+    })
+}
+
+// The Discord payload as MarkdownV2 text: per embed, the title in bold (a link when the
+// embed has a URL), the author in italics, the description, fields and footer. Split
+// between lines into messages within Telegram's limit.
+pub fn messages(payload: &Value) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(content) = payload["content"].as_str().filter(|c| !c.is_empty()) {
+        lines.extend(convert(content));
+    }
+    for embed in payload["embeds"].as_array().into_iter().flatten() {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        if let Some(title) = embed["title"].as_str() {
+            lines.push(match embed["url"].as_str() {
+                Some(url) => format!("*[{}]({})*", escape(title), escape_url(url)),
+                None => format!("*{}*", escape(title)),
+            });
+        }
+        if let Some(author) = embed["author"]["name"].as_str() {
+            lines.push(format!("_{}_", escape(author)));
+        }
+        if let Some(description) = embed["description"].as_str().filter(|d| !d.is_empty()) {
+            lines.push(String::new());
+            lines.extend(convert(description));
+        }
+        for field in embed["fields"].as_array().into_iter().flatten() {
+            lines.push(String::new());
+            lines.push(format!("*{}*", escape(field["name"].as_str().unwrap_or_default())));
+            lines.extend(convert(field["value"].as_str().unwrap_or_default()));
+        }
+        if let Some(footer) = embed["footer"]["text"].as_str() {
+            lines.push(String::new());
+            lines.push(format!("_{}_", escape(footer)));
+        }
+    }
+
+    let mut messages: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && length(&current) + 1 + length(&line) > MAX_TEXT {
+            messages.push(std::mem::take(&mut current));
+        }
+        // A blank line opening a message would be trimmed by Telegram anyway
+        if current.is_empty() && line.is_empty() {
+            continue;
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(&line);
+    }
+    if !current.is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+// Converts text line by line. A line too long for one message is cut into plain-text
+// pieces, so no bold or link is split across messages.
+fn convert(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let converted = markup(line);
+        if length(&converted) <= MAX_TEXT {
+            lines.push(converted);
+            continue;
+        }
+        // Escaping at most doubles the length
+        let chars: Vec<char> = line.chars().collect();
+        for piece in chars.chunks(MAX_TEXT / 2) {
+            lines.push(escape(&piece.iter().collect::<String>()));
+        }
+    }
+    lines
+}
+
+fn markup(line: &str) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for caps in MARKUP.captures_iter(line) {
+        let whole = caps.get(0).unwrap();
+        out.push_str(&escape(&line[last..whole.start()]));
+        out.push_str(&entity(&caps));
+        last = whole.end();
+    }
+    out.push_str(&escape(&line[last..]));
+    out
+}
+
+fn entity(caps: &Captures) -> String {
+    match (caps.get(1), caps.get(2), caps.get(3)) {
+        (Some(bold), _, _) => format!("*{}*", escape(bold.as_str())),
+        (_, Some(text), Some(url)) => format!("[{}]({})", escape(text.as_str()), escape_url(url.as_str())),
+        _ => escape(&caps[0]),
+    }
+}
+
+// Every character MarkdownV2 gives a meaning has to be escaped outside of entities
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// Inside a link's URL only ) and \ are escaped
+fn escape_url(url: &str) -> String {
+    url.replace('\\', "\\\\").replace(')', "\\)")
+}
+
+fn length(text: &str) -> usize {
+    text.encode_utf16().count()
+}
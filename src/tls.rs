@@ -0,0 +1,20 @@
+use crate::error::Error;
+use native_tls::TlsStream;
+use openssl::hash::{MessageDigest, hash};
+use openssl::x509::X509;
+use std::net::TcpStream;
+
+// Pins are the base64 SHA-256 of the certificate's SubjectPublicKeyInfo, optionally written
+// with the HPKP-style `sha256/` prefix. Pinning the key rather than the certificate keeps
+// the pin valid across renewals that reuse the same key.
+pub fn spki_sha256(stream: &TlsStream<TcpStream>) -> Result<String, Error> {
+    let cert = stream.peer_certificate()?.ok_or_else(|| Error::imap("Server presented no certificate"))?;
+    let x509 = X509::from_der(&cert.to_der()?)?;
+    let spki = x509.public_key()?.public_key_to_der()?;
+    let digest = hash(MessageDigest::sha256(), &spki)?;
+    Ok(openssl::base64::encode_block(&digest))
+}
+
+pub fn pin_matches(pins: &[String], actual: &str) -> bool {
+    pins.iter().any(|pin| pin.trim().trim_start_matches("sha256/") == actual)
+}
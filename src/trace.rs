@@ -0,0 +1,62 @@
+use crate::mail::Email;
+use openssl::hash::{MessageDigest, hash};
+use std::cell::RefCell;
+use tracing::field::Empty;
+use tracing::span::EnteredSpan;
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Derived from the Message-ID (or the raw message when it has none), so a message that is
+// retried across cycles or restarts keeps the same ID in logs and history.
+pub fn id_for(message_id: Option<&str>, raw: &[u8]) -> String {
+    let input = message_id.map(str::as_bytes).unwrap_or(raw);
+    match hash(MessageDigest::sha256(), input) {
+        Ok(digest) => digest[..8].iter().map(|b| format!("{:02x}", b)).collect(),
+        Err(_) => "0000000000000000".to_string(),
+    }
+}
+
+// The trace of the message currently being processed on this thread, picked up by code
+// that doesn't have the message at hand (ops alerts).
+pub fn current() -> Option<String> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+// Also enters the message's log span, whose fields go with every log line until the scope
+// ends. The route is filled in once it is known, by `record_route`.
+pub struct Scope {
+    span: EnteredSpan,
+}
+
+pub fn enter(email: &Email) -> Scope {
+    CURRENT.with(|c| *c.borrow_mut() = Some(email.trace_id.clone()));
+    let span = tracing::info_span!(
+        "message",
+        trace_id = %email.trace_id,
+        uid = Empty,
+        from = %email.from,
+        subject = %email.subject,
+        route = Empty
+    );
+    Scope { span: span.entered() }
+}
+
+impl Scope {
+    // The IMAP UID (or sequence number) of the message, when it came from a mailbox
+    pub fn uid(self, uid: u32) -> Scope {
+        self.span.record("uid", uid);
+        self
+    }
+}
+
+pub fn record_route(route: &str) {
+    tracing::Span::current().record("route", route);
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.with(|c| *c.borrow_mut() = None);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::config::{Config, SummarizeConfig};
+use crate::error::Error;
+use crate::ops;
+use crate::state::StateStore;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+const PREFIX: &str = "usage:";
+
+// Tokens spent on AI enrichment per UTC day, with the estimated cost at the configured prices
+#[derive(Serialize, Deserialize, Default)]
+pub struct DailyUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost_usd: f64,
+}
+
+fn key() -> String {
+    format!("{}{}", PREFIX, Utc::now().format("%Y-%m-%d"))
+}
+
+pub fn today(store: &dyn StateStore) -> Result<DailyUsage, Error> {
+    Ok(store.get_json(&key())?.unwrap_or_default())
+}
+
+// False once today's spend has reached either daily budget. AI enrichment is skipped for
+// the rest of the day and messages are posted truncated instead.
+pub fn within_budget(config: &Config, store: &dyn StateStore, summarize: &SummarizeConfig) -> bool {
+    if summarize.daily_budget_usd.is_none() && summarize.daily_token_budget.is_none() {
+        return true;
+    }
+    let usage = match today(store) {
+        Ok(usage) => usage,
+        Err(e) => {
+            error!("Failed to read AI usage: {}", e);
+            return true;
+        }
+    };
+    let over_cost = summarize.daily_budget_usd.is_some_and(|budget| usage.cost_usd >= budget);
+    let over_tokens = summarize
+        .daily_token_budget
+        .is_some_and(|budget| usage.prompt_tokens + usage.completion_tokens >= budget);
+    if over_cost || over_tokens {
+        ops::alert_once(
+            config,
+            &key(),
+            "AI budget exhausted",
+            &format!(
+                "{} tokens (${:.2}) spent today. Summaries are off until midnight UTC; long emails are truncated instead.",
+                usage.prompt_tokens + usage.completion_tokens,
+                usage.cost_usd
+            ),
+        );
+        return false;
+    }
+    true
+}
+
+// `cost` in USD, from the prices of whichever model was used
+pub fn record(store: &dyn StateStore, prompt_tokens: u64, completion_tokens: u64, cost: f64) {
+    crate::otel::add("newsletter.ai.tokens", Some(("type", "prompt")), prompt_tokens);
+    crate::otel::add("newsletter.ai.tokens", Some(("type", "completion")), completion_tokens);
+    crate::otel::add("newsletter.ai.cost_microdollars", None, (cost * 1_000_000.0).round() as u64);
+
+    let result = today(store).and_then(|mut usage| {
+        usage.requests += 1;
+        usage.prompt_tokens += prompt_tokens;
+        usage.completion_tokens += completion_tokens;
+        usage.cost_usd += cost;
+        store.put_json(&key(), &usage)
+    });
+    if let Err(e) = result {
+        error!("Failed to record AI usage: {}", e);
+    }
+}
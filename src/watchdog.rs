@@ -0,0 +1,99 @@
+use crate::config::Config;
+use crate::{ops, otel};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// Supervises one account's monitor worker: when no cycle has completed within `stall_intervals` poll
+// intervals, e.g. on a TLS connection the server stopped answering without closing it, the
+// connection is torn down. A thread can't be killed, so the supervisor shuts the worker's
+// socket instead; the blocked read fails and the worker reconnects as after any other error.
+pub struct Watchdog {
+    limit: Option<Duration>,
+    // A handle on the current IMAP socket, only set while the worker is connected. Also
+    // used to interrupt IDLE on shutdown, so it is kept even without a stall limit.
+    socket: Mutex<Option<TcpStream>>,
+    last_cycle: Mutex<Instant>,
+    // Set while the worker waits in IDLE, where a shutdown may interrupt it
+    idling: AtomicBool,
+}
+
+impl Watchdog {
+    pub fn from_config(config: &Config, interval: Duration) -> Watchdog {
+        let intervals = config.watchdog.clone().unwrap_or_default().stall_intervals();
+        Watchdog {
+            limit: (intervals > 0).then(|| interval * intervals),
+            socket: Mutex::new(None),
+            last_cycle: Mutex::new(Instant::now()),
+            idling: AtomicBool::new(false),
+        }
+    }
+
+    // Called with each new connection, before the TLS handshake, which can hang too
+    pub fn attach(&self, tcp: &TcpStream) {
+        match tcp.try_clone() {
+            Ok(handle) => *self.socket.lock().unwrap() = Some(handle),
+            Err(e) => warn!("Watchdog cannot supervise this connection: {}", e),
+        }
+        self.beat();
+    }
+
+    pub fn detach(&self) {
+        self.socket.lock().unwrap().take();
+    }
+
+    pub fn beat(&self) {
+        *self.last_cycle.lock().unwrap() = Instant::now();
+    }
+
+    // Before blocking in IDLE: a cycle that takes `wait` longer than usual isn't a stall
+    pub fn beat_after(&self, wait: Duration) {
+        *self.last_cycle.lock().unwrap() = Instant::now() + wait;
+    }
+
+    pub fn set_idling(&self, idling: bool) {
+        self.idling.store(idling, Ordering::SeqCst);
+    }
+
+    // On shutdown: ends a wait in IDLE by closing the socket for reading. Writes still go
+    // through, so the worker can send DONE and LOGOUT before it returns.
+    pub fn interrupt_idle(&self) {
+        if self.idling.load(Ordering::SeqCst)
+            && let Some(ref socket) = *self.socket.lock().unwrap()
+        {
+            let _ = socket.shutdown(Shutdown::Read);
+        }
+    }
+
+    pub fn supervise(&self, config: &Config) {
+        let Some(limit) = self.limit else {
+            return;
+        };
+        loop {
+            thread::sleep((limit / 10).max(Duration::from_secs(1)));
+            let stalled = self.last_cycle.lock().unwrap().elapsed();
+            if stalled < limit {
+                continue;
+            }
+            // Nothing to restart while the worker is between connections (backoff, standby)
+            let Some(socket) = self.socket.lock().unwrap().take() else {
+                continue;
+            };
+            let _ = socket.shutdown(Shutdown::Both);
+            otel::count("newsletter.watchdog.restarts", config.account.as_deref().map(|a| ("account", a)));
+            ops::alert(
+                config,
+                "IMAP worker restarted",
+                &format!(
+                    "No monitor cycle completed in {} seconds; dropped the connection to {} ({}) to reconnect.",
+                    stalled.as_secs(),
+                    config.imap_server,
+                    config.imap_username
+                ),
+            );
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::archive::{self, Archived};
+use crate::mail::{self, InlineImage, TRACKING_PIXEL};
+use crate::server::{HttpResponse, json_response, text_response};
+use crate::state::StateStore;
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Response};
+
+// Served on top of the sanitizing, in case a browser parses something differently
+const CSP: &str = "default-src 'none'; img-src 'self'; style-src 'unsafe-inline'";
+
+// GET /archive/<trace id>[/image/<n> | /proxy?url=...]. An archived email is shown as
+// sanitized HTML when the original had an HTML part, otherwise (or with ?format=text) as
+// plain text. Scripts, forms and styles are stripped and tracking pixels dropped; inline
+// images are served from the archived message and remote images through /proxy, so a
+// reader's browser never contacts the sender's servers.
+pub fn handle(store: &dyn StateStore, path: &str, query: &str) -> HttpResponse {
+    let (trace_id, rest) = path.split_once('/').unwrap_or((path, ""));
+    let archived = match archive::get(store, trace_id) {
+        Ok(Some(archived)) => archived,
+        Ok(None) => return json_response(404, json!({ "error": "not found" })),
+        Err(e) => return json_response(500, json!({ "error": e.to_string() })),
+    };
+    let html = archived
+        .raw
+        .as_deref()
+        .and_then(|raw| openssl::base64::decode_block(raw).ok())
+        .and_then(|raw| mail::html_part(&raw));
+    let param = |name: &str| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    match (rest.split_once('/'), html) {
+        (None, Some((ref html, ref images))) if rest.is_empty() && param("format").as_deref() != Some("text") => {
+            html_response(page(&archived, &served(html, images, &archived.trace_id).0))
+        }
+        (None, _) if rest.is_empty() => text_response(200, plain(&archived)),
+        (Some(("image", index)), Some((_, images))) => match index.parse::<usize>().ok().and_then(|i| images.get(i)) {
+            Some(image) => image_response(&image.content_type, image.data.clone()),
+            None => json_response(404, json!({ "error": "not found" })),
+        },
+        (None, Some((ref html, ref images))) if rest == "proxy" => {
+            // Only images the archived email itself shows, so this isn't an open proxy
+            let Some(url) = param("url").filter(|url| served(html, images, &archived.trace_id).1.contains(url)) else {
+                return json_response(404, json!({ "error": "not found" }));
+            };
+            match crate::http::get(&url) {
+                Ok(body) => match image_type(&body) {
+                    Some(content_type) => image_response(content_type, body.to_vec()),
+                    None => json_response(415, json!({ "error": "not an image" })),
+                },
+                Err(e) => json_response(502, json!({ "error": e.to_string() })),
+            }
+        }
+        _ => json_response(404, json!({ "error": "not found" })),
+    }
+}
+
+fn plain(archived: &Archived) -> String {
+    format!(
+        "{}\nFrom: {}\nDate: {}\n\n{}\n",
+        archived.subject,
+        archived.from,
+        archived.date.unwrap_or(archived.archived_at).to_rfc2822(),
+        archived.body
+    )
+}
+
+// Where an image in the original HTML comes from
+pub enum ImageSrc<'a> {
+    // Index into the email's inline images
+    Inline(usize),
+    Remote(&'a str),
+}
+
+// The sanitized HTML, with image sources mapped by `rewrite` (None drops the source), and
+// the remote image URLs it references.
+pub fn sanitize(
+    html: &str,
+    images: &[InlineImage],
+    rewrite: impl Fn(ImageSrc) -> Option<String> + Send + Sync + 'static,
+) -> (String, HashSet<String>) {
+    // The filter has to be 'static
+    let remote = Arc::new(Mutex::new(HashSet::new()));
+    let seen = remote.clone();
+    let cids: Vec<String> = images.iter().map(|image| image.content_id.clone()).collect();
+    let without_pixels = TRACKING_PIXEL.replace_all(html, "");
+    let clean = ammonia::Builder::default()
+        .add_url_schemes(&["cid"])
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .attribute_filter(move |element, attribute, value| {
+            if element != "img" || attribute != "src" {
+                return Some(value.into());
+            }
+            if let Some(cid) = value.strip_prefix("cid:") {
+                let index = cids.iter().position(|c| c == cid)?;
+                return rewrite(ImageSrc::Inline(index)).map(Into::into);
+            }
+            if !value.starts_with("http://") && !value.starts_with("https://") {
+                return None;
+            }
+            seen.lock().unwrap().insert(value.to_string());
+            rewrite(ImageSrc::Remote(value)).map(Into::into)
+        })
+        .clean(&without_pixels)
+        .to_string();
+    let remote = remote.lock().unwrap().clone();
+    (clean, remote)
+}
+
+// Images as served by this module: inline ones from the archive, remote ones via the proxy
+fn served(html: &str, images: &[InlineImage], trace_id: &str) -> (String, HashSet<String>) {
+    let trace_id = trace_id.to_string();
+    sanitize(html, images, move |src| {
+        Some(match src {
+            ImageSrc::Inline(index) => format!("/archive/{}/image/{}", trace_id, index),
+            ImageSrc::Remote(url) => {
+                let url: String = form_urlencoded::byte_serialize(url.as_bytes()).collect();
+                format!("/archive/{}/proxy?url={}", trace_id, url)
+            }
+        })
+    })
+}
+
+fn page(archived: &Archived, body: &str) -> String {
+    let escape = |s: &str| ammonia::clean_text(s);
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title></head><body>\n\
+         <header><h1>{}</h1><p>From: {}<br>Date: {}</p></header>\n<hr>\n{}\n</body></html>\n",
+        escape(&archived.subject),
+        escape(&archived.subject),
+        escape(&archived.from),
+        archived.date.unwrap_or(archived.archived_at).to_rfc2822(),
+        body
+    )
+}
+
+// Proxied responses are only passed on when they are recognizably an image
+fn image_type(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+fn html_response(body: String) -> HttpResponse {
+    Response::from_data(body.into_bytes())
+        .with_header(Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap())
+        .with_header(Header::from_bytes("Content-Security-Policy", CSP).unwrap())
+        .with_header(Header::from_bytes("Referrer-Policy", "no-referrer").unwrap())
+}
+
+fn image_response(content_type: &str, data: Vec<u8>) -> HttpResponse {
+    // Inline images are only ever served with an image type, never as whatever the email claimed
+    let content_type = if content_type.starts_with("image/") && !content_type.contains("svg") {
+        content_type
+    } else {
+        "application/octet-stream"
+    };
+    // A type with bytes a header can't carry falls back too, rather than panicking the thread
+    let content_type = Header::from_bytes("Content-Type", content_type)
+        .unwrap_or_else(|_| Header::from_bytes("Content-Type", "application/octet-stream").unwrap());
+    Response::from_data(data)
+        .with_header(content_type)
+        .with_header(Header::from_bytes("Content-Security-Policy", CSP).unwrap())
+        .with_header(Header::from_bytes("X-Content-Type-Options", "nosniff").unwrap())
+}
@@ -0,0 +1,276 @@
+use crate::config::{Config, NotifierKind, Route, WebhookStrategy, parse_duration};
+use crate::discord::{self, Failure, Posted};
+use crate::error::Error;
+use crate::notify::{self, DiscordBot, DiscourseCategory, MatrixRoom, Notifier, TelegramBot, ZulipStream};
+use crate::ops;
+use crate::state::StateStore;
+use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+// Once on a backup, the primary is given another chance this often
+const RETRY_PRIMARY_AFTER: Duration = Duration::from_secs(15 * 60);
+
+// Deliveries to a dead webhook (401/403/404) are paused for this long, then tried again
+const PAUSE_MINUTES: i64 = 30;
+const PAUSE_PREFIX: &str = "paused:";
+
+const PACED_PREFIX: &str = "paced:";
+
+// Pause key for emails that match no route
+pub const DEFAULT_TARGET: &str = "default";
+
+// Per-route delivery state, kept in memory: a restart starts over on the primary.
+static ROUTES: Mutex<Option<HashMap<String, Targets>>> = Mutex::new(None);
+
+#[derive(Default)]
+struct Targets {
+    // Index of the webhook currently in use (failover) or next in line (round robin)
+    active: usize,
+    failures: u32,
+    switched_at: Option<Instant>,
+}
+
+// Posts as the bot when the route has a channel_id, to the route's own webhooks when it has
+// any, otherwise to discord_webhook_url; webhooks are Discord's or Slack's per `notifier`,
+// and `notifier = "telegram"` posts to the route's chat_id or telegram.chat_id instead
+// (likewise the route's Zulip stream, Discourse category or Matrix room, or the section's
+// default).
+// Embed timestamps are given in the route's timezone.
+pub fn send(
+    config: &Config,
+    route: Option<&Route>,
+    payload: &Value,
+    thread_id: Option<&str>,
+) -> Result<Posted, Error> {
+    let tz = config.timezone(route);
+    let mut payload = Cow::Borrowed(payload);
+    if tz != chrono_tz::Tz::UTC {
+        discord::localize_timestamps(payload.to_mut(), tz);
+    }
+    let payload = payload.as_ref();
+    if let Some(channel_id) = route.and_then(|r| r.channel_id.as_deref()) {
+        let token = config.discord_bot_token.as_deref().ok_or_else(|| {
+            Error::Config(format!("Route {} has a channel_id but discord_bot_token is not set", route.unwrap().name))
+        })?;
+        return DiscordBot { token, channel_id }.send(payload, thread_id);
+    }
+    let kind = config.notifier(route);
+    if kind == NotifierKind::Telegram
+        && let Some(ref telegram) = config.telegram
+    {
+        let chat_id = route.and_then(|r| r.chat_id.as_deref()).unwrap_or(&telegram.chat_id);
+        return TelegramBot { token: &telegram.bot_token, chat_id }.send(payload, thread_id);
+    }
+    if kind == NotifierKind::Zulip
+        && let Some(ref zulip) = config.zulip
+    {
+        return ZulipStream {
+            zulip,
+            stream: route.and_then(|r| r.zulip_stream.as_deref()).unwrap_or(&zulip.stream),
+            topic: route.and_then(|r| r.zulip_topic.as_deref()).or(zulip.topic.as_deref()).unwrap_or("{sender}"),
+            route: route.map_or(DEFAULT_TARGET, |r| r.name.as_str()),
+        }
+        .send(payload, thread_id);
+    }
+    if kind == NotifierKind::Discourse
+        && let Some(ref discourse) = config.discourse
+    {
+        let category = route.and_then(|r| r.discourse_category).unwrap_or(discourse.category);
+        return DiscourseCategory { discourse, category }.send(payload, thread_id);
+    }
+    if kind == NotifierKind::Matrix
+        && let Some(ref matrix) = config.matrix
+    {
+        let room_id = route.and_then(|r| r.matrix_room.as_deref()).unwrap_or(&matrix.room_id);
+        return MatrixRoom { matrix, room_id }.send(payload, thread_id);
+    }
+    let Some((route, urls)) = route.and_then(|r| r.webhooks.as_ref().filter(|w| !w.is_empty()).map(|w| (r, w))) else {
+        return notify::webhook(kind, &config.discord_webhook_url).send(payload, thread_id);
+    };
+    match route.strategy.unwrap_or_default() {
+        WebhookStrategy::Failover => failover(config, route, kind, urls, payload, thread_id),
+        WebhookStrategy::RoundRobin => round_robin(route, kind, urls, payload, thread_id),
+    }
+}
+
+// The end of a pause on a route's deliveries, if one is in effect
+pub fn paused_until(store: &dyn StateStore, target: &str) -> Result<Option<DateTime<Utc>>, Error> {
+    let until: Option<DateTime<Utc>> = store.get_json(&format!("{}{}", PAUSE_PREFIX, target))?;
+    Ok(until.filter(|until| *until > Utc::now()))
+}
+
+// Stops deliveries for a route whose webhook is gone. Its emails stay with the source and
+// are retried after the pause.
+pub fn pause(config: &Config, store: &dyn StateStore, target: &str, error: &dyn std::fmt::Display) {
+    let until = Utc::now() + chrono::Duration::minutes(PAUSE_MINUTES);
+    if let Err(e) = store.put_json(&format!("{}{}", PAUSE_PREFIX, target), &until) {
+        error!("Failed to pause {}: {}", target, e);
+    }
+    ops::alert_once(
+        config,
+        &format!("webhook-dead:{}", target),
+        "Webhook rejected",
+        &format!(
+            "Deliveries for {} were refused ({}); the webhook was probably deleted or its token revoked. \
+             Pausing the route for {} minutes at a time until it works again.",
+            target, error, PAUSE_MINUTES
+        ),
+    );
+}
+
+// When the route may post next under its min_interval, if that's still in the future
+pub fn paced_until(store: &dyn StateStore, route: &Route) -> Result<Option<DateTime<Utc>>, Error> {
+    let Some(ref interval) = route.min_interval else {
+        return Ok(None);
+    };
+    let interval = parse_duration(interval).map_err(|e| Error::Config(format!("Route {} min_interval: {}", route.name, e)))?;
+    let last: Option<DateTime<Utc>> = store.get_json(&format!("{}{}", PACED_PREFIX, route.name))?;
+    let next = last.map(|last| last + interval);
+    Ok(next.filter(|next| *next > Utc::now()))
+}
+
+// When the route's quiet hours end, if they are in effect now
+pub fn quiet_until(config: &Config, route: &Route) -> Result<Option<DateTime<Utc>>, Error> {
+    let Some(ref hours) = route.quiet_hours else {
+        return Ok(None);
+    };
+    let invalid = || Error::Config(format!("Route {} quiet_hours: expected \"HH:MM-HH:MM\", got {:?}", route.name, hours));
+    let (start, end) = hours.split_once('-').ok_or_else(invalid)?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| invalid())?;
+    let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| invalid())?;
+
+    let tz = config.timezone(Some(route));
+    let now = Utc::now().with_timezone(&tz);
+    let time = now.time();
+    // A range like 22:00-07:00 runs over midnight
+    let quiet = if start <= end { time >= start && time < end } else { time >= start || time < end };
+    if !quiet {
+        return Ok(None);
+    }
+    let mut day = now.date_naive();
+    if time >= end {
+        day = day.succ_opt().ok_or_else(|| Error::config("quiet_hours end is out of range"))?;
+    }
+    // When the end falls in a DST gap, the hour after is close enough
+    let local = day.and_time(end);
+    let until = tz
+        .from_local_datetime(&local)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(local + chrono::Duration::hours(1))).earliest())
+        .ok_or_else(invalid)?;
+    Ok(Some(until.with_timezone(&Utc)))
+}
+
+pub fn record_post(store: &dyn StateStore, route: &Route) {
+    if route.min_interval.is_none() {
+        return;
+    }
+    if let Err(e) = store.put_json(&format!("{}{}", PACED_PREFIX, route.name), &Utc::now()) {
+        error!("Failed to record post time for {}: {}", route.name, e);
+    }
+}
+
+fn with_targets<T>(route: &str, f: impl FnOnce(&mut Targets) -> T) -> T {
+    let mut routes = ROUTES.lock().unwrap();
+    f(routes.get_or_insert_with(HashMap::new).entry(route.to_string()).or_default())
+}
+
+fn failover(
+    config: &Config,
+    route: &Route,
+    kind: NotifierKind,
+    urls: &[String],
+    payload: &Value,
+    thread_id: Option<&str>,
+) -> Result<Posted, Error> {
+    let threshold = route.failover_after.unwrap_or(3).max(1);
+    let active = with_targets(&route.name, |t| {
+        if t.active > 0 && t.switched_at.is_some_and(|at| at.elapsed() >= RETRY_PRIMARY_AFTER) {
+            t.active = 0;
+            t.failures = 0;
+            t.switched_at = None;
+        }
+        t.active.min(urls.len() - 1)
+    });
+
+    let mut index = active;
+    loop {
+        match notify::webhook(kind, &urls[index]).send(payload, thread_id) {
+            Ok(posted) => {
+                with_targets(&route.name, |t| {
+                    if t.active == index {
+                        t.failures = 0;
+                    }
+                });
+                return Ok(posted);
+            }
+            // The payload is at fault, not the webhook
+            Err(e) if matches!(e.delivery_failure(), Some(Failure::BadPayload | Failure::TooLarge)) => {
+                return Err(e);
+            }
+            Err(e) => {
+                let switch = with_targets(&route.name, |t| {
+                    if t.active != index {
+                        return false;
+                    }
+                    t.failures += 1;
+                    if t.failures < threshold || index + 1 >= urls.len() {
+                        return false;
+                    }
+                    t.active = index + 1;
+                    t.failures = 0;
+                    t.switched_at = Some(Instant::now());
+                    true
+                });
+                if !switch {
+                    return Err(e);
+                }
+                ops::alert(
+                    config,
+                    "Webhook failover",
+                    &format!(
+                        "Route {}: webhook #{} failed {} times in a row ({}). Switching to webhook #{}.",
+                        route.name,
+                        index + 1,
+                        threshold,
+                        e,
+                        index + 2
+                    ),
+                );
+                index += 1;
+            }
+        }
+    }
+}
+
+// Spreads posts over all webhooks; a failing one is skipped for that post.
+fn round_robin(
+    route: &Route,
+    kind: NotifierKind,
+    urls: &[String],
+    payload: &Value,
+    thread_id: Option<&str>,
+) -> Result<Posted, Error> {
+    let start = with_targets(&route.name, |t| {
+        let start = t.active % urls.len();
+        t.active = (start + 1) % urls.len();
+        start
+    });
+    let mut last_error = None;
+    for offset in 0..urls.len() {
+        let index = (start + offset) % urls.len();
+        match notify::webhook(kind, &urls[index]).send(payload, thread_id) {
+            Ok(posted) => return Ok(posted),
+            Err(e) => {
+                warn!("Route {}: webhook #{} failed: {}", route.name, index + 1, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap())
+}
@@ -0,0 +1,180 @@
+use crate::archive;
+use crate::config::{Config, XmppConfig};
+use crate::error::Error;
+use crate::mail::Email;
+use native_tls::TlsConnector;
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use tracing::error;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+// Characters of the posted text to include; chat clients don't fold long messages
+const MAX_SUMMARY: usize = 1000;
+
+// Sends each delivered email to an XMPP contact (`to`) or group chat (`room`): subject,
+// sender, the posted text (the AI summary when there is one) and the archive link. A
+// connection is made per message: STARTTLS, SASL PLAIN, bind, join the room, send, close.
+// Failures are logged and don't hold up the delivery.
+pub fn announce(config: &Config, email: &Email, payload: &Value) {
+    let Some(ref xmpp) = config.xmpp else {
+        return;
+    };
+    let text = message(email, payload, archive::url(config, &email.trace_id).as_deref());
+    if let Err(e) = send(xmpp, &text) {
+        error!("Failed to send to XMPP ({}): {}", xmpp.room.as_deref().or(xmpp.to.as_deref()).unwrap_or_default(), e);
+    }
+}
+
+fn message(email: &Email, payload: &Value, url: Option<&str>) -> String {
+    let posted = payload["embeds"][0]["description"].as_str().or(payload["content"].as_str()).unwrap_or(&email.body);
+    let mut summary: String = posted.trim().chars().take(MAX_SUMMARY).collect();
+    if summary.len() < posted.trim().len() {
+        summary.push('…');
+    }
+    let mut text = format!("📰 {}\nFrom: {}", email.subject, email.from);
+    if !summary.is_empty() {
+        text.push_str(&format!("\n\n{}", summary));
+    }
+    if let Some(url) = url {
+        text.push_str(&format!("\n\n{}", url));
+    }
+    text
+}
+
+fn send(xmpp: &XmppConfig, text: &str) -> Result<(), Error> {
+    let (user, domain) = xmpp.jid.split_once('@').ok_or_else(|| Error::config("xmpp.jid must look like user@domain"))?;
+    let domain = domain.split('/').next().unwrap_or(domain);
+    // Without SRV lookups, the server is the JID's domain unless given
+    let server = xmpp.server.clone().unwrap_or_else(|| format!("{}:5222", domain));
+    let address = server
+        .to_socket_addrs()
+        .map_err(Error::network)?
+        .next()
+        .ok_or_else(|| Error::Network(format!("{} did not resolve", server)))?;
+    let tcp = TcpStream::connect_timeout(&address, TIMEOUT).map_err(Error::network)?;
+    tcp.set_read_timeout(Some(TIMEOUT)).map_err(Error::network)?;
+    tcp.set_write_timeout(Some(TIMEOUT)).map_err(Error::network)?;
+
+    // STARTTLS is mandatory: a password never goes out in the clear
+    let mut plain = Stream { inner: &tcp, buffer: String::new() };
+    plain.open(domain)?;
+    if !plain.read_until(&["</stream:features>"])?.contains("urn:ietf:params:xml:ns:xmpp-tls") {
+        return Err(Error::network("The XMPP server does not offer STARTTLS"));
+    }
+    plain.write("<starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>")?;
+    if !plain.read_until(&["<proceed", "<failure"])?.contains("<proceed") {
+        return Err(Error::network("The XMPP server refused STARTTLS"));
+    }
+    let connector = TlsConnector::new().map_err(Error::network)?;
+    let tls = connector.connect(domain, tcp).map_err(Error::network)?;
+    let mut stream = Stream { inner: tls, buffer: String::new() };
+
+    stream.open(domain)?;
+    stream.read_until(&["</stream:features>"])?;
+    let token = format!("\0{}\0{}", user, xmpp.password);
+    stream.write(&format!(
+        "<auth xmlns='urn:ietf:params:xml:ns:xmpp-sasl' mechanism='PLAIN'>{}</auth>",
+        openssl::base64::encode_block(token.as_bytes())
+    ))?;
+    if !stream.read_until(&["<success", "</failure>", "<failure/>"])?.contains("<success") {
+        return Err(Error::network("XMPP authentication failed"));
+    }
+
+    stream.open(domain)?;
+    let features = stream.read_until(&["</stream:features>"])?;
+    stream.write(
+        "<iq type='set' id='bind'><bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'><resource>newsletter</resource></bind></iq>",
+    )?;
+    expect_result(&stream.read_until(&["</iq>", "/>"])?, "bind")?;
+    // Only servers from before RFC 6121 still want a session
+    if features.contains("urn:ietf:params:xml:ns:xmpp-session") && !features.contains("<optional/>") {
+        stream.write("<iq type='set' id='session'><session xmlns='urn:ietf:params:xml:ns:xmpp-session'/></iq>")?;
+        expect_result(&stream.read_until(&["</iq>", "/>"])?, "session")?;
+    }
+
+    let body = escape(text);
+    match (xmpp.room.as_deref(), xmpp.to.as_deref()) {
+        (Some(room), _) => {
+            let nick = escape(xmpp.nick.as_deref().unwrap_or("newsletter"));
+            let room = escape(room);
+            // No history: the room's backlog would only be read past
+            stream.write(&format!(
+                "<presence to='{}/{}'><x xmlns='http://jabber.org/protocol/muc'><history maxstanzas='0'/></x></presence>",
+                room, nick
+            ))?;
+            // The room sends the occupants' presence, ours last (status 110), or an error
+            let joined = stream.read_until(&["code='110'", "code=\"110\"", "type='error'", "type=\"error\""])?;
+            if joined.ends_with("type='error'") || joined.ends_with("type=\"error\"") {
+                return Err(Error::Network(format!("Could not join {}", room)));
+            }
+            stream.write(&format!("<message to='{}' type='groupchat'><body>{}</body></message>", room, body))?;
+        }
+        (None, Some(to)) => {
+            stream.write(&format!("<message to='{}' type='chat'><body>{}</body></message>", escape(to), body))?;
+        }
+        (None, None) => return Err(Error::config("[xmpp] needs a room or a to")),
+    }
+    stream.write("</stream:stream>")
+}
+
+fn expect_result(reply: &str, id: &str) -> Result<(), Error> {
+    if reply.contains("type='result'") || reply.contains("type=\"result\"") {
+        Ok(())
+    } else {
+        Err(Error::Network(format!("XMPP {} failed", id)))
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+        .replace('"', "&quot;")
+}
+
+// Just enough XML for a handful of known replies: input is gathered until one of the
+// expected markers shows up, without a parser
+struct Stream<S: Read + Write> {
+    inner: S,
+    buffer: String,
+}
+
+impl<S: Read + Write> Stream<S> {
+    fn open(&mut self, domain: &str) -> Result<(), Error> {
+        self.write(&format!(
+            "<?xml version='1.0'?><stream:stream to='{}' version='1.0' xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>",
+            escape(domain)
+        ))
+    }
+
+    fn write(&mut self, data: &str) -> Result<(), Error> {
+        self.inner
+            .write_all(data.as_bytes())
+            .and_then(|_| self.inner.flush())
+            .map_err(Error::network)
+    }
+
+    // Everything received up to and including the first of `markers`
+    fn read_until(&mut self, markers: &[&str]) -> Result<String, Error> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            let found = markers.iter().filter_map(|m| self.buffer.find(m).map(|i| i + m.len())).min();
+            if let Some(end) = found {
+                let rest = self.buffer.split_off(end);
+                return Ok(std::mem::replace(&mut self.buffer, rest));
+            }
+            if self.buffer.contains("</stream:stream>") || self.buffer.contains("<stream:error") {
+                return Err(Error::Network(format!("XMPP server closed the stream: {}", self.buffer)));
+            }
+            let n = self.inner.read(&mut chunk).map_err(Error::network)?;
+            if n == 0 {
+                return Err(Error::network("XMPP server closed the connection"));
+            }
+            self.buffer.push_str(&String::from_utf8_lossy(&chunk[..n]));
+        }
+    }
+}
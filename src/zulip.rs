@@ -0,0 +1,76 @@
+use crate::config::ZulipConfig;
+use crate::discord::{self, Posted, WebhookError};
+use crate::error::Error;
+use crate::notify;
+use serde_json::{Value, json};
+use std::time::Duration;
+
+// Zulip's limits, in bytes for the content and characters for the topic
+const MAX_CONTENT: usize = 10000;
+const MAX_TOPIC: usize = 60;
+
+// Posts the payload as Markdown to a stream, under a topic filled in from the payload:
+// `{subject}` from the embed title, `{sender}` from its author (the display name). Topics
+// keep each newsletter's issues together the way threads would.
+pub fn send(zulip: &ZulipConfig, stream: &str, topic: &str, payload: &Value) -> Result<Posted, Error> {
+    let embed = &payload["embeds"][0];
+    let subject = embed["title"].as_str().unwrap_or("Newsletter");
+    let from = embed["author"]["name"].as_str().unwrap_or_default();
+    let sender = from.split('<').next().map(|s| s.trim().trim_matches('"')).filter(|s| !s.is_empty()).unwrap_or(from);
+    let topic = cut(&topic.replace("{subject}", subject).replace("{sender}", sender), MAX_TOPIC);
+    let mut content = notify::markdown(payload, true);
+    if content.len() > MAX_CONTENT {
+        let mut end = MAX_CONTENT - 3;
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        content.truncate(end);
+        content.push('…');
+    }
+    let message = json!({ "type": "stream", "to": stream, "topic": topic, "content": content });
+    // Zulip's limits are per user
+    let target = format!("zulip:{}@{}", zulip.email, zulip.site);
+    discord::send_with("zulip.send", &target, &message, |message| post(zulip, message), |_, _| None)
+}
+
+fn cut(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    format!("{}…", s.chars().take(max - 1).collect::<String>())
+}
+
+fn post(zulip: &ZulipConfig, message: &Value) -> Result<Posted, WebhookError> {
+    let form: Vec<(&str, &str)> = ["type", "to", "topic", "content"]
+        .into_iter()
+        .map(|key| (key, message[key].as_str().unwrap_or_default()))
+        .collect();
+    let response = crate::http::client()
+        .post(format!("{}/api/v1/messages", zulip.site.trim_end_matches('/')))
+        .basic_auth(&zulip.email, Some(&zulip.api_key))
+        .form(&form)
+        .send()
+        .map_err(|e| WebhookError {
+            status: None,
+            retry_after: None,
+            message: e.to_string(),
+        })?;
+    let status = response.status();
+    let header_wait = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<f64>().ok());
+    let body: Value = response.json().unwrap_or_default();
+    if !status.is_success() || body["result"].as_str() != Some("success") {
+        return Err(WebhookError {
+            status: Some(status.as_u16()),
+            retry_after: body["retry-after"].as_f64().or(header_wait).map(Duration::from_secs_f64),
+            message: body["msg"].as_str().map_or_else(|| status.to_string(), str::to_string),
+        });
+    }
+    Ok(Posted {
+        id: body["id"].as_u64().map(|id| id.to_string()).unwrap_or_default(),
+        channel_id: message["to"].as_str().unwrap_or_default().to_string(),
+    })
+}